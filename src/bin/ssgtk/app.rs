@@ -0,0 +1,1701 @@
+//! This module contains code that defines the entire application,
+//! and holds all its components.
+//!
+//! With the "gui" feature, the application is driven by the GTK main loop,
+//! and also holds the tray and the log viewer.
+//! Without it, the application runs headless: it is driven by a plain loop,
+//! and is controlled via the runtime API (i.e. `ssgtkctl`) and signals.
+
+use std::{
+    fmt, io,
+    path::PathBuf,
+    process,
+    sync::{Arc, Mutex, RwLock},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crossbeam_channel::{unbounded as unbounded_channel, Receiver, Sender};
+#[cfg(feature = "gui")]
+use gtk::prelude::*;
+#[cfg(feature = "runtime-api")]
+use itertools::Itertools;
+use log::{debug, error, info, trace, warn};
+
+#[cfg(any(feature = "gui", feature = "runtime-api"))]
+use shadowsocks_gtk_rs::log_level::LogLevel;
+use shadowsocks_gtk_rs::{
+    notify_method::NotifyMethod,
+    util::{self, mutex_lock},
+};
+#[cfg(feature = "runtime-api")]
+use shadowsocks_gtk_rs::{
+    profile_tree::ProfileTreeNode,
+    runtime_api_msg::{APICommand, ProfileLatency, QueryReply},
+    util::leaky_bucket::NaiveLeakyBucketConfig,
+};
+
+#[cfg(feature = "gui")]
+use crate::gui::{
+    log_viewer::{LogViewerWindow, StatusFn},
+    tray::{IconState, TrayItem},
+};
+#[cfg(feature = "runtime-api")]
+use crate::io::runtime_api::{APIListener, APIListenerError, APIRequest};
+#[cfg(feature = "systemd")]
+use crate::io::sd_notify;
+#[cfg(feature = "gui")]
+use crate::notification::confirm_blocking_prompt;
+use crate::{
+    clap_def::CliArgs,
+    event::AppEvent,
+    io::{
+        app_state::{AppState, AppStateError, ProfileHistory, TrayIconSet},
+        connectivity,
+        kill_switch::KillSwitch,
+        latency_prober::LatencyProber,
+        log_file::{LogFileConfig, RotatingLogFile},
+        network_watcher::NetworkWatcher,
+        pid_registry::PidRegistry,
+        profile_loader::{Profile, ProfileFolder, ProfileLoadError},
+        profile_watcher::ProfileWatcher,
+        resume_detector::ResumeDetector,
+    },
+    notification::{notify, notify_log, Level, ToastTimeout},
+    profile_manager::{DeferredStart, ProfileManager, StartProgress},
+};
+
+/// How long to wait after an important change before saving the app state,
+/// so that changes made in quick succession are saved together.
+const SAVE_REQUEST_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How often to refresh the instance stats shown in the tray.
+#[cfg(feature = "gui")]
+const TRAY_STATS_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub enum AppStartError {
+    ProfileLoadError(ProfileLoadError),
+    CtrlCError(ctrlc::Error),
+    #[cfg(feature = "gui")]
+    GLibBoolError(glib::BoolError),
+    #[cfg(feature = "gui")]
+    GLibError(glib::Error),
+    /// GTK cannot be initialised, most likely because there's no display to connect to.
+    #[cfg(feature = "gui")]
+    NoDisplay(glib::BoolError),
+    #[cfg(feature = "runtime-api")]
+    APIListenerError(APIListenerError),
+    IOError(io::Error),
+}
+
+/// Describe the value of a display environment variable for diagnostics.
+#[cfg(feature = "gui")]
+fn describe_display_var(name: &str) -> String {
+    match std::env::var_os(name) {
+        Some(val) if !val.is_empty() => format!("{}={:?}", name, val),
+        _ => format!("{} is unset", name),
+    }
+}
+
+impl fmt::Display for AppStartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use AppStartError::*;
+        let prefix = "AppStartError";
+        match self {
+            ProfileLoadError(e) => write!(f, "{}-ProfileLoadError: {}", prefix, e),
+            CtrlCError(e) => write!(f, "{}-CtrlCError: {}", prefix, e),
+            #[cfg(feature = "gui")]
+            GLibBoolError(e) => write!(f, "{}-GLibBoolError: {}", prefix, e),
+            #[cfg(feature = "gui")]
+            GLibError(e) => write!(f, "{}-GLibError: {}", prefix, e),
+            #[cfg(feature = "gui")]
+            NoDisplay(e) => write!(
+                f,
+                "{}-NoDisplay: {} ({}, {}). Make sure that ssgtk runs in a graphical session \
+                (e.g. with X forwarding over SSH), or use a headless build (without the \"gui\" feature) \
+                to run without a display; --no-tray still needs one",
+                prefix,
+                e,
+                describe_display_var("DISPLAY"),
+                describe_display_var("WAYLAND_DISPLAY")
+            ),
+            #[cfg(feature = "runtime-api")]
+            APIListenerError(e) => write!(f, "{}-APIListenerError: {}", prefix, e),
+            IOError(e) => write!(f, "{}-IOError: {}", prefix, e),
+        }
+    }
+}
+
+impl From<ProfileLoadError> for AppStartError {
+    fn from(err: ProfileLoadError) -> Self {
+        Self::ProfileLoadError(err)
+    }
+}
+impl From<ctrlc::Error> for AppStartError {
+    fn from(err: ctrlc::Error) -> Self {
+        Self::CtrlCError(err)
+    }
+}
+#[cfg(feature = "gui")]
+impl From<glib::BoolError> for AppStartError {
+    fn from(err: glib::BoolError) -> Self {
+        Self::GLibBoolError(err)
+    }
+}
+#[cfg(feature = "gui")]
+impl From<glib::Error> for AppStartError {
+    fn from(err: glib::Error) -> Self {
+        Self::GLibError(err)
+    }
+}
+#[cfg(feature = "runtime-api")]
+impl From<APIListenerError> for AppStartError {
+    fn from(err: APIListenerError) -> Self {
+        Self::APIListenerError(err)
+    }
+}
+impl From<io::Error> for AppStartError {
+    fn from(err: io::Error) -> Self {
+        Self::IOError(err)
+    }
+}
+
+#[derive(Debug)]
+struct App {
+    // core
+    app_state_path: PathBuf,
+    profiles_dir: PathBuf,
+    profile_folder: ProfileFolder,
+    profile_manager: Arc<RwLock<ProfileManager>>,
+    events_tx: Sender<AppEvent>,
+    events_rx: Receiver<AppEvent>,
+    #[allow(dead_code)]
+    resume_detector: Option<ResumeDetector>, // this needs to be stored to be kept alive
+    #[allow(dead_code)]
+    network_watcher: Option<NetworkWatcher>, // this needs to be stored to be kept alive
+    #[allow(dead_code)]
+    profile_watcher: Option<ProfileWatcher>, // this needs to be stored to be kept alive
+    /// `None` if latency probing is disabled.
+    latency_prober: Option<LatencyProber>,
+    /// Blocks traffic while no `sslocal` is running; `None` if not configured.
+    kill_switch: Option<KillSwitch>,
+
+    // runtime API
+    #[cfg(feature = "runtime-api")]
+    #[allow(dead_code)]
+    api_listener: APIListener, // this needs to be stored to be kept alive
+    #[cfg(feature = "runtime-api")]
+    api_cmds_rx: Receiver<APIRequest>,
+
+    // GUI components
+    /// `None` if running without a tray icon.
+    #[cfg(feature = "gui")]
+    tray: Option<TrayItem>,
+    #[cfg(feature = "gui")]
+    log_viewer_window: Option<LogViewerWindow>,
+    /// Whether to show the active profile's uptime in the log viewer's title.
+    #[cfg(feature = "gui")]
+    log_viewer_uptime: bool,
+    /// When the instance stats shown in the tray were last refreshed.
+    #[cfg(feature = "gui")]
+    tray_stats_refreshed_at: Instant,
+
+    // misc
+    /// `None` means autosave is disabled.
+    autosave_interval: Option<Duration>,
+    /// The app state as last saved (or loaded), used to skip needless autosaves.
+    last_saved_state: Option<AppState>,
+    /// When an important change that should be saved soon was first made since the last save.
+    save_requested: Option<Instant>,
+    notify_method: NotifyMethod,
+    toast_timeout: ToastTimeout,
+    /// Always resume with this profile if set; preserved in the app state.
+    default_profile: Option<String>,
+    /// The most recent profile switches; preserved in the app state.
+    profile_history: ProfileHistory,
+    /// The tray icons loaded from app state; `None` if derived from `--icon-name`.
+    ///
+    /// Unused when headless, but kept so that it's preserved in the app state.
+    tray_icons: Option<TrayIconSet>,
+    /// Whether quitting from the tray needs confirmation while `sslocal` is running.
+    ///
+    /// Unused when headless, but kept so that it's preserved in the app state.
+    confirm_quit: bool,
+    /// Where to write `sslocal`'s logs to disk, if anywhere.
+    ///
+    /// Kept even if the log file could not be opened, so that it's preserved in the app state.
+    log_file: Option<LogFileConfig>,
+    /// Whether the most recently started profile suppresses notifications.
+    profile_quiet: bool,
+    /// Set to false to exit the main loop.
+    running: bool,
+}
+
+impl App {
+    /// Construct the application.
+    fn new(args: &CliArgs) -> Result<Self, AppStartError> {
+        let CliArgs {
+            profiles_dir,
+            app_state_path,
+            #[cfg(feature = "gui")]
+            tray_icon_filename,
+            #[cfg(feature = "gui")]
+            icon_active,
+            #[cfg(feature = "gui")]
+            icon_inactive,
+            #[cfg(feature = "gui")]
+            icon_error,
+            #[cfg(feature = "gui")]
+            icon_theme_dir,
+            #[cfg(feature = "gui")]
+            tray_icon_size,
+            #[cfg(feature = "gui")]
+            no_tray,
+            startup_test,
+            restart_on_resume,
+            restart_on_network_change,
+            watch_profiles,
+            switch_strategy,
+            spool_backlog,
+            backlog_limit,
+            log_buffer_lines,
+            default_profile,
+            autosave_interval,
+            latency_probe_interval,
+            event_poll_ms: _, // used by `run`
+            #[cfg(feature = "gui")]
+            log_viewer_uptime,
+            verbose: _,
+            quiet: _,
+            log_format: _, // used by `main`
+            #[cfg(feature = "runtime-api")]
+            runtime_api_socket_path,
+            #[cfg(feature = "runtime-api")]
+            runtime_api_backup_socket_path,
+            #[cfg(feature = "runtime-api")]
+            runtime_api_require_quit_confirm,
+            sub_cmd: _,
+        } = args;
+
+        // init GTK
+        #[cfg(feature = "gui")]
+        gtk::init().map_err(AppStartError::NoDisplay)?;
+
+        // load profiles
+        let profile_folder = ProfileFolder::from_path_recurse(profiles_dir)?;
+        debug!(
+            "Successfully loaded {} profiles in total",
+            profile_folder.profile_count()
+        );
+
+        // load app state
+        let previous_state = {
+            let state_res = AppState::from_file(app_state_path);
+            match state_res {
+                Err(AppStateError::NotFound(_)) => info!("No saved app state found; using default"),
+                Err(ref err) => warn!("Failed to load saved app state: {}", err),
+                Ok(_) => {}
+            }
+            let mut state = state_res.unwrap_or_default();
+            if let Some(bytes) = backlog_limit {
+                state.backlog_limit = *bytes;
+            }
+            if let Some(lines) = log_buffer_lines {
+                state.log_buffer_lines = *lines as usize;
+            }
+            match default_profile.as_deref() {
+                None => {}
+                Some("") => state.default_profile = None,
+                Some(name) if profile_folder.lookup(name).is_some() => state.default_profile = Some(name.into()),
+                Some(name) => error!("Cannot find default profile \"{}\"; ignoring --default-profile", name),
+            }
+            state
+        };
+
+        // start runtime API
+        // this must precede the leftovers check, so that a live instance holding the socket
+        // fails this one before its `sslocal` processes can be mistaken for leftovers
+        #[cfg(feature = "runtime-api")]
+        let (api_listener, api_cmds_rx) = {
+            let (tx, rx) = unbounded_channel();
+            let bind_addrs: Vec<_> = std::iter::once(runtime_api_socket_path)
+                .chain(runtime_api_backup_socket_path)
+                .collect();
+            let listener = APIListener::start(
+                &bind_addrs,
+                *runtime_api_require_quit_confirm,
+                previous_state.stop_timeout,
+                tx,
+            )?;
+            (listener, rx)
+        };
+        #[cfg(not(any(feature = "gui", feature = "runtime-api")))]
+        warn!("Running headless without the runtime API; ssgtk can only be stopped by signals");
+
+        // check for leftover `sslocal` processes from a crash
+        let pid_registry = {
+            #[cfg(feature = "runtime-api")]
+            let key_path = runtime_api_socket_path;
+            #[cfg(not(feature = "runtime-api"))]
+            let key_path = app_state_path;
+            PidRegistry::for_key(key_path)
+        };
+        match pid_registry.leftovers() {
+            Ok(pids) if pids.is_empty() => trace!("No leftover sslocal processes found"),
+            Ok(pids) => warn!("Found sslocal processes left over from a previous session: {:?}", pids),
+            Err(err) => warn!("Failed to read the sslocal PID registry: {}", err),
+        }
+
+        // resume core
+        let (events_tx, events_rx) = unbounded_channel();
+        let pm_arc = {
+            let mut pm =
+                ProfileManager::resume_from(&previous_state, &profile_folder, Some(pid_registry), events_tx.clone());
+            pm.switch_strategy = *switch_strategy;
+            if *spool_backlog {
+                mutex_lock(&pm.backlog).spool()?;
+            }
+            if let Some(config) = &previous_state.log_file {
+                match RotatingLogFile::open(config.clone()) {
+                    Ok(log_file) => mutex_lock(&pm.backlog).set_log_file(log_file),
+                    Err(err) => error!("Cannot write sslocal logs to {:?}: {}", config.dir, err),
+                }
+            }
+            Arc::new(RwLock::new(pm))
+        };
+
+        // start resume detector
+        let resume_detector = match restart_on_resume {
+            true => Some(ResumeDetector::start(events_tx.clone())?),
+            false => None,
+        };
+
+        // start network watcher
+        let network_watcher = match restart_on_network_change {
+            true => Some(NetworkWatcher::start(events_tx.clone())?),
+            false => None,
+        };
+
+        // start profile watcher
+        let profile_watcher = match watch_profiles {
+            true => Some(ProfileWatcher::start(profiles_dir, events_tx.clone())?),
+            false => None,
+        };
+
+        // start latency prober
+        let latency_prober = match latency_probe_interval {
+            0 => None,
+            secs => Some(LatencyProber::start(
+                &profile_folder,
+                Duration::from_secs(*secs),
+                events_tx.clone(),
+            )?),
+        };
+
+        let profile_quiet = util::rwlock_read(&pm_arc)
+            .current_profile()
+            .filter(|p| p.metadata.quiet)
+            .is_some();
+
+        // build permanent GUI components
+        #[cfg(feature = "gui")]
+        let tray = (!*no_tray).then(|| {
+            let mut icons = previous_state
+                .tray_icons
+                .clone()
+                .unwrap_or_else(|| TrayIconSet::from_base(tray_icon_filename));
+            // launch options take precedence; not saved to app state
+            if let Some(name) = icon_active {
+                icons.active = name.clone();
+            }
+            if let Some(name) = icon_inactive {
+                icons.stopped = name.clone();
+            }
+            if let Some(name) = icon_error {
+                icons.error = name.clone();
+            }
+            let mut tray = TrayItem::build_and_show(
+                &icons,
+                icon_theme_dir.as_deref(),
+                *tray_icon_size,
+                events_tx.clone(),
+                &profile_folder,
+                previous_state.notify_method,
+                log::max_level().into(),
+            );
+            // set tray state to match profile manager state
+            match util::rwlock_read(&pm_arc).current_profile() {
+                Some(p) => tray.notify_profile_switch(p.metadata.display_name),
+                None => tray.notify_sslocal_stop(),
+            }
+            tray.set_profile_history(&previous_state.profile_history);
+            tray
+        });
+        #[cfg(all(feature = "gui", not(feature = "runtime-api")))]
+        if *no_tray {
+            warn!("Running without a tray icon or the runtime API; the app can only be stopped with a signal");
+        }
+
+        // run startup self-test
+        if *startup_test {
+            match util::rwlock_read(&pm_arc).current_profile() {
+                Some(p) => connectivity::spawn_self_test(p, events_tx.clone())?,
+                None => warn!("Startup self-test requested, but no profile is running"),
+            }
+        }
+
+        Ok(Self {
+            app_state_path: app_state_path.clone(),
+            profiles_dir: profiles_dir.clone(),
+            profile_folder,
+            profile_manager: pm_arc,
+            events_tx,
+            events_rx,
+            resume_detector,
+            network_watcher,
+            profile_watcher,
+            latency_prober,
+            kill_switch: previous_state.kill_switch.clone().map(KillSwitch::new),
+
+            #[cfg(feature = "runtime-api")]
+            api_listener,
+            #[cfg(feature = "runtime-api")]
+            api_cmds_rx,
+
+            #[cfg(feature = "gui")]
+            tray,
+            #[cfg(feature = "gui")]
+            log_viewer_window: None,
+            #[cfg(feature = "gui")]
+            log_viewer_uptime: *log_viewer_uptime,
+            #[cfg(feature = "gui")]
+            tray_stats_refreshed_at: Instant::now(),
+
+            autosave_interval: match autosave_interval {
+                0 => None,
+                secs => Some(Duration::from_secs(*secs)),
+            },
+            last_saved_state: Some(previous_state.clone()),
+            save_requested: None,
+            notify_method: previous_state.notify_method,
+            toast_timeout: ToastTimeout {
+                ms: previous_state.notify_timeout_ms,
+                sticky_errors: previous_state.sticky_error_toasts,
+            },
+            default_profile: previous_state.default_profile,
+            profile_history: previous_state.profile_history,
+            tray_icons: previous_state.tray_icons,
+            confirm_quit: previous_state.confirm_quit,
+            log_file: previous_state.log_file,
+            profile_quiet,
+            running: true,
+        })
+    }
+
+    /// Export the current application state.
+    pub fn snapshot(&self) -> AppState {
+        let pm = util::rwlock_read(&self.profile_manager);
+        let most_recent_profile = pm.current_profile().map_or("".into(), |p| p.metadata.display_name);
+        let backlog_limit = mutex_lock(&pm.backlog).limit().map_or(0, |bytes| bytes as u64);
+        #[cfg(feature = "gui")]
+        let log_viewer_open = self.log_viewer_window.is_some();
+        // keep whatever a GUI build last recorded
+        #[cfg(not(feature = "gui"))]
+        let log_viewer_open = matches!(
+            self.last_saved_state,
+            Some(AppState {
+                log_viewer_open: true,
+                ..
+            })
+        );
+        AppState {
+            most_recent_profile,
+            default_profile: self.default_profile.clone(),
+            profile_history: self.profile_history.clone(),
+            restart_limit: pm.restart_limit(),
+            notify_method: self.notify_method,
+            notify_timeout_ms: self.toast_timeout.ms,
+            sticky_error_toasts: self.toast_timeout.sticky_errors,
+            tray_icons: self.tray_icons.clone(),
+            backlog_limit,
+            stop_timeout: pm.stop_timeout,
+            launch_timeout: pm.launch_timeout,
+            log_buffer_lines: pm.log_buffer_lines,
+            resume_retry: pm.resume_retry,
+            log_viewer_open,
+            confirm_quit: self.confirm_quit,
+            kill_switch: self.kill_switch.as_ref().map(|ks| ks.config().clone()),
+            log_file: self.log_file.clone(),
+        }
+    }
+
+    /// Save the app state to disk.
+    fn save_state(&mut self) -> Result<(), AppStateError> {
+        let state = self.snapshot();
+        self.save_requested = None;
+        state.write_to_file(&self.app_state_path)?;
+        self.last_saved_state = Some(state);
+        Ok(())
+    }
+
+    /// Save the app state, unless it hasn't changed since the last save.
+    fn autosave(&mut self) {
+        if self.last_saved_state.as_ref() == Some(&self.snapshot()) {
+            trace!("App state unchanged; skipping autosave");
+            self.save_requested = None;
+            return;
+        }
+        match self.save_state() {
+            Ok(_) => debug!("App state autosaved to {:?}", self.app_state_path),
+            Err(err) => warn!("Failed to autosave app state: {}", err),
+        }
+    }
+
+    /// Have the app state saved shortly, so that an important change survives a crash.
+    ///
+    /// Changes made in quick succession are saved together.
+    fn request_save(&mut self) {
+        self.save_requested.get_or_insert_with(Instant::now);
+    }
+
+    /// Show the log viewer window, if not already shown.
+    #[cfg(feature = "gui")]
+    fn show_log_viewer(&mut self) {
+        match self.log_viewer_window.as_ref() {
+            Some(w) => {
+                debug!("Log viewer window already showing; bringing to foreground");
+                w.show();
+            }
+            None => {
+                let pm_inner = util::rwlock_read(&self.profile_manager);
+                let events_tx = self.events_tx.clone();
+                let backlog = Arc::clone(&pm_inner.backlog);
+                let log_listener = pm_inner.new_listener();
+                let status_fn = self.log_viewer_uptime.then(|| {
+                    let pm = Arc::clone(&self.profile_manager);
+                    Box::new(move || {
+                        let pm = util::rwlock_read(&pm);
+                        let name = pm.current_profile()?.metadata.display_name;
+                        Some((name, pm.uptime()?))
+                    }) as StatusFn
+                });
+
+                debug!("Opening log viewer window.");
+                let window = LogViewerWindow::new(events_tx, backlog, log_listener, status_fn);
+                window.show();
+
+                self.log_viewer_window = Some(window);
+            }
+        }
+    }
+    /// Drop the log viewer window without emitting an extra close event.
+    ///
+    /// Useful when the window has already been closed by an external source
+    /// and we only need to drop the object.
+    #[cfg(feature = "gui")]
+    fn drop_log_viewer(&mut self) {
+        match self.log_viewer_window.take() {
+            None => debug!("Log viewer window is None; nothing to drop"),
+            some => {
+                debug!("Dropping log viewer window");
+                drop(some);
+            }
+        }
+    }
+    /// Close the log viewer window if currently showing.
+    #[cfg(all(feature = "gui", feature = "runtime-api"))]
+    fn close_log_viewer(&mut self) {
+        match self.log_viewer_window.take() {
+            None => debug!("Log viewer window is None; nothing to close"),
+            Some(w) => {
+                debug!("Closing log viewer window");
+                w.close();
+                drop(w);
+            }
+        }
+    }
+    /// Send a notification using the current notification method,
+    /// unless the most recently started profile is quiet, in which case only log it.
+    fn notify(&self, level: Level, text_1: impl AsRef<str>, text_2: impl AsRef<str>) {
+        match self.profile_quiet {
+            true => notify_log(level, text_1.as_ref(), text_2.as_ref()),
+            false => notify(self.notify_method, self.toast_timeout, level, text_1, text_2),
+        }
+    }
+    /// Set the notification method.
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    fn set_notify_method(&mut self, method: NotifyMethod) {
+        info!("Setting notify method to {}", method);
+        self.notify_method = method;
+        self.request_save();
+    }
+    /// Set how long toast notifications stay on screen.
+    #[cfg(feature = "runtime-api")]
+    fn set_toast_timeout(&mut self, timeout: ToastTimeout) {
+        info!("Setting toast timeout to {:?}", timeout);
+        self.toast_timeout = timeout;
+        self.request_save();
+    }
+    /// Set the log level of this application (not `sslocal`).
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    fn set_log_level(&mut self, level: LogLevel) {
+        info!("Setting log level to {}", level);
+        log::set_max_level(level.into());
+    }
+    /// Restart the `sslocal` instance with the current profile.
+    fn restart(&mut self) {
+        let current_profile = util::rwlock_read(&self.profile_manager).current_profile();
+        match current_profile {
+            Some(p) => {
+                info!("Restarting profile \"{}\"", p.metadata.display_name);
+                self.switch_profile(p);
+            }
+            None => warn!("Cannot restart because no sslocal instance is running"),
+        }
+    }
+    /// Switch to the specified profile.
+    fn switch_profile(&mut self, profile: Profile) {
+        self.begin_switch(profile, None);
+    }
+    /// Resume with a profile on launch, retrying later if it fails to start.
+    ///
+    /// `attempt` is the number of retries so far.
+    fn resume_profile(&mut self, profile: Profile, attempt: u32) {
+        self.begin_switch(profile, Some(attempt));
+    }
+    /// Implementation of `Self::switch_profile` & `Self::resume_profile`.
+    fn begin_switch(&mut self, profile: Profile, resume_attempt: Option<u32>) {
+        let name = profile.metadata.display_name.clone();
+        info!("Switching profile to \"{}\"", name);
+        #[cfg(feature = "gui")]
+        if let Some(tray) = &mut self.tray {
+            tray.set_icon_state(IconState::Connecting);
+        }
+        let switch_res = {
+            let mut pm = util::rwlock_write(&self.profile_manager);
+            match resume_attempt {
+                Some(attempt) => pm.resume(profile.clone(), attempt),
+                None => pm.switch_to(profile.clone()),
+            }
+        };
+        match switch_res {
+            Ok(StartProgress::Started) => self.finish_switch(profile, Ok(())),
+            Ok(StartProgress::Deferred) => {
+                debug!("Switching to profile \"{}\" once the old instances have exited", name)
+            }
+            Err(err) => self.finish_switch(profile, Err(err)),
+        }
+    }
+    /// Update the app's state and the tray once a switch has succeeded or failed,
+    /// whether right away or after being deferred.
+    fn finish_switch(&mut self, profile: Profile, res: io::Result<()>) {
+        let name = profile.metadata.display_name;
+        let quiet = profile.metadata.quiet;
+        match res {
+            Ok(_) => {
+                // only once it's actually running
+                self.profile_quiet = quiet;
+                self.profile_history.push(&name, SystemTime::now());
+                self.request_save();
+                #[cfg(feature = "gui")]
+                if let Some(tray) = &mut self.tray {
+                    tray.notify_profile_switch(&name);
+                    tray.set_profile_history(&self.profile_history);
+                }
+                // in case it was running alongside the old profile
+                #[cfg(feature = "gui")]
+                self.sync_tray_concurrent();
+            }
+            Err(err) => {
+                error!("Cannot switch to profile \"{}\": {}", name, err);
+                #[cfg(feature = "gui")]
+                {
+                    self.sync_tray_selection();
+                    if let Some(tray) = &mut self.tray {
+                        tray.set_icon_state(IconState::Error);
+                    }
+                }
+            }
+        }
+    }
+    /// Start a profile alongside the active one.
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    fn start_concurrent(&mut self, profile: Profile) {
+        let name = profile.metadata.display_name.clone();
+        info!("Starting profile \"{}\" alongside the active one", name);
+        let start_res = util::rwlock_write(&self.profile_manager).start(profile);
+        match start_res {
+            Ok(StartProgress::Started) => self.finish_start_concurrent(&name, Ok(())),
+            Ok(StartProgress::Deferred) => debug!("Starting profile \"{}\" once the old instances have exited", name),
+            Err(err) => self.finish_start_concurrent(&name, Err(err)),
+        }
+    }
+    /// Update the tray once a profile has started alongside the active one, or failed to,
+    /// whether right away or after being deferred.
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    fn finish_start_concurrent(&mut self, name: &str, res: io::Result<()>) {
+        if let Err(err) = res {
+            error!("Cannot start profile \"{}\": {}", name, err);
+        }
+        #[cfg(feature = "gui")]
+        self.sync_tray_concurrent();
+    }
+    /// Stop a profile running alongside the active one, returning whether it was running.
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    fn stop_concurrent(&mut self, name: &str) -> bool {
+        let stop_res = {
+            let mut pm = util::rwlock_write(&self.profile_manager);
+            let is_active = pm.current_profile().map(|p| p.metadata.display_name).as_deref() == Some(name);
+            match is_active {
+                // also cancels a deferred start
+                false => pm.stop(name),
+                true => Err(()),
+            }
+        };
+        match stop_res {
+            Ok(_) => info!("Stopped profile \"{}\"", name),
+            Err(_) => warn!("Profile \"{}\" is not running alongside the active one", name),
+        }
+        #[cfg(feature = "gui")]
+        self.sync_tray_concurrent();
+        stop_res.is_ok()
+    }
+    /// Set the tray's checked concurrent profiles to match the profile manager's actual state.
+    #[cfg(feature = "gui")]
+    fn sync_tray_concurrent(&mut self) {
+        if let Some(tray) = &mut self.tray {
+            tray.set_concurrent_profiles(&util::rwlock_read(&self.profile_manager).concurrent_profiles());
+        }
+    }
+    /// Set the tray's profile selection to match the profile manager's actual state,
+    /// e.g. after a failed switch.
+    #[cfg(feature = "gui")]
+    fn sync_tray_selection(&mut self) {
+        if let Some(tray) = &mut self.tray {
+            match util::rwlock_read(&self.profile_manager).current_profile() {
+                Some(p) => tray.notify_profile_switch(p.metadata.display_name),
+                None => tray.notify_sslocal_stop(),
+            }
+        }
+    }
+    /// Stop the current `sslocal` instance.
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    fn stop(&mut self) {
+        let mut pm_inner = util::rwlock_write(&self.profile_manager);
+        // also cancels a deferred switch
+        if pm_inner.try_stop().is_ok() {
+            info!("Sending stop signal to sslocal");
+            #[cfg(feature = "gui")]
+            if let Some(tray) = &mut self.tray {
+                tray.set_icon_state(IconState::Stopped);
+            }
+        } else {
+            info!("sslocal is not running; nothing to stop");
+        }
+    }
+    /// Stop the current `sslocal` instance if active,
+    /// otherwise start the most recent profile again.
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    fn toggle(&mut self) {
+        let (active, last_profile) = {
+            let pm = util::rwlock_read(&self.profile_manager);
+            (pm.is_active() || pm.is_starting(), pm.last_profile())
+        };
+        match (active, last_profile) {
+            (true, _) => {
+                self.stop();
+                #[cfg(feature = "gui")]
+                if let Some(tray) = &mut self.tray {
+                    tray.notify_sslocal_stop();
+                }
+            }
+            (false, Some(p)) => {
+                self.switch_profile(p);
+            }
+            (false, None) => warn!("Cannot toggle sslocal on because no profile has been started yet"),
+        }
+    }
+    /// Switch to the profile after (or before) the active one, in the order shown in the tray.
+    #[cfg(feature = "runtime-api")]
+    fn cycle_profile(&mut self, forward: bool) {
+        let current = util::rwlock_read(&self.profile_manager)
+            .current_profile()
+            .map(|p| p.metadata.display_name);
+        match self.profile_folder.cycle_profile(current.as_deref(), forward).cloned() {
+            Some(p) => {
+                self.switch_profile(p);
+            }
+            None => warn!("Cannot switch profile because there are no profiles"),
+        }
+    }
+    /// Re-scan the profiles directory, and rebuild the tray's profile menu.
+    ///
+    /// The active instance is kept running, even if its profile no longer exists.
+    ///
+    /// Returns the number of profiles loaded.
+    fn reload_profiles(&mut self) -> Result<usize, ProfileLoadError> {
+        let profile_folder = ProfileFolder::from_path_recurse(&self.profiles_dir)?;
+        let count = profile_folder.profile_count();
+        info!("Reloaded {} profiles from {:?}", count, self.profiles_dir);
+
+        // check that the active profile still exists
+        let current_name = util::rwlock_read(&self.profile_manager)
+            .current_profile()
+            .map(|p| p.metadata.display_name);
+        if let Some(name) = &current_name {
+            if profile_folder.lookup(name).is_none() {
+                warn!("Active profile \"{}\" no longer exists; keeping it running", name);
+            }
+        }
+
+        if let Some(prober) = &self.latency_prober {
+            prober.set_profiles(&profile_folder);
+        }
+
+        #[cfg(feature = "gui")]
+        if let Some(tray) = &mut self.tray {
+            tray.reload_profiles(&profile_folder, self.events_tx.clone());
+            tray.set_profile_history(&self.profile_history);
+            if let Some(prober) = &self.latency_prober {
+                tray.set_latencies(&prober.latencies());
+            }
+            // reselect the active profile, if it's still in the menu
+            if let Some(name) = current_name.filter(|name| profile_folder.lookup(name).is_some()) {
+                tray.notify_profile_switch(name);
+            }
+        }
+        // the rebuilt menu has all concurrent items unchecked
+        #[cfg(feature = "gui")]
+        self.sync_tray_concurrent();
+
+        self.profile_folder = profile_folder;
+        Ok(count)
+    }
+    /// Reload profiles after their config files have changed, and offer to restart
+    /// the active profile with its new config if it's among them.
+    ///
+    /// Changes to other profiles take effect the next time they're switched to.
+    fn apply_profile_changes(&mut self, paths: &[PathBuf]) {
+        if let Err(err) = self.reload_profiles() {
+            error!("Failed to reload changed profiles; keeping the old ones: {}", err);
+            self.notify(Level::Error, "Profile Reload Failed", err.to_string());
+            return;
+        }
+        let current_file = match util::rwlock_read(&self.profile_manager).current_profile() {
+            Some(p) if paths.contains(&p.metadata.config_file) => p.metadata.config_file,
+            _ => return,
+        };
+        let profile = match self.profile_folder.lookup_by_config_file(&current_file) {
+            Some(p) => p.clone(),
+            None => {
+                warn!("The active profile's config file {:?} has been removed", current_file);
+                return;
+            }
+        };
+        let name = profile.metadata.display_name.clone();
+        #[cfg(feature = "gui")]
+        {
+            let text_2 = format!("Restart \"{}\" with its new config?", name);
+            if confirm_blocking_prompt("Active Profile Changed", &text_2) {
+                self.switch_profile(profile);
+            } else {
+                info!(
+                    "Not restarting profile \"{}\"; its changes apply on the next switch",
+                    name
+                );
+            }
+        }
+        #[cfg(not(feature = "gui"))]
+        {
+            let text_2 = format!("The config of \"{}\" has changed; switch to it again to apply", name);
+            self.notify(Level::Info, "Active Profile Changed", text_2);
+        }
+    }
+    /// Open the current profile's link using `xdg-open`.
+    #[cfg(feature = "gui")]
+    fn open_profile_link(&mut self) {
+        let profile = match util::rwlock_read(&self.profile_manager).current_profile() {
+            Some(p) => p,
+            None => {
+                warn!("Cannot open profile link because no sslocal instance is running");
+                return;
+            }
+        };
+        match &profile.metadata.url {
+            Some(url) => {
+                info!("Opening link of profile \"{}\": {}", profile.metadata.display_name, url);
+                // wait for `xdg-open` in a separate thread, so that it's reaped without blocking
+                let url = url.clone();
+                let spawn_res = thread::Builder::new().name("xdg-open".into()).spawn(move || {
+                    match duct::cmd!("xdg-open", url).stdin_null().unchecked().run() {
+                        Ok(output) if !output.status.success() => error!("xdg-open exited with {}", output.status),
+                        Ok(_) => {}
+                        Err(err) => error!("Failed to open link with xdg-open: {}", err),
+                    }
+                });
+                if let Err(err) = spawn_res {
+                    error!("Failed to spawn a thread to run xdg-open: {}", err);
+                }
+            }
+            None => warn!("Profile \"{}\" has no link", profile.metadata.display_name),
+        }
+    }
+    /// Quit the application from the tray,
+    /// asking for confirmation first if configured to and `sslocal` is running.
+    #[cfg(feature = "gui")]
+    fn manual_quit(&mut self) {
+        let active = util::rwlock_read(&self.profile_manager).is_active();
+        if self.confirm_quit && active {
+            let confirmed = confirm_blocking_prompt(
+                "Quit shadowsocks-gtk-rs?",
+                "sslocal is running, and all connections through it will be closed.",
+            );
+            if !confirmed {
+                info!("Quit cancelled");
+                return;
+            }
+        }
+        self.quit();
+    }
+    /// Quit the application.
+    fn quit(&mut self) {
+        info!("Quit");
+
+        #[cfg(feature = "systemd")]
+        if let Err(err) = sd_notify::notify("STOPPING=1") {
+            warn!("Failed to notify systemd of stopping: {}", err);
+        }
+
+        // cleanup
+        // save app state
+        match self.save_state() {
+            Ok(_) => info!("App state saved to {:?}", self.app_state_path),
+            Err(err) => error!("Failed to save app state: {}", err),
+        };
+        // stop any running `sslocal` process
+        let _ = util::rwlock_write(&self.profile_manager).try_stop();
+        // stop blocking traffic
+        if let Some(kill_switch) = &mut self.kill_switch {
+            if let Err(err) = kill_switch.release() {
+                error!("Failed to release kill switch: {}", err);
+            }
+        }
+
+        // drop all optional windows
+        #[cfg(feature = "gui")]
+        {
+            debug!("Closing all optional windows");
+            drop(self.log_viewer_window.take());
+            gtk::main_quit();
+        }
+
+        self.running = false;
+    }
+
+    /// Engage the kill switch if no `sslocal` is running, or release it otherwise.
+    fn sync_kill_switch(&mut self) {
+        let kill_switch = match &mut self.kill_switch {
+            Some(ks) => ks,
+            None => return,
+        };
+        let (active, last_profile) = {
+            let pm = util::rwlock_read(&self.profile_manager);
+            (pm.is_active(), pm.last_profile())
+        };
+        let server = last_profile.as_ref().and_then(Profile::server_addr);
+        if let Err(err) = kill_switch.sync(active, server) {
+            let action = if active { "release" } else { "engage" };
+            error!("Failed to {} kill switch: {}", action, err);
+            let text_2 = format!("Failed to {} kill switch: {}", action, err);
+            self.notify(Level::Error, "Kill Switch Failed", text_2);
+        }
+    }
+
+    /// Handles the queued incoming app events.
+    fn handle_app_events(&mut self) {
+        use AppEvent::*;
+        // using `while let` rather than `for` due to borrow checker issue
+        while let Some(event) = self.events_rx.try_iter().next() {
+            trace!("Received an AppEvent: {:?}", event);
+            match event {
+                #[cfg(feature = "gui")]
+                LogViewerShow => self.show_log_viewer(),
+                #[cfg(feature = "gui")]
+                LogViewerHide => self.drop_log_viewer(),
+                #[cfg(feature = "gui")]
+                SwitchProfile(p) => {
+                    self.switch_profile(p);
+                }
+                #[cfg(feature = "gui")]
+                StartConcurrent(p) => {
+                    self.start_concurrent(p);
+                }
+                #[cfg(feature = "gui")]
+                StopConcurrent(name) => {
+                    self.stop_concurrent(&name);
+                }
+                Restart => self.restart(),
+                #[cfg(feature = "gui")]
+                ManualStop => self.stop(),
+                #[cfg(feature = "gui")]
+                Toggle => self.toggle(),
+                #[cfg(feature = "gui")]
+                OpenProfileLink => self.open_profile_link(),
+                #[cfg(feature = "gui")]
+                SetNotify(method) => self.set_notify_method(method),
+                #[cfg(feature = "gui")]
+                SetLogLevel(level) => self.set_log_level(level),
+                #[cfg(feature = "gui")]
+                ManualQuit => self.manual_quit(),
+                Quit => self.quit(),
+
+                OkStop { instance_name } => {
+                    // this event could be received because an old instance is stopped
+                    // and a new one is started (or about to be), therefore we first check for active instance
+                    let busy = {
+                        let pm = util::rwlock_read(&self.profile_manager);
+                        pm.is_active() || pm.is_starting()
+                    };
+                    if !busy {
+                        #[cfg(feature = "gui")]
+                        if let Some(tray) = &mut self.tray {
+                            tray.notify_sslocal_stop();
+                        }
+                        let text_2 = format!("An instance has stopped: {}", instance_name.unwrap_or("None".into()));
+                        self.notify(Level::Warn, "Auto-restart Stopped", text_2);
+                    }
+                }
+                ErrorStop {
+                    instance_name,
+                    err,
+                    kind,
+                } => {
+                    #[cfg(feature = "gui")]
+                    {
+                        if let Some(tray) = &mut self.tray {
+                            tray.notify_sslocal_stop();
+                            tray.set_icon_state(IconState::Error);
+                        }
+                    }
+                    // explain recognised failures, keeping the raw message for reference
+                    let explanation = match kind {
+                        Some(k) => format!("{}\n{}", k, err),
+                        None => err,
+                    };
+                    let text_2 = format!(
+                        "An instance has errored: {}\n{}",
+                        instance_name.unwrap_or("None".into()),
+                        explanation
+                    );
+                    self.notify(Level::Error, "Auto-restart Stopped", text_2);
+                }
+                ConcurrentStop {
+                    profile_name,
+                    err,
+                    kind,
+                } => {
+                    #[cfg(feature = "gui")]
+                    self.sync_tray_concurrent();
+                    match err {
+                        None => {
+                            let text_2 = format!("Profile \"{}\" has stopped", profile_name);
+                            self.notify(Level::Warn, "Auto-restart Stopped", text_2);
+                        }
+                        Some(err) => {
+                            let explanation = match kind {
+                                Some(k) => format!("{}\n{}", k, err),
+                                None => err,
+                            };
+                            let text_2 = format!("Profile \"{}\" has errored:\n{}", profile_name, explanation);
+                            self.notify(Level::Error, "Auto-restart Stopped", text_2);
+                        }
+                    }
+                }
+                RestartOk { instance_name, attempt } => {
+                    info!("{} has restarted successfully (attempt {})", instance_name, attempt);
+                    #[cfg(feature = "gui")]
+                    self.sync_tray_selection();
+                    let text_2 = format!("Restarted after a failure (attempt {}): {}", attempt, instance_name);
+                    self.notify(Level::Info, "sslocal Restarted", text_2);
+                }
+                RestartBudgetLow {
+                    profile_name,
+                    remaining,
+                } => {
+                    let text_2 = format!(
+                        "Profile \"{}\" keeps failing and will be abandoned after {} more failure(s).\n\
+                        Consider switching to another profile.",
+                        profile_name, remaining
+                    );
+                    self.notify(Level::Warn, "Auto-restart Budget Low", text_2);
+                }
+                StartupWaitDone { profile, generation } => {
+                    let stale = util::rwlock_read(&self.profile_manager).switch_generation() != generation;
+                    if stale {
+                        info!(
+                            "Profile \"{}\" has finished waiting, but sslocal has since been switched or stopped",
+                            profile.metadata.display_name
+                        );
+                    } else {
+                        self.resume_profile(profile, 0);
+                    }
+                }
+                ResumeRetry {
+                    profile,
+                    attempt,
+                    generation,
+                } => {
+                    let stale = util::rwlock_read(&self.profile_manager).switch_generation() != generation;
+                    if stale {
+                        info!(
+                            "Not retrying to resume with profile \"{}\", because sslocal has since been switched or stopped",
+                            profile.metadata.display_name
+                        );
+                    } else {
+                        self.resume_profile(profile, attempt);
+                    }
+                }
+                StartReady { id, pre_up } => {
+                    let deferred = util::rwlock_write(&self.profile_manager).proceed(id, pre_up);
+                    match deferred {
+                        Some(DeferredStart {
+                            profile,
+                            concurrent: false,
+                            res,
+                        }) => self.finish_switch(profile, res),
+                        #[cfg(any(feature = "gui", feature = "runtime-api"))]
+                        Some(DeferredStart {
+                            profile,
+                            concurrent: true,
+                            res,
+                        }) => self.finish_start_concurrent(&profile.metadata.display_name, res),
+                        _ => {}
+                    }
+                }
+                SelfTestResult { profile_name, res } => {
+                    #[cfg(feature = "gui")]
+                    if let Some(tray) = &mut self.tray {
+                        tray.notify_self_test_result(res.is_ok());
+                    }
+                    match res {
+                        Ok(msg) => {
+                            info!("Self-test for profile \"{}\" passed: {}", profile_name, msg);
+                            self.notify(Level::Info, "Self-test Passed", msg);
+                        }
+                        Err(err) => {
+                            error!("Self-test for profile \"{}\" failed: {}", profile_name, err);
+                            let text_2 = format!("Profile \"{}\": {}", profile_name, err);
+                            self.notify(Level::Error, "Self-test Failed", text_2);
+                        }
+                    }
+                }
+                NetworkChanged => {
+                    let count_res = {
+                        let pm = util::rwlock_read(&self.profile_manager);
+                        pm.is_active().then(|| pm.count_network_restart())
+                    };
+                    match count_res {
+                        None => debug!("Network has changed, but no profile is active; did nothing"),
+                        Some(Ok(_)) => {
+                            info!("Network has changed; restarting the active profile");
+                            self.restart();
+                        }
+                        Some(Err(err)) => warn!("Network has changed, but restarting too often; did nothing: {}", err),
+                    }
+                }
+                ProfileFilesChanged { paths } => self.apply_profile_changes(&paths),
+                LatenciesProbed =>
+                {
+                    #[cfg(feature = "gui")]
+                    if let (Some(tray), Some(prober)) = (&mut self.tray, &self.latency_prober) {
+                        tray.set_latencies(&prober.latencies());
+                    }
+                }
+                ProbeFailed { profile_name, err } => {
+                    warn!("Connectivity probe for profile \"{}\" failed: {}", profile_name, err);
+                    let text_2 = format!("Profile \"{}\" is running, but may not work.\n{}", profile_name, err);
+                    self.notify(Level::Warn, "Connectivity Probe Failed", text_2);
+                }
+            }
+        }
+    }
+
+    /// Handles the queued incoming runtime API commands.
+    #[cfg(feature = "runtime-api")]
+    fn handle_api_commands(&mut self) {
+        use APICommand::*;
+        // using `while let` rather than `for` due to borrow checker issue
+        while let Some(APIRequest { cmd, reply_tx }) = self.api_cmds_rx.try_iter().next() {
+            match cmd {
+                #[cfg(feature = "gui")]
+                LogViewerShow => self.show_log_viewer(),
+                #[cfg(feature = "gui")]
+                LogViewerHide => self.close_log_viewer(),
+                #[cfg(not(feature = "gui"))]
+                LogViewerShow | LogViewerHide => {
+                    let reply = "There is no log viewer when headless; did nothing";
+                    warn!("{}", reply);
+                    if let Err(_) = reply_tx.send(reply.into()) {
+                        warn!(
+                            "Trying to reply to {:?} command, but the client handler has hung up.",
+                            cmd
+                        );
+                    }
+                }
+                SetNotify(method) => {
+                    self.set_notify_method(method);
+                    #[cfg(feature = "gui")]
+                    if let Some(tray) = &mut self.tray {
+                        tray.notify_notify_method_change(method);
+                    }
+                }
+                SetNotifyTimeout { ms, sticky_errors } => self.set_toast_timeout(ToastTimeout { ms, sticky_errors }),
+                SetLogLevel(level) => {
+                    self.set_log_level(level);
+                    #[cfg(feature = "gui")]
+                    if let Some(tray) = &mut self.tray {
+                        tray.notify_log_level_change(level);
+                    }
+                }
+                #[cfg(feature = "gui")]
+                ReloadIcon | SetIcon { .. } if self.tray.is_none() => {
+                    let reply = "Error: running without a tray icon; did nothing";
+                    warn!("{}", reply);
+                    if let Err(_) = reply_tx.send(reply.into()) {
+                        warn!(
+                            "Trying to reply to {:?} command, but the client handler has hung up.",
+                            cmd
+                        );
+                    }
+                }
+                #[cfg(feature = "gui")]
+                ReloadIcon => {
+                    if let Some(tray) = &mut self.tray {
+                        tray.reload_icon();
+                    }
+                }
+                #[cfg(feature = "gui")]
+                SetIcon { name, theme_dir } => {
+                    if let Some(tray) = &mut self.tray {
+                        if let Err(err) = tray.set_icon(&name, theme_dir.as_deref()) {
+                            if let Err(_) = reply_tx.send(format!("Error: {}; kept the current icon", err)) {
+                                warn!("Trying to reply to SetIcon command, but the client handler has hung up.");
+                            }
+                        }
+                    }
+                }
+                #[cfg(not(feature = "gui"))]
+                ReloadIcon | SetIcon { .. } => {
+                    let reply = "There is no tray icon when headless; did nothing";
+                    warn!("{}", reply);
+                    if let Err(_) = reply_tx.send(reply.into()) {
+                        warn!(
+                            "Trying to reply to {:?} command, but the client handler has hung up.",
+                            cmd
+                        );
+                    }
+                }
+
+                Restart => self.restart(),
+                SwitchProfile(name) => match self.profile_folder.lookup(&name).cloned() {
+                    Some(p) => {
+                        self.switch_profile(p);
+                    }
+                    None => error!("Cannot find a profile named \"{}\"; did nothing", name),
+                },
+                StartProfile(name) => match self.profile_folder.lookup(&name).cloned() {
+                    Some(p) => {
+                        self.start_concurrent(p);
+                    }
+                    None => error!("Cannot find a profile named \"{}\"; did nothing", name),
+                },
+                StopProfile(name) => {
+                    self.stop_concurrent(&name);
+                }
+                NextProfile => self.cycle_profile(true),
+                PrevProfile => self.cycle_profile(false),
+                Stop => {
+                    self.stop();
+                    #[cfg(feature = "gui")]
+                    if let Some(tray) = &mut self.tray {
+                        tray.notify_sslocal_stop();
+                    }
+                }
+                Toggle => self.toggle(),
+                StopWait => {
+                    // stopping can take a while, so reply from the stopping thread
+                    let reply_tx_clone = reply_tx.clone();
+                    let stop_res = util::rwlock_write(&self.profile_manager).stop_then(move || {
+                        let reply = "sslocal has exited";
+                        info!("{}", reply);
+                        if let Err(_) = reply_tx_clone.send(reply.into()) {
+                            warn!("Trying to reply to StopWait command, but the client handler has hung up.");
+                        }
+                    });
+                    match stop_res {
+                        Ok(_) => info!("Stopping sslocal; will reply once it has exited"),
+                        Err(_) => {
+                            let reply = "sslocal is not running; nothing to stop";
+                            info!("{}", reply);
+                            if let Err(_) = reply_tx.send(reply.into()) {
+                                warn!("Trying to reply to StopWait command, but the client handler has hung up.");
+                            }
+                        }
+                    }
+                    #[cfg(feature = "gui")]
+                    if let Some(tray) = &mut self.tray {
+                        tray.notify_sslocal_stop();
+                    }
+                }
+                Quit | QuitConfirmed => self.quit(),
+                ResetRestartCounter => {
+                    info!("Resetting auto-restart counter");
+                    util::rwlock_write(&self.profile_manager).reset_restart_counter();
+                }
+                PauseAutoRestart | ResumeAutoRestart => {
+                    let paused = matches!(cmd, PauseAutoRestart);
+                    info!("{} auto-restart", if paused { "Pausing" } else { "Resuming" });
+                    util::rwlock_write(&self.profile_manager).set_auto_restart_paused(paused);
+                    #[cfg(feature = "gui")]
+                    if let Some(tray) = &mut self.tray {
+                        tray.notify_auto_restart_paused(paused);
+                    }
+                }
+                SetRestartLimit { times, within_secs } => {
+                    info!("Setting auto-restart limit to {} times within {}s", times, within_secs);
+                    let limit = NaiveLeakyBucketConfig::new(times, Duration::from_secs(within_secs));
+                    util::rwlock_write(&self.profile_manager).set_restart_limit(limit);
+                }
+                ReloadProfiles => {
+                    let reply = match self.reload_profiles() {
+                        Ok(count) => format!("Reloaded {} profiles", count),
+                        Err(err) => {
+                            error!("Failed to reload profiles: {}", err);
+                            format!("Failed to reload profiles; kept the old ones: {}", err)
+                        }
+                    };
+                    if let Err(_) = reply_tx.send(reply) {
+                        warn!("Trying to reply to ReloadProfiles command, but the client handler has hung up.");
+                    }
+                }
+                Bypass { enable } => {
+                    let current_profile = util::rwlock_read(&self.profile_manager).current_profile();
+                    // hooks can be slow (e.g. waiting for a password prompt), so run them off the main loop
+                    let spawn_res = thread::Builder::new().name("Bypass hook runner".into()).spawn(move || {
+                        let action = if enable { "bypass" } else { "restore" };
+                        let reply = match current_profile.as_ref().map(|p| (p, p.run_bypass_hook(enable))) {
+                            None => "No active profile; did nothing".into(),
+                            Some((p, None)) => format!(
+                                "Profile \"{}\" has no bypass hooks; did nothing",
+                                p.metadata.display_name
+                            ),
+                            Some((_, Some(Ok(_)))) => format!("The {} hook has run successfully", action),
+                            Some((_, Some(Err(err)))) => format!("The {} hook failed: {}", action, err),
+                        };
+                        info!("{}", reply);
+                        if let Err(_) = reply_tx.send(reply) {
+                            warn!("Trying to reply to Bypass command, but the client handler has hung up.");
+                        }
+                    });
+                    if let Err(err) = spawn_res {
+                        error!("Failed to spawn a thread to run the bypass hook: {}", err);
+                    }
+                }
+
+                Query => {
+                    let reply = json5::to_string(&self.query()).expect("serialising QueryReply to json5 is infallible");
+                    if let Err(_) = reply_tx.send(reply) {
+                        warn!("Trying to reply to Query command, but the client handler has hung up.");
+                    }
+                }
+                FindProfiles(pattern) => {
+                    let reply = self
+                        .profile_folder
+                        .find(&pattern)
+                        .into_iter()
+                        .map(|p| &p.metadata.display_name)
+                        .join("\n");
+                    if let Err(_) = reply_tx.send(reply) {
+                        warn!("Trying to reply to FindProfiles command, but the client handler has hung up.");
+                    }
+                }
+                ListProfiles => {
+                    let tree = ProfileTreeNode::from(&self.profile_folder);
+                    let reply = json5::to_string(&tree).expect("serialising ProfileTreeNode to json5 is infallible");
+                    if let Err(_) = reply_tx.send(reply) {
+                        warn!("Trying to reply to ListProfiles command, but the client handler has hung up.");
+                    }
+                }
+                GetPids => {
+                    let pids = util::rwlock_read(&self.profile_manager).pids();
+                    let reply = json5::to_string(&pids).expect("serialising PIDs to json5 is infallible");
+                    if let Err(_) = reply_tx.send(reply) {
+                        warn!("Trying to reply to GetPids command, but the client handler has hung up.");
+                    }
+                }
+                Metrics => {
+                    if let Err(_) = reply_tx.send(self.metrics()) {
+                        warn!("Trying to reply to Metrics command, but the client handler has hung up.");
+                    }
+                }
+                GetLatencies => {
+                    let reply = match &self.latency_prober {
+                        Some(prober) => {
+                            let latencies: Vec<_> = prober
+                                .latencies()
+                                .into_iter()
+                                .map(|(profile, latency)| ProfileLatency {
+                                    profile,
+                                    latency_ms: latency.map(|rtt| rtt.as_millis() as u64),
+                                })
+                                .collect();
+                            json5::to_string(&latencies).expect("serialising ProfileLatency to json5 is infallible")
+                        }
+                        None => "Error: latency probing is disabled".into(),
+                    };
+                    if let Err(_) = reply_tx.send(reply) {
+                        warn!("Trying to reply to GetLatencies command, but the client handler has hung up.");
+                    }
+                }
+                TestProfile(name) => match self.profile_folder.lookup(&name) {
+                    Some(profile) => {
+                        // the test takes a few seconds, so don't block the main thread
+                        let profile = profile.clone();
+                        let spawn_res = thread::Builder::new().name("profile test".into()).spawn(move || {
+                            let reply = match crate::profile_manager::test_profile(profile) {
+                                Ok(res) => {
+                                    json5::to_string(&res).expect("serialising TestProfileReply to json5 is infallible")
+                                }
+                                Err(err) => format!("Error: cannot start sslocal: {}", err),
+                            };
+                            if let Err(_) = reply_tx.send(reply) {
+                                warn!("Trying to reply to TestProfile command, but the client handler has hung up.");
+                            }
+                        });
+                        if let Err(err) = spawn_res {
+                            error!("Failed to start the profile test: {}", err);
+                        }
+                    }
+                    None => {
+                        let reply = format!("Error: profile \"{}\" not found", name);
+                        if let Err(_) = reply_tx.send(reply) {
+                            warn!("Trying to reply to TestProfile command, but the client handler has hung up.");
+                        }
+                    }
+                },
+                GetBacklog { lines } => {
+                    let backlog = Arc::clone(&util::rwlock_read(&self.profile_manager).backlog);
+                    let reply = util::mutex_lock(&backlog).tail(lines).unwrap_or_else(|err| {
+                        warn!("Failed to read backlog: {}", err);
+                        String::new()
+                    });
+                    if let Err(_) = reply_tx.send(reply) {
+                        warn!("Trying to reply to GetBacklog command, but the client handler has hung up.");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Generate the reply for a `Query` command.
+    #[cfg(feature = "runtime-api")]
+    fn query(&self) -> QueryReply {
+        let pm = util::rwlock_read(&self.profile_manager);
+        let stats = pm.instance_stats();
+        QueryReply {
+            active_profile: pm.current_profile().map(|p| p.metadata.display_name),
+            sslocal_running: pm.is_active(),
+            notify_method: self.notify_method,
+            bytes_transferred: pm.bytes_transferred(),
+            auto_restart_paused: pm.auto_restart_paused(),
+            concurrent_profiles: pm.concurrent_profiles(),
+            uptime_secs: stats.map(|s| s.uptime.as_secs()),
+            bytes_sent: stats.map_or(0, |s| s.bytes_sent),
+            bytes_received: stats.map_or(0, |s| s.bytes_received),
+        }
+    }
+
+    /// Generate the reply for a `Metrics` command, in the Prometheus text exposition format.
+    #[cfg(feature = "runtime-api")]
+    fn metrics(&self) -> String {
+        /// Escape a label value as required by the exposition format.
+        fn escape(value: &str) -> String {
+            value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+        }
+
+        let pm = util::rwlock_read(&self.profile_manager);
+        let mut metrics = vec![
+            (
+                "ssgtk_sslocal_running",
+                "gauge",
+                "Whether an sslocal instance is running.",
+                String::new(),
+                if pm.is_active() { 1.0 } else { 0.0 },
+            ),
+            (
+                "ssgtk_restarts_total",
+                "counter",
+                "Successful auto-restarts since launch.",
+                String::new(),
+                pm.total_restarts() as f64,
+            ),
+            (
+                "ssgtk_restarts_recent",
+                "gauge",
+                "Auto-restarts of the active profile within the restart limit's time window.",
+                String::new(),
+                pm.recent_restarts() as f64,
+            ),
+        ];
+        if let Some(profile) = pm.current_profile() {
+            metrics.push((
+                "ssgtk_active_profile_info",
+                "gauge",
+                "The active profile.",
+                format!("{{profile=\"{}\"}}", escape(&profile.metadata.display_name)),
+                1.0,
+            ));
+        }
+        if let Some(elapsed) = pm.since_switch() {
+            metrics.push((
+                "ssgtk_seconds_since_switch",
+                "gauge",
+                "Seconds since the most recent profile switch or manual restart.",
+                String::new(),
+                elapsed.as_secs_f64(),
+            ));
+        }
+        if let Some(bytes) = pm.bytes_transferred() {
+            metrics.push((
+                "ssgtk_sslocal_bytes_transferred",
+                "gauge",
+                "Total bytes transferred by the active sslocal instance.",
+                String::new(),
+                bytes as f64,
+            ));
+        }
+
+        metrics
+            .into_iter()
+            .map(|(name, kind, help, labels, value)| {
+                format!(
+                    "# HELP {0} {1}\n# TYPE {0} {2}\n{0}{3} {4}\n",
+                    name, help, kind, labels, value
+                )
+            })
+            .collect()
+    }
+
+    /// Handle everything queued since the last call; return whether to keep running.
+    fn tick(&mut self, last_autosave: &mut Instant) -> bool {
+        self.handle_app_events();
+
+        #[cfg(feature = "runtime-api")]
+        self.handle_api_commands();
+
+        self.sync_kill_switch();
+
+        // keep the instance stats in the tray up to date
+        #[cfg(feature = "gui")]
+        if self.tray_stats_refreshed_at.elapsed() >= TRAY_STATS_REFRESH_INTERVAL {
+            if let Some(tray) = self.tray.as_mut() {
+                tray.set_instance_stats(util::rwlock_read(&self.profile_manager).instance_stats());
+            }
+            self.tray_stats_refreshed_at = Instant::now();
+        }
+
+        // save important changes soon after they're made
+        if let Some(requested) = self.save_requested {
+            if requested.elapsed() >= SAVE_REQUEST_DEBOUNCE {
+                self.autosave();
+            }
+        }
+
+        // periodically save app state
+        if let Some(interval) = self.autosave_interval {
+            if last_autosave.elapsed() >= interval {
+                self.autosave();
+                *last_autosave = Instant::now();
+            }
+        }
+
+        self.running
+    }
+}
+
+/// Initialize all components and run the main loop until quit.
+///
+/// This is the GTK main loop with the "gui" feature, or a plain loop otherwise.
+pub fn run(args: &CliArgs) -> Result<(), AppStartError> {
+    // init app
+    let mut app = App::new(args)?;
+
+    // all components (including the runtime API listener) are up
+    #[cfg(feature = "systemd")]
+    if let Err(err) = sd_notify::notify("READY=1") {
+        warn!("Failed to notify systemd of readiness: {}", err);
+    }
+
+    // catch signals for soft shutdown
+    let shutdown_trigger_count = Arc::new(Mutex::new(0usize));
+    let events_tx = app.events_tx.clone();
+    ctrlc::set_handler(move || {
+        let mut count = util::mutex_lock(&shutdown_trigger_count);
+        match *count {
+            0 => {
+                info!("Signal received, sending Quit event");
+                if let Err(_) = events_tx.send(AppEvent::Quit) {
+                    error!("Trying to send Quit event for soft shutdown, but all receivers have hung up");
+                    error!("Performing hard shutdown; the app state may be unsaved");
+                    process::exit(0);
+                }
+            }
+            1 => warn!("Send one more signal for hard shutdown"),
+            _ => {
+                warn!("Performing hard shutdown; the app state may be unsaved");
+                process::exit(0);
+            }
+        }
+        *count += 1;
+    })?;
+
+    let mut last_autosave = Instant::now();
+    let poll_interval = Duration::from_millis(args.event_poll_ms);
+
+    #[cfg(feature = "gui")]
+    {
+        // reopen the log viewer if it was open when last quit
+        if let Some(AppState {
+            log_viewer_open: true, ..
+        }) = app.last_saved_state
+        {
+            app.show_log_viewer();
+        }
+
+        // starts looping event listeners
+        let loop_action_id = glib::timeout_add_local(poll_interval, move || {
+            app.tick(&mut last_autosave);
+            Continue(true)
+        });
+
+        // start GTK main loop
+        info!("Application started");
+        gtk::main(); // blocks until `gtk::main_quit` is called
+
+        // cleanup
+        // this is necessary because `app` was moved into the closure
+        // and it needs to be dropped for its members to be dropped (hence cleaned up)
+        loop_action_id.remove();
+    }
+
+    #[cfg(not(feature = "gui"))]
+    {
+        info!("Application started (headless)");
+        while app.tick(&mut last_autosave) {
+            thread::sleep(poll_interval);
+        }
+    }
+
+    Ok(())
+}
@@ -1,10 +1,15 @@
 //! This module contains code that define the CLI API.
 
-use std::{fs, path::PathBuf};
+#[cfg(feature = "runtime-api")]
+use std::ffi::OsStr;
+use std::{fs, io, path::PathBuf};
 
-use clap::{ArgAction, IntoApp, Parser};
+use clap::{ArgAction, IntoApp, Parser, Subcommand};
+use clap_complete::Shell;
 use shadowsocks_gtk_rs::consts::*;
 
+use crate::{io::json_logger::LogFormat, profile_manager::SwitchStrategy};
+
 #[derive(Debug, Clone, Parser)]
 #[clap(name = "ssgtk", author, version, about, disable_help_subcommand = true)]
 pub struct CliArgs {
@@ -19,16 +24,158 @@ pub struct CliArgs {
     pub app_state_path: PathBuf,
 
     /// Search for a custom image to use for the tray icon.
+    #[cfg(feature = "gui")]
     #[clap(long = "icon-name", value_name = "NAME", default_value = "shadowsocks-gtk-rs")]
     pub tray_icon_filename: String,
 
+    /// Use a specific tray icon while a profile is running.
+    ///
+    /// Defaults to the icon set in the app state, or the `--icon-name` icon.
+    #[cfg(feature = "gui")]
+    #[clap(long = "icon-active", value_name = "NAME")]
+    pub icon_active: Option<String>,
+
+    /// Use a specific tray icon while no profile is running.
+    ///
+    /// Defaults to the icon set in the app state, or `<--icon-name>-stopped`.
+    #[cfg(feature = "gui")]
+    #[clap(long = "icon-inactive", value_name = "NAME")]
+    pub icon_inactive: Option<String>,
+
+    /// Use a specific tray icon after auto-restart has stopped due to an error.
+    ///
+    /// Defaults to the icon set in the app state, or `<--icon-name>-error`.
+    #[cfg(feature = "gui")]
+    #[clap(long = "icon-error", value_name = "NAME")]
+    pub icon_error: Option<String>,
+
     /// Set a custom directory to search for the tray icon.
     ///
     /// Useful for testing (when the icon is not installed in standard
     /// system directories; see https://askubuntu.com/a/43951/1020143).
+    ///
+    /// It is an error if this directory does not exist.
+    #[cfg(feature = "gui")]
     #[clap(long = "icon-theme-dir", value_name = "DIR")]
     pub icon_theme_dir: Option<PathBuf>,
 
+    /// Use tray icons of a specific size, e.g. if the icon is blurry on a HiDPI panel.
+    ///
+    /// Icons are looked up in a `<PX>x<PX>` subdirectory of the icon theme directory.
+    /// Ignored with a warning if there's no such directory, or no icon theme directory is set.
+    #[cfg(feature = "gui")]
+    #[clap(long = "tray-icon-size", value_name = "PX")]
+    pub tray_icon_size: Option<u32>,
+
+    /// Run without a tray icon, e.g. if there's no status notifier host.
+    ///
+    /// The app can then only be controlled via the runtime API (see `ssgtkctl`),
+    /// and reports events via notifications.
+    #[cfg(feature = "gui")]
+    #[clap(long = "no-tray")]
+    pub no_tray: bool,
+
+    /// Run a one-time connectivity self-test on the resumed profile after launch.
+    ///
+    /// Tests both the server (if its address is known) and the local SOCKS5 listener (if any),
+    /// and reports the result via notification and the tray.
+    #[clap(long = "startup-test")]
+    pub startup_test: bool,
+
+    /// Restart the active profile when the system resumes from suspend.
+    ///
+    /// Useful on laptops, where the tunnel is often dead after waking up.
+    #[clap(long = "restart-on-resume")]
+    pub restart_on_resume: bool,
+
+    /// Restart the active profile when the system's network changes.
+    ///
+    /// A change is only acted upon once the new network has been stable for a few seconds,
+    /// and these restarts count towards the auto-restart limit.
+    #[clap(long = "restart-on-network-change")]
+    pub restart_on_network_change: bool,
+
+    /// Reload profiles when their config files are edited.
+    ///
+    /// If the active profile is edited, you are asked whether to restart it with the new config.
+    #[clap(long = "watch-profiles")]
+    pub watch_profiles: bool,
+
+    /// How to replace the running instance when switching profiles.
+    ///
+    /// `make-before-break` minimises downtime when switching between profiles
+    /// that listen on different ports.
+    #[clap(
+        long = "switch-strategy",
+        value_name = "STRATEGY",
+        value_enum,
+        default_value = "break-before-make"
+    )]
+    pub switch_strategy: SwitchStrategy,
+
+    /// Spool the log backlog to a temporary file instead of holding it in memory.
+    ///
+    /// The log viewer then only loads the newest logs, and pages in older ones on demand.
+    /// Useful if ssgtk runs for a long time and `sslocal` logs a lot.
+    #[clap(long = "spool-backlog")]
+    pub spool_backlog: bool,
+
+    /// Cap the in-memory log backlog at this many bytes, dropping the oldest lines beyond it.
+    ///
+    /// Overrides and replaces the value saved in the app state (1 MiB by default); set to 0 for unlimited.
+    /// Has no effect with `--spool-backlog`.
+    #[clap(long = "backlog-limit", value_name = "BYTES")]
+    pub backlog_limit: Option<u64>,
+
+    /// Queue up to this many lines of logs for the log viewer, if it falls behind.
+    ///
+    /// If `sslocal` logs a burst of lines faster than the log viewer can show them,
+    /// the excess lines are left out of the log viewer's live view (but not the backlog).
+    /// A larger value makes this less likely, at the cost of memory.
+    /// Overrides and replaces the value saved in the app state (256 by default).
+    #[clap(
+        long = "log-buffer-lines",
+        value_name = "LINES",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub log_buffer_lines: Option<u64>,
+
+    /// Always start this profile on launch, instead of the one that was running on quit.
+    ///
+    /// Overrides and replaces the value saved in the app state; set to an empty string to unset.
+    /// Ignored with an error if there's no such profile.
+    #[clap(long = "default-profile", value_name = "NAME")]
+    pub default_profile: Option<String>,
+
+    /// Save the app state every this many seconds, if it has changed.
+    ///
+    /// The app state is always saved on quit; set to 0 to only save then.
+    #[clap(long = "autosave-interval", value_name = "SECS", default_value = "60")]
+    pub autosave_interval: u64,
+
+    /// Measure the latency to each profile's server every this many seconds,
+    /// by connecting to it over TCP.
+    ///
+    /// The results are shown in the tray and reported by `ssgtkctl latencies`; set to 0 to disable.
+    #[clap(long = "latency-probe-interval", value_name = "SECS", default_value = "300")]
+    pub latency_probe_interval: u64,
+
+    /// Check for events every this many milliseconds.
+    ///
+    /// Raise this (e.g. to 50) to save power on battery, at the cost of slightly slower responses.
+    #[clap(
+        long = "event-poll-ms",
+        value_name = "MS",
+        default_value = "10",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub event_poll_ms: u64,
+
+    /// Show the active profile and its uptime in the log viewer's title.
+    #[cfg(feature = "gui")]
+    #[clap(long = "log-viewer-uptime")]
+    pub log_viewer_uptime: bool,
+
     /// Increase the verbosity level of output.
     /// This is a repeatable flag.
     #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
@@ -39,12 +186,113 @@ pub struct CliArgs {
     #[clap(short = 'q', long = "quiet", action = ArgAction::Count)]
     pub quiet: u8,
 
+    /// The format of ssgtk's own log output.
+    ///
+    /// `json` writes one object per line, e.g. for a log aggregator.
+    #[clap(long = "log-format", value_name = "FORMAT", value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+
     /// Bind the runtime API listener to a custom socket.
     ///
     /// Useful if you want to control multiple instances.
     #[cfg(feature = "runtime-api")]
-    #[clap(long = "api-socket", value_name = "PATH", default_value_os = RUNTIME_API_SOCKET_PATH_DEFAULT.as_os_str())]
+    #[clap(long = "api-socket", value_name = "PATH", default_value_os = default_api_socket())]
     pub runtime_api_socket_path: PathBuf,
+
+    /// Additionally bind the runtime API listener to a backup socket.
+    ///
+    /// Commands are accepted on either socket. Useful if different tools
+    /// expect the socket at different locations.
+    #[cfg(feature = "runtime-api")]
+    #[clap(long = "api-backup-socket", value_name = "PATH")]
+    pub runtime_api_backup_socket_path: Option<PathBuf>,
+
+    /// Reject `quit` commands sent to the runtime API, unless confirmed.
+    ///
+    /// Useful if the socket is shared, so that a stray `quit` doesn't
+    /// take down the tunnel unexpectedly. Use `ssgtkctl quit --confirm`.
+    #[cfg(feature = "runtime-api")]
+    #[clap(long = "api-require-quit-confirm")]
+    pub runtime_api_require_quit_confirm: bool,
+
+    #[clap(subcommand)]
+    pub sub_cmd: Option<SubCmd>,
+}
+
+/// The default runtime API socket path.
+///
+/// It's only known if there's a runtime directory, so tests use a fixed path instead.
+#[cfg(all(feature = "runtime-api", not(test)))]
+fn default_api_socket() -> &'static OsStr {
+    RUNTIME_API_SOCKET_PATH_DEFAULT.as_os_str()
+}
+#[cfg(all(feature = "runtime-api", test))]
+fn default_api_socket() -> &'static OsStr {
+    OsStr::new("/run/user/0/TEST.sock")
+}
+
+/// Subcommands that perform a one-off task instead of launching the GUI.
+#[derive(Debug, Clone, Subcommand)]
+#[clap(rename_all = "kebab-case")]
+pub enum SubCmd {
+    /// Migrate legacy profiles in the profiles directory to the current format.
+    ///
+    /// Each original `profile.yaml` is backed up as `profile.yaml.bak`.
+    Migrate,
+    /// Export a profile as an equivalent `sslocal` config file, e.g. to run `sslocal` standalone.
+    ///
+    /// The output is JSON5, which `sslocal` accepts. It contains the password in plain text.
+    Export {
+        /// The display name of the profile to export (CASE SENSITIVE).
+        #[clap(index = 1, value_name = "NAME")]
+        profile_name: String,
+        /// Write to a file instead of stdout.
+        #[clap(short = 'o', long = "output", value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Create a "proxy"-type profile from an `ss://` URI, e.g. one shared by a phone app.
+    ///
+    /// The profile is named after the URI's `#tag`, and is written to a new directory
+    /// in the profiles directory. Both SIP002 and legacy base64 URIs are accepted.
+    ImportUri {
+        /// The `ss://` URI to import.
+        #[clap(index = 1, value_name = "URI")]
+        uri: String,
+        /// The local port the new profile listens on.
+        #[clap(long = "local-port", value_name = "PORT", default_value = "1080")]
+        local_port: u16,
+    },
+    /// Print the `ss://` URI of a "proxy"-type profile, e.g. to share it with a phone app.
+    ///
+    /// The URI contains the password in plain text.
+    ExportUri {
+        /// The display name of the profile to export (CASE SENSITIVE).
+        #[clap(index = 1, value_name = "NAME")]
+        profile_name: String,
+    },
+    /// Print the profile hierarchy loaded from the profiles directory.
+    Tree {
+        /// Output as nested JSON, e.g. for building menus in external front-ends.
+        ///
+        /// Passwords and other secrets are never included.
+        #[clap(long = "json")]
+        json: bool,
+    },
+    /// Write a desktop entry to the XDG autostart directory, so that ssgtk starts on login.
+    ///
+    /// The entry launches this executable with the current `--profiles-dir` and `--app-state`.
+    WriteAutostart {
+        /// Overwrite an existing autostart desktop entry.
+        #[clap(long = "force")]
+        force: bool,
+    },
+    /// Print a completion script for a shell to stdout.
+    #[clap(hide = true)]
+    GenerateCompletions {
+        /// The shell to generate the completion script for.
+        #[clap(index = 1, value_name = "SHELL", value_enum)]
+        shell: Shell,
+    },
 }
 
 /// Build a clap app and return matches. Only call once.
@@ -55,6 +303,7 @@ pub fn parse_and_validate() -> CliArgs {
     }
 }
 
+#[allow(unused_mut)] // only mutated with the "gui" feature
 fn validate_impl(mut args: CliArgs) -> Result<CliArgs, clap::Error> {
     // validate profiles_dir
     let profiles_dir = &args.profiles_dir;
@@ -69,11 +318,39 @@ fn validate_impl(mut args: CliArgs) -> Result<CliArgs, clap::Error> {
         // if default, then mkdir if absent
         XDG_DIRS.place_state_file(STATE_FILE_NAME_DEFAULT)?;
     }
+    if app_state_path.is_dir() {
+        Err(CliArgs::command().error(
+            clap::ErrorKind::InvalidValue,
+            format!("app-state path ({:?}) is a directory, expected a file", app_state_path),
+        ))?;
+    }
+    if let Err(err) = fs::File::open(app_state_path) {
+        if err.kind() == io::ErrorKind::PermissionDenied {
+            Err(CliArgs::command().error(
+                clap::ErrorKind::InvalidValue,
+                format!("app-state path ({:?}) is not readable: {}", app_state_path, err),
+            ))?;
+        }
+        // not found is fine; it will be created on quit
+    }
 
     // validate and canonicalize icon_theme_dir
+    #[cfg(feature = "gui")]
     if let Some(theme_dir) = &args.icon_theme_dir {
         // AppIndicator requires an absolute path
-        let abs_dir = theme_dir.canonicalize()?;
+        let abs_dir = theme_dir.canonicalize().map_err(|err| {
+            let msg = match err.kind() {
+                io::ErrorKind::NotFound => format!("icon theme directory does not exist: {:?}", theme_dir),
+                _ => format!("icon theme directory ({:?}) is not accessible: {}", theme_dir, err),
+            };
+            CliArgs::command().error(clap::ErrorKind::InvalidValue, msg)
+        })?;
+        if !abs_dir.is_dir() {
+            Err(CliArgs::command().error(
+                clap::ErrorKind::InvalidValue,
+                format!("icon theme directory ({:?}) is not a directory", theme_dir),
+            ))?;
+        }
         if abs_dir.to_str().is_none() {
             Err(CliArgs::command().error(
                 clap::ErrorKind::InvalidUtf8,
@@ -95,3 +372,46 @@ fn validate_impl(mut args: CliArgs) -> Result<CliArgs, clap::Error> {
 
     Ok(args)
 }
+
+#[cfg(test)]
+mod test {
+    use clap::IntoApp;
+    use clap_complete::Shell;
+
+    use super::CliArgs;
+
+    #[test]
+    fn completions_cover_all_subcommands() {
+        let cmd = CliArgs::command();
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let mut script = vec![];
+            clap_complete::generate(shell, &mut cmd.clone(), "ssgtk", &mut script);
+            let script = String::from_utf8(script).unwrap();
+            let subs: Vec<_> = cmd.get_subcommands().filter(|sub| !sub.is_hide_set()).collect();
+            for sub in &subs {
+                assert!(
+                    script.contains(sub.get_name()),
+                    "{} script is missing {:?}",
+                    shell,
+                    sub.get_name()
+                );
+            }
+            for sub in std::iter::once(&cmd).chain(subs) {
+                let values = sub
+                    .get_arguments()
+                    .filter(|arg| !arg.is_hide_set())
+                    .filter_map(|arg| arg.get_value_parser().possible_values())
+                    .flatten();
+                for value in values {
+                    assert!(
+                        script.contains(value.get_name()),
+                        "{} script is missing value {:?} of {:?}",
+                        shell,
+                        value.get_name(),
+                        sub.get_name()
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,20 +1,108 @@
 //! This module defines events passed between core and GUI elements.
 
-use shadowsocks_gtk_rs::notify_method::NotifyMethod;
+use std::path::PathBuf;
 
-use crate::io::profile_loader::Profile;
+#[cfg(feature = "gui")]
+use shadowsocks_gtk_rs::{log_level::LogLevel, notify_method::NotifyMethod};
+
+use crate::io::{profile_loader::Profile, sslocal_error::SslocalErrorKind};
 
 #[derive(Debug, Clone)]
 pub enum AppEvent {
     // from GUI
+    #[cfg(feature = "gui")]
     LogViewerShow,
+    #[cfg(feature = "gui")]
     LogViewerHide,
+    #[cfg(feature = "gui")]
     SwitchProfile(Profile),
+    /// Start a profile alongside the active one.
+    #[cfg(feature = "gui")]
+    StartConcurrent(Profile),
+    /// Stop a profile running alongside the active one, by name.
+    #[cfg(feature = "gui")]
+    StopConcurrent(String),
+    Restart, // also sent by the resume detector
+    #[cfg(feature = "gui")]
     ManualStop,
+    #[cfg(feature = "gui")]
+    Toggle,
+    #[cfg(feature = "gui")]
+    OpenProfileLink,
+    #[cfg(feature = "gui")]
     SetNotify(NotifyMethod),
-    Quit,
+    #[cfg(feature = "gui")]
+    SetLogLevel(LogLevel),
+    Quit, // also sent by the signal handler
+    /// Quit from the tray, which may need confirmation.
+    #[cfg(feature = "gui")]
+    ManualQuit,
 
     // from core
-    OkStop { instance_name: Option<String> },
-    ErrorStop { instance_name: Option<String>, err: String },
+    OkStop {
+        instance_name: Option<String>,
+    },
+    ErrorStop {
+        instance_name: Option<String>,
+        err: String,
+        /// The recognised cause of the failure, if any.
+        kind: Option<SslocalErrorKind>,
+    },
+    /// An instance running alongside the active one has stopped, and will not be restarted.
+    ConcurrentStop {
+        profile_name: String,
+        /// `None` if it has stopped cleanly.
+        err: Option<String>,
+        /// The recognised cause of the failure, if any.
+        kind: Option<SslocalErrorKind>,
+    },
+    /// An instance has failed, and has been restarted successfully.
+    RestartOk {
+        instance_name: String,
+        /// The number of restarts within the auto-restart window, including this one.
+        attempt: usize,
+    },
+    RestartBudgetLow {
+        profile_name: String,
+        remaining: usize,
+    },
+    SelfTestResult {
+        profile_name: String,
+        res: Result<String, String>,
+    },
+    /// The resumed profile has finished waiting for its startup delay and network.
+    StartupWaitDone {
+        profile: Profile,
+        /// The profile manager's switch generation when waiting started; see `ResumeRetry`.
+        generation: u64,
+    },
+    /// It's time to retry resuming a profile that has failed to start on launch.
+    ResumeRetry {
+        profile: Profile,
+        /// Starts from 1.
+        attempt: u32,
+        /// The profile manager's switch generation when the retry was scheduled;
+        /// if it has changed since, the user has switched or stopped manually, and the retry is stale.
+        generation: u64,
+    },
+    /// A deferred start can proceed, because the instances it was waiting for have exited,
+    /// and its profile's `pre_up` hook has run.
+    StartReady {
+        id: u64,
+        /// Whether the `pre_up` hook has succeeded; `Ok` if there is none.
+        pre_up: Result<(), String>,
+    },
+    /// The system's network has changed and settled, e.g. after switching to a different Wi-Fi network.
+    NetworkChanged,
+    /// Profile config files have been created, edited, or removed.
+    ProfileFilesChanged {
+        paths: Vec<PathBuf>,
+    },
+    /// A round of probing the latency to each profile's server has completed.
+    LatenciesProbed,
+    /// The connectivity probe after starting a profile has failed.
+    ProbeFailed {
+        profile_name: String,
+        err: String,
+    },
 }
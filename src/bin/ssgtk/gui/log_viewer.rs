@@ -1,17 +1,75 @@
 //! This module contains code that creates a window for showing
 //! the logs emitted by `sslocal`.
 
-use std::{rc::Rc, sync::mpsc::TryRecvError, time::Duration};
+use std::{
+    cell::{Cell, RefCell},
+    fs,
+    rc::Rc,
+    sync::{mpsc::TryRecvError, Arc, Mutex},
+    time::Duration,
+};
 
 use bus::BusReader;
 use crossbeam_channel::Sender;
 use glib::SourceId;
 use gtk::{
-    prelude::*, ApplicationWindow, CheckButton, Frame, Grid, PolicyType, ScrolledWindow, TextBuffer, TextView, WrapMode,
+    gdk, prelude::*, ApplicationWindow, Button, CheckButton, Clipboard, Entry, EntryIconPosition, FileChooserAction,
+    FileChooserDialog, Frame, Grid, MessageType, PolicyType, ResponseType, ScrolledWindow, TextBuffer, TextTag,
+    TextView, WrapMode,
 };
-use log::{error, trace};
+use log::{debug, error, info, trace, warn};
+use regex::Regex;
+use shadowsocks_gtk_rs::util::{format_duration_short, mutex_lock, OutputKind};
+
+use crate::{event::AppEvent, io::backlog::Backlog, notification::notify_nonblocking_prompt};
+
+/// When the backlog is spooled, how many bytes to load at a time.
+const PAGE_SIZE: u64 = 64 * 1024;
+
+/// When the backlog is spooled, older lines are unloaded from the viewer beyond this count.
+const MAX_LOADED_LINES: i32 = 5000;
+
+/// The name of the tag applied to lines matching the search.
+const SEARCH_MATCH_TAG: &str = "search-match";
+
+/// Determine which stream a log line came from, by its `[stdout]` or `[stderr]` prefix.
+fn line_stream(line: &str) -> Option<OutputKind> {
+    [OutputKind::Stdout, OutputKind::Stderr]
+        .into_iter()
+        .find(|kind| line.starts_with(&format!("[{}] ", kind)))
+}
+
+/// Matches log lines against the search box's content.
+#[derive(Debug)]
+enum LineMatcher {
+    Substring(String),
+    Regex(Regex),
+}
 
-use crate::event::AppEvent;
+impl LineMatcher {
+    /// Build a matcher from the search box's content.
+    ///
+    /// Returns `Ok(None)` if the search is empty, and the error message if the regex is invalid.
+    fn new(query: &str, use_regex: bool) -> Result<Option<Self>, String> {
+        match (query, use_regex) {
+            ("", _) => Ok(None),
+            (q, false) => Ok(Some(Self::Substring(q.into()))),
+            (q, true) => Regex::new(q)
+                .map(|re| Some(Self::Regex(re)))
+                .map_err(|err| err.to_string()),
+        }
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        match self {
+            Self::Substring(s) => line.contains(s.as_str()),
+            Self::Regex(re) => re.is_match(line),
+        }
+    }
+}
+
+/// A function that returns the name and uptime of the active profile, if any.
+pub type StatusFn = Box<dyn Fn() -> Option<(String, Duration)>>;
 
 #[derive(Debug)]
 pub struct LogViewerWindow {
@@ -35,7 +93,16 @@ impl Drop for LogViewerWindow {
 
 impl LogViewerWindow {
     /// Create a new `LogViewerWindow`, fill with existing backlog, and set up piping for new logs.
-    pub fn new(events_tx: Sender<AppEvent>, backlog: impl AsRef<str>, mut log_listener: BusReader<String>) -> Self {
+    ///
+    /// If the backlog is spooled, only its tail is loaded, and older content is paged in on demand.
+    ///
+    /// If `status_fn` is set, the window title periodically shows the active profile and its uptime.
+    pub fn new(
+        events_tx: Sender<AppEvent>,
+        backlog: Arc<Mutex<Backlog>>,
+        mut log_listener: BusReader<String>,
+        status_fn: Option<StatusFn>,
+    ) -> Self {
         // compose window
         let text_view = TextView::builder()
             .cursor_visible(false)
@@ -59,16 +126,53 @@ impl LogViewerWindow {
             .margin(12)
             .margin_bottom(0)
             .build();
+        let search_entry = Entry::builder()
+            .hexpand(true)
+            .margin(12)
+            .margin_bottom(0)
+            .placeholder_text("Highlight lines containing...")
+            .build();
+        let regex_checkbox = CheckButton::builder().label("Regex").build();
+        let show_stdout_checkbox = CheckButton::builder().active(true).label("stdout").build();
+        let show_stderr_checkbox = CheckButton::builder().active(true).label("stderr").build();
+        let search_options = {
+            let hbox = gtk::Box::builder().margin(12).margin_bottom(0).spacing(12).build();
+            hbox.add(&regex_checkbox);
+            hbox.add(&show_stdout_checkbox);
+            hbox.add(&show_stderr_checkbox);
+            hbox
+        };
         let scroll_checkbox = CheckButton::builder()
             .active(true)
             .hexpand(true)
             .label("Auto-scroll to the newest logs")
             .margin(12)
             .build();
+        let spooled = mutex_lock(&backlog).is_spooled();
+        let load_older_button = Button::builder()
+            .label("Load older logs")
+            .margin(12)
+            .sensitive(false)
+            .build();
+        let copy_button = Button::builder().label("Copy to clipboard").margin(12).build();
+        let save_button = Button::builder().label("Save to file").margin(12).build();
+        // older logs are only on disk if written to log files
+        let log_dir = mutex_lock(&backlog).log_file().map(|f| f.config().dir.clone());
+        let open_log_file_button = Button::builder().label("Open log file...").margin(12).build();
         let grid = {
             let grid = Grid::new();
-            grid.attach(&frame, 0, 0, 1, 1);
-            grid.attach(&scroll_checkbox, 0, 1, 1, 1);
+            grid.attach(&search_entry, 0, 0, 3, 1);
+            grid.attach(&search_options, 3, 0, 1, 1);
+            grid.attach(&frame, 0, 1, 4, 1);
+            grid.attach(&scroll_checkbox, 0, 2, 1, 1);
+            if spooled {
+                grid.attach(&load_older_button, 1, 2, 1, 1);
+            }
+            grid.attach(&copy_button, 2, 2, 1, 1);
+            grid.attach(&save_button, 3, 2, 1, 1);
+            if log_dir.is_some() {
+                grid.attach(&open_log_file_button, 3, 3, 1, 1);
+            }
             grid
         };
         let window = ApplicationWindow::builder()
@@ -87,21 +191,185 @@ impl LogViewerWindow {
         };
 
         // insert backlog
-        ret.buffer.place_cursor(&ret.buffer.end_iter());
-        ret.buffer.insert_at_cursor(backlog.as_ref());
+        // the byte offset in the backlog of the first loaded line
+        let loaded_from = {
+            let backlog = mutex_lock(&backlog);
+            let max_bytes = if spooled { PAGE_SIZE } else { u64::MAX };
+            let (start, page) = backlog.read_page(backlog.len(), max_bytes).unwrap_or_else(|err| {
+                warn!("Failed to read backlog: {}", err);
+                (backlog.len(), String::new())
+            });
+            ret.buffer.place_cursor(&ret.buffer.end_iter());
+            ret.buffer.insert_at_cursor(&page);
+            Rc::new(Cell::new(start))
+        };
+        load_older_button.set_sensitive(loaded_from.get() > 0);
 
-        // pipe incoming new logs
+        // distinguish stdout & stderr, and allow hiding either
+        let tag_table = ret.buffer.tag_table().unwrap(); // `TextBuffer` always has a tag table
+        for (kind, checkbox) in [
+            (OutputKind::Stdout, &show_stdout_checkbox),
+            (OutputKind::Stderr, &show_stderr_checkbox),
+        ] {
+            let tag = TextTag::builder().name(&kind.to_string()).build();
+            if kind == OutputKind::Stderr {
+                tag.set_foreground(Some("#cc0000"));
+            }
+            tag_table.add(&tag);
+            checkbox.connect_toggled(move |checkbox| tag.set_invisible(!checkbox.is_active()));
+        }
+        tag_lines(&ret.buffer, None, 0);
+
+        // highlight lines matching the search
+        let match_tag = TextTag::builder().name(SEARCH_MATCH_TAG).background("#fce94f").build();
+        tag_table.add(&match_tag);
+        let matcher: Rc<RefCell<Option<LineMatcher>>> = Rc::new(RefCell::new(None));
+        let update_search = {
+            let buffer = Rc::clone(&ret.buffer);
+            let matcher = Rc::clone(&matcher);
+            let search_entry = search_entry.clone();
+            let regex_checkbox = regex_checkbox.clone();
+            move || {
+                let query = search_entry.text();
+                match LineMatcher::new(query.as_str(), regex_checkbox.is_active()) {
+                    Ok(m) => {
+                        search_entry.set_icon_from_icon_name(EntryIconPosition::Secondary, None);
+                        matcher.replace(m);
+                    }
+                    Err(err) => {
+                        debug!("Invalid search regex {:?}: {}", query, err);
+                        search_entry.set_icon_from_icon_name(EntryIconPosition::Secondary, Some("dialog-error"));
+                        search_entry.set_icon_tooltip_text(EntryIconPosition::Secondary, Some(&err));
+                        matcher.replace(None);
+                    }
+                }
+                tag_lines(&buffer, matcher.borrow().as_ref(), 0);
+            }
+        };
+        let update_search = Rc::new(update_search);
+        let update_search_mv = Rc::clone(&update_search);
+        search_entry.connect_changed(move |_| update_search_mv());
+        regex_checkbox.connect_toggled(move |_| update_search());
+
+        // page in older logs on demand
         let buffer = Rc::clone(&ret.buffer);
-        let id = glib::source::timeout_add_local(Duration::from_millis(100), move || match log_listener.try_recv() {
-            Ok(s) => {
-                buffer.place_cursor(&buffer.end_iter());
-                buffer.insert_at_cursor(&s);
-                Continue(true)
+        let loaded_from_mv = Rc::clone(&loaded_from);
+        let matcher_mv = Rc::clone(&matcher);
+        load_older_button.connect_clicked(move |button| {
+            match mutex_lock(&backlog).read_page(loaded_from_mv.get(), PAGE_SIZE) {
+                Ok((start, page)) => {
+                    buffer.insert(&mut buffer.start_iter(), &page);
+                    loaded_from_mv.set(start);
+                    tag_lines(&buffer, matcher_mv.borrow().as_ref(), 0);
+                }
+                Err(err) => warn!("Failed to read backlog: {}", err),
             }
-            Err(TryRecvError::Empty) => Continue(true),
-            Err(TryRecvError::Disconnected) => {
-                error!("Profile manager's logs broadcast has been dropped unexpectedly!");
-                Continue(false)
+            button.set_sensitive(loaded_from_mv.get() > 0);
+        });
+
+        // copy loaded logs to clipboard
+        let buffer = Rc::clone(&ret.buffer);
+        copy_button.connect_clicked(move |_| {
+            let text = buffer_text(&buffer);
+            Clipboard::get(&gdk::SELECTION_CLIPBOARD).set_text(&text);
+            info!("Copied {} bytes of logs to clipboard", text.len());
+        });
+
+        // save loaded logs to file
+        let buffer = Rc::clone(&ret.buffer);
+        let window = ret.window.clone();
+        save_button.connect_clicked(move |_| {
+            let dialog = FileChooserDialog::with_buttons(
+                Some("Save Logs"),
+                Some(&window),
+                FileChooserAction::Save,
+                &[("_Cancel", ResponseType::Cancel), ("_Save", ResponseType::Accept)],
+            );
+            dialog.set_current_name("sslocal.log");
+            dialog.set_do_overwrite_confirmation(true);
+            let buffer = Rc::clone(&buffer);
+            dialog.connect_response(move |dialog, res| {
+                if let (ResponseType::Accept, Some(path)) = (res, dialog.filename()) {
+                    match fs::write(&path, buffer_text(&buffer)) {
+                        Ok(_) => info!("Saved logs to {:?}", path),
+                        Err(err) => {
+                            warn!("Failed to save logs to {:?}: {}", path, err);
+                            let text_2 = format!("Cannot write to {:?}: {}", path, err);
+                            notify_nonblocking_prompt(MessageType::Error, "Failed to save logs", &text_2);
+                        }
+                    }
+                }
+                dialog.close();
+            });
+            dialog.show();
+        });
+
+        // open a (possibly rotated) log file in the default application
+        if let Some(log_dir) = log_dir {
+            let window = ret.window.clone();
+            open_log_file_button.connect_clicked(move |_| {
+                let dialog = FileChooserDialog::with_buttons(
+                    Some("Open Log File"),
+                    Some(&window),
+                    FileChooserAction::Open,
+                    &[("_Cancel", ResponseType::Cancel), ("_Open", ResponseType::Accept)],
+                );
+                if let Err(err) = dialog.set_current_folder(&log_dir) {
+                    warn!("Failed to show log directory {:?}: {}", log_dir, err);
+                }
+                let window = window.clone();
+                dialog.connect_response(move |dialog, res| {
+                    if let (ResponseType::Accept, Some(path)) = (res, dialog.filename()) {
+                        let open_res = glib::filename_to_uri(&path, None)
+                            .and_then(|uri| gtk::show_uri_on_window(Some(&window), &uri, gtk::current_event_time()));
+                        match open_res {
+                            Ok(_) => info!("Opened log file {:?}", path),
+                            Err(err) => {
+                                warn!("Failed to open log file {:?}: {}", path, err);
+                                let text_2 = format!("Cannot open {:?}: {}", path, err);
+                                notify_nonblocking_prompt(MessageType::Error, "Failed to open log file", &text_2);
+                            }
+                        }
+                    }
+                    dialog.close();
+                });
+                dialog.show();
+            });
+        }
+
+        // pipe incoming new logs
+        // drain everything available on each tick, so that we don't fall behind
+        // and cause the broadcasting end to block or drop lines
+        let buffer = Rc::clone(&ret.buffer);
+        // the first line that may have changed since the last tick
+        let mut dirty_from = None;
+        let id = glib::source::timeout_add_local(Duration::from_millis(100), move || loop {
+            match log_listener.try_recv() {
+                Ok(s) => {
+                    // the last line may be incomplete, so it's rechecked too
+                    dirty_from.get_or_insert(buffer.line_count() - 1);
+                    buffer.place_cursor(&buffer.end_iter());
+                    buffer.insert_at_cursor(&s);
+                }
+                Err(TryRecvError::Empty) => {
+                    if let Some(from) = dirty_from.take() {
+                        tag_lines(&buffer, matcher.borrow().as_ref(), from);
+                    }
+                    // unload older lines; they can be paged back in
+                    let excess = buffer.line_count() - MAX_LOADED_LINES;
+                    if spooled && excess > 0 {
+                        let (mut start, mut end) = (buffer.start_iter(), buffer.iter_at_line(excess));
+                        let unloaded = buffer.text(&start, &end, true).map_or(0, |s| s.len());
+                        buffer.delete(&mut start, &mut end);
+                        loaded_from.set(loaded_from.get() + unloaded as u64);
+                        load_older_button.set_sensitive(true);
+                    }
+                    break Continue(true);
+                }
+                Err(TryRecvError::Disconnected) => {
+                    error!("Profile manager's logs broadcast has been dropped unexpectedly!");
+                    break Continue(false);
+                }
             }
         });
         ret.scheduled_fn_ids.push(id);
@@ -121,6 +389,24 @@ impl LogViewerWindow {
         );
         ret.scheduled_fn_ids.push(id);
 
+        // update title with status
+        if let Some(status_fn) = status_fn {
+            let window = ret.window.clone();
+            let update_title = move || {
+                let title = match status_fn() {
+                    Some((name, uptime)) => format!("Log Viewer — {} (up {})", name, format_duration_short(uptime)),
+                    None => "Log Viewer — Inactive".into(),
+                };
+                window.set_title(&title);
+            };
+            update_title();
+            let id = glib::source::timeout_add_local(Duration::from_secs(1), move || {
+                update_title();
+                Continue(true)
+            });
+            ret.scheduled_fn_ids.push(id);
+        }
+
         // send event on window destroy
         ret.window.connect_destroy(move |_| {
             if let Err(_) = events_tx.send(AppEvent::LogViewerHide) {
@@ -143,20 +429,75 @@ impl LogViewerWindow {
     }
 }
 
+/// Tag lines by the stream they came from, and highlight the lines matching the search,
+/// starting from a line number.
+///
+/// Lines before `from_line` are left as they are.
+fn tag_lines(buffer: &TextBuffer, matcher: Option<&LineMatcher>, from_line: i32) {
+    let from_line = from_line.max(0);
+    buffer.remove_tag_by_name(SEARCH_MATCH_TAG, &buffer.iter_at_line(from_line), &buffer.end_iter());
+    for line in from_line..buffer.line_count() {
+        let start = buffer.iter_at_line(line);
+        let mut end = start;
+        // include the newline, so that hidden lines don't leave blank lines behind
+        end.forward_line();
+        let text = match buffer.text(&start, &end, true) {
+            Some(text) => text,
+            None => continue,
+        };
+        if let Some(kind) = line_stream(&text) {
+            buffer.apply_tag_by_name(&kind.to_string(), &start, &end);
+        }
+        if matches!(matcher, Some(m) if m.is_match(&text)) {
+            buffer.apply_tag_by_name(SEARCH_MATCH_TAG, &start, &end);
+        }
+    }
+}
+
+/// Get the entire content of a `TextBuffer`.
+fn buffer_text(buffer: &TextBuffer) -> String {
+    buffer
+        .text(&buffer.start_iter(), &buffer.end_iter(), true)
+        .map_or_else(String::new, String::from)
+}
+
 #[cfg(test)]
 mod test {
+    use std::sync::{Arc, Mutex};
+
     use bus::Bus;
     use crossbeam_channel::unbounded as unbounded_channel;
-    use shadowsocks_gtk_rs::consts::*;
+    use shadowsocks_gtk_rs::{consts::*, util::OutputKind};
 
-    use super::LogViewerWindow;
+    use super::{line_stream, LineMatcher, LogViewerWindow};
+    use crate::io::backlog::Backlog;
 
+    #[test]
+    fn line_matcher() {
+        assert!(LineMatcher::new("", true).unwrap().is_none());
+        let substring = LineMatcher::new("ERROR", false).unwrap().unwrap();
+        assert!(substring.is_match("2022-08-01 ERROR: failed"));
+        assert!(!substring.is_match("2022-08-01 INFO: listening"));
+        let regex = LineMatcher::new(r"^\d+ (WARN|ERROR)", true).unwrap().unwrap();
+        assert!(regex.is_match("12 WARN something"));
+        assert!(!regex.is_match("WARN 12 something"));
+        assert!(LineMatcher::new("(unclosed", true).is_err());
+    }
+    #[test]
+    fn line_stream_prefix() {
+        assert_eq!(line_stream("[stdout] listening on 1080\n"), Some(OutputKind::Stdout));
+        assert_eq!(line_stream("[stderr] failed\n"), Some(OutputKind::Stderr));
+        assert_eq!(line_stream("stderr without prefix"), None);
+    }
     #[test]
     fn show_default_window_with_backlog() {
         gtk::init().unwrap();
         let log_listener = Bus::new(BUS_BUFFER_SIZE).add_rx();
         let (events_tx, _) = unbounded_channel();
-        LogViewerWindow::new(events_tx, "Mock backlog", log_listener).show();
+        let mut backlog = Backlog::default();
+        backlog.push_str("Mock backlog").unwrap();
+        let backlog = Arc::new(Mutex::new(backlog));
+        LogViewerWindow::new(events_tx, backlog, log_listener, None).show();
         gtk::main();
     }
 }
@@ -1,9 +1,7 @@
 //! This module contains code relating to GUI.
 
 // public members
-pub mod app;
 pub mod log_viewer;
-pub mod notification;
 pub mod tray;
 
 // private members with re-export
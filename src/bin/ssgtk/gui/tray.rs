@@ -1,15 +1,30 @@
 //! This module contains code that creates a tray item.
 
-use std::{path::Path, rc::Rc, sync::RwLock};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::RwLock,
+    time::UNIX_EPOCH,
+};
 
 use crossbeam_channel::Sender;
 use derivative::Derivative;
-use gtk::{prelude::*, Menu, MenuItem, RadioMenuItem, SeparatorMenuItem};
+use enum_iterator::Sequence;
+use gtk::{prelude::*, CheckMenuItem, Menu, MenuItem, RadioMenuItem, SeparatorMenuItem};
 use libappindicator::{AppIndicator, AppIndicatorStatus};
 use log::{debug, error, warn};
-use shadowsocks_gtk_rs::{consts::*, notify_method::NotifyMethod, util};
+use shadowsocks_gtk_rs::{consts::*, log_level::LogLevel, notify_method::NotifyMethod, util};
 
-use crate::{event::AppEvent, io::profile_loader::ProfileFolder};
+use crate::{
+    event::AppEvent,
+    io::{
+        app_state::{ProfileHistory, TrayIconSet},
+        latency_prober::Latencies,
+        profile_loader::ProfileFolder,
+    },
+    profile_manager::InstanceStats,
+};
 
 /// A `RadioMenuItem` with its listen enable flag.
 ///
@@ -20,6 +35,18 @@ use crate::{event::AppEvent, io::profile_loader::ProfileFolder};
 /// from emitting an extraneous event when we programmatically set it to active.
 type ListeningRadioMenuItem = (RadioMenuItem, Rc<RwLock<bool>>);
 
+/// A `CheckMenuItem` with its listen enable flag; see `ListeningRadioMenuItem`.
+type ListeningCheckMenuItem = (CheckMenuItem, Rc<RwLock<bool>>);
+
+/// The application states that have a distinct tray icon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconState {
+    Active,
+    Stopped,
+    Error,
+    Connecting,
+}
+
 #[derive(Debug, Clone)]
 enum ProfileMenuItem {
     Profile(ListeningRadioMenuItem),
@@ -31,13 +58,39 @@ enum ProfileMenuItem {
 pub struct TrayItem {
     #[derivative(Debug(format_with = "shadowsocks_gtk_rs::util::hacks::omit_ai"))]
     ai: AppIndicator,
+    /// The icons for each state, as requested.
+    requested_icons: TrayIconSet,
+    /// The icons for each state, with missing variants already replaced by the active icon.
+    icons: TrayIconSet,
+    /// The directory icons are searched for in besides the system icon theme, adjusted for `icon_size`.
+    icon_theme_dir: Option<PathBuf>,
+    /// The size of icons to look up in `icon_theme_dir`, if set.
+    icon_size: Option<u32>,
+    /// The state the current icon is shown for, so that it can be reapplied on reload.
+    icon_state: IconState,
     menu: Menu,
     /// The `ListeningRadioMenuItem` for the stop button.
     manual_stop_item: ListeningRadioMenuItem,
     /// The `ListeningRadioMenuItem`s for the list of profiles.
     profile_items: Vec<ListeningRadioMenuItem>,
+    /// The top-level menu entries of the profile tree, i.e. profiles and groups.
+    profile_entries: Vec<MenuItem>,
+    /// The position in the menu of the first profile tree entry.
+    profile_entries_pos: i32,
+    /// The "Recent" submenu's parent, whose submenu is replaced when the profile history changes.
+    recent_item: MenuItem,
+    /// The "Run Alongside" submenu's parent, whose submenu is replaced when profiles are reloaded.
+    concurrent_item: MenuItem,
+    /// The `ListeningCheckMenuItem`s for running profiles alongside the active one.
+    concurrent_items: Vec<ListeningCheckMenuItem>,
+    /// A non-clickable label that is only shown while auto-restart is paused.
+    auto_restart_paused_item: MenuItem,
+    /// A non-clickable label showing the active instance's uptime and traffic; hidden if inactive.
+    stats_item: MenuItem,
     /// The `ListeningRadioMenuItem`s for the list of notify methods.
     notify_method_items: Vec<ListeningRadioMenuItem>,
+    /// The `ListeningRadioMenuItem`s for the list of log levels.
+    log_level_items: Vec<ListeningRadioMenuItem>,
 }
 
 impl TrayItem {
@@ -45,11 +98,13 @@ impl TrayItem {
     ///
     /// Should only be called once.
     pub fn build_and_show(
-        icon_name: &str,
+        icons: &TrayIconSet,
         icon_theme_dir: Option<impl AsRef<Path>>,
+        icon_size: Option<u32>,
         events_tx: Sender<AppEvent>,
         profile_folder: &ProfileFolder,
         notify_method: NotifyMethod,
+        log_level: LogLevel,
     ) -> Self {
         // create stop button up top because `TrayItem` has a mandatory field
         let manual_stop_item = {
@@ -68,38 +123,77 @@ impl TrayItem {
         };
 
         // create tray with icon
+        let icon_theme_dir = effective_icon_theme_dir(icon_theme_dir.as_ref().map(AsRef::as_ref), icon_size);
         let mut tray = Self {
-            ai: match icon_theme_dir {
+            ai: match icon_theme_dir.as_deref().and_then(Path::to_str) {
                 // BUG: For some reason the title is not set?
-                Some(dir) => {
-                    let dir_str = dir.as_ref().to_str().unwrap(); // UTF-8 guaranteed by clap validator.
-                    AppIndicator::with_path(APP_NAME, icon_name, dir_str)
-                }
-                None => AppIndicator::new(APP_NAME, icon_name),
+                Some(dir_str) => AppIndicator::with_path(APP_NAME, &icons.active, dir_str),
+                None => AppIndicator::new(APP_NAME, &icons.active),
             },
+            requested_icons: icons.clone(),
+            icons: resolve_icons(icons, icon_theme_dir.as_deref()),
+            icon_theme_dir,
+            icon_size,
+            icon_state: IconState::Active,
             menu: Menu::new(),
             manual_stop_item,
-            profile_items: vec![],       // will be populated when adding dynamic profiles
+            profile_items: vec![],   // will be populated when adding dynamic profiles
+            profile_entries: vec![], // ditto
+            profile_entries_pos: 0,  // ditto
+            recent_item: MenuItem::with_label("Recent"),
+            concurrent_item: MenuItem::with_label("Run Alongside"),
+            concurrent_items: vec![], // will be populated when adding dynamic profiles
+            auto_restart_paused_item: MenuItem::with_label("Auto-restart Paused"),
+            stats_item: MenuItem::with_label(""),
             notify_method_items: vec![], // will be replaced when adding the selector
+            log_level_items: vec![],     // will be replaced when adding the selector
         };
         tray.ai.set_status(AppIndicatorStatus::Active);
 
         // add dynamic profiles
         tray.add_label("Profiles");
         tray.add_separator();
+        tray.profile_entries_pos = tray.menu.children().len() as i32;
         tray.load_profiles(profile_folder, events_tx.clone());
+        tray.recent_item.set_sensitive(false); // until the history is set
+        tray.menu.append(&tray.recent_item);
+        tray.menu.append(&tray.concurrent_item);
         tray.add_separator();
 
         // add stop button (previously created)
         tray.menu.append(&tray.manual_stop_item.0);
+        let toggle_tx = events_tx.clone();
+        tray.add_menu_item("Toggle sslocal", move || {
+            if let Err(_) = toggle_tx.send(AppEvent::Toggle) {
+                error!("Trying to send Toggle event, but all receivers have hung up.");
+            }
+        });
+        tray.auto_restart_paused_item.set_sensitive(false);
+        tray.auto_restart_paused_item.set_no_show_all(true); // hidden until paused
+        tray.menu.append(&tray.auto_restart_paused_item);
+        tray.stats_item.set_sensitive(false);
+        tray.stats_item.set_no_show_all(true); // hidden until active
+        tray.menu.append(&tray.stats_item);
 
         // add notify method selector
         let (notify_selector_item, notify_method_items) =
-            generate_notify_method_selector(notify_method, events_tx.clone());
+            generate_enum_selector("Notifications", notify_method, AppEvent::SetNotify, events_tx.clone());
         tray.notify_method_items = notify_method_items;
         tray.menu.append(&notify_selector_item);
 
+        // add log level selector
+        let (log_level_selector_item, log_level_items) =
+            generate_enum_selector("Log Level", log_level, AppEvent::SetLogLevel, events_tx.clone());
+        tray.log_level_items = log_level_items;
+        tray.menu.append(&log_level_selector_item);
+
         // add other static menu entries
+        let open_link_tx = events_tx.clone();
+        tray.add_menu_item("Open Profile Link", move || {
+            if let Err(_) = open_link_tx.send(AppEvent::OpenProfileLink) {
+                error!("Trying to send OpenProfileLink event, but all receivers have hung up.");
+            }
+        });
         let log_viewer_tx = events_tx.clone();
         tray.add_menu_item("Show sslocal Output", move || {
             if let Err(_) = log_viewer_tx.send(AppEvent::LogViewerShow) {
@@ -108,8 +202,8 @@ impl TrayItem {
         });
         let quit_tx = events_tx.clone();
         tray.add_menu_item("Quit", move || {
-            if let Err(_) = quit_tx.send(AppEvent::Quit) {
-                error!("Trying to send Quit event, but all receivers have hung up.");
+            if let Err(_) = quit_tx.send(AppEvent::ManualQuit) {
+                error!("Trying to send ManualQuit event, but all receivers have hung up.");
             }
         });
 
@@ -118,6 +212,68 @@ impl TrayItem {
         tray
     }
 
+    /// Set the tray icon to the variant for the specified state.
+    pub fn set_icon_state(&mut self, state: IconState) {
+        let name = match state {
+            IconState::Active => &self.icons.active,
+            IconState::Stopped => &self.icons.stopped,
+            IconState::Error => &self.icons.error,
+            IconState::Connecting => &self.icons.connecting,
+        };
+        debug!("Setting tray icon to \"{}\" for state {:?}", name, state);
+        self.ai.set_icon_full(name, &format!("{:?}", state));
+        self.icon_state = state;
+    }
+
+    /// Look up the tray icons again, e.g. after a new icon theme has been installed.
+    #[cfg(feature = "runtime-api")]
+    pub fn reload_icon(&mut self) {
+        debug!("Reloading tray icons");
+        if let Some(theme) = gtk::IconTheme::default() {
+            theme.rescan_if_needed();
+        }
+        if let Some(dir_str) = self.icon_theme_dir.as_deref().and_then(Path::to_str) {
+            self.ai.set_icon_theme_path(dir_str);
+        }
+        self.icons = resolve_icons(&self.requested_icons, self.icon_theme_dir.as_deref());
+        self.set_icon_state(self.icon_state);
+    }
+
+    /// Use a new base icon, with variants derived as with `--icon-name`,
+    /// optionally searched for in a new icon theme directory.
+    ///
+    /// Leaves the current icon unchanged if the new icon cannot be found.
+    #[cfg(feature = "runtime-api")]
+    pub fn set_icon(&mut self, name: &str, theme_dir: Option<&Path>) -> Result<(), String> {
+        if let Some(theme) = gtk::IconTheme::default() {
+            theme.rescan_if_needed();
+        }
+        let icon_theme_dir = match theme_dir {
+            Some(dir) if !dir.is_dir() => {
+                let msg = format!("icon theme directory {:?} is not a directory", dir);
+                warn!("Cannot set tray icon: {}; keeping the current icon", msg);
+                return Err(msg);
+            }
+            Some(dir) => effective_icon_theme_dir(Some(dir), self.icon_size),
+            None => self.icon_theme_dir.clone(),
+        };
+        if !icon_exists(name, icon_theme_dir.as_deref()) {
+            let msg = format!("cannot find an icon named \"{}\"", name);
+            warn!("Cannot set tray icon: {}; keeping the current icon", msg);
+            return Err(msg);
+        }
+
+        debug!("Setting tray icon base to \"{}\"", name);
+        if let Some(dir_str) = icon_theme_dir.as_deref().and_then(Path::to_str) {
+            self.ai.set_icon_theme_path(dir_str);
+        }
+        self.requested_icons = TrayIconSet::from_base(name);
+        self.icons = resolve_icons(&self.requested_icons, icon_theme_dir.as_deref());
+        self.icon_theme_dir = icon_theme_dir;
+        self.set_icon_state(self.icon_state);
+        Ok(())
+    }
+
     /// Notify the tray about sslocal stoppage (primarily, due to error),
     /// without emitting a `ManualStop` event.
     pub fn notify_sslocal_stop(&mut self) {
@@ -125,49 +281,154 @@ impl TrayItem {
         *util::rwlock_write(&self.manual_stop_item.1) = false; // set listen disable
         self.manual_stop_item.0.set_active(true);
         *util::rwlock_write(&self.manual_stop_item.1) = true; // set listen enable
+        self.set_icon_state(IconState::Stopped);
+    }
+
+    /// Notify the tray about auto-restart being paused or resumed,
+    /// by showing or hiding a label in the menu.
+    #[cfg(feature = "runtime-api")]
+    pub fn notify_auto_restart_paused(&mut self, paused: bool) {
+        debug!("Setting tray auto-restart paused state to {}", paused);
+        self.auto_restart_paused_item.set_visible(paused);
+    }
+
+    /// Show the uptime and traffic of the active instance in the menu,
+    /// or hide them if there is no active instance.
+    pub fn set_instance_stats(&mut self, stats: Option<InstanceStats>) {
+        match stats {
+            Some(stats) => {
+                let label = format!(
+                    "Up {} · ↑ {} ↓ {}",
+                    util::format_duration_short(stats.uptime),
+                    util::format_bytes_short(stats.bytes_sent),
+                    util::format_bytes_short(stats.bytes_received)
+                );
+                self.stats_item.set_label(&label);
+                self.stats_item.set_visible(true);
+            }
+            None => self.stats_item.set_visible(false),
+        }
+    }
+
+    /// Notify the tray about the result of a connectivity self-test,
+    /// by showing a warning label next to the icon if it failed.
+    pub fn notify_self_test_result(&mut self, passed: bool) {
+        debug!(
+            "Setting tray self-test state to {}",
+            if passed { "passed" } else { "failed" }
+        );
+        match passed {
+            true => self.ai.set_label("", ""),
+            false => self.ai.set_label("Self-test failed", "Self-test failed"),
+        }
     }
 
     /// Notify the tray about sslocal switching to a another,
     /// without emitting a `SwitchProfile` event.
     pub fn notify_profile_switch(&mut self, name: impl AsRef<str>) {
-        let profile_item = self.profile_items.iter().find(|(item, _)| {
-            let item_name = item
-                .label()
-                .expect("A profile's RadioMenuItem has no label")
-                .to_string();
-            name.as_ref() == item_name
-        });
-        match profile_item {
+        match self.find_profile_item(name.as_ref()) {
             Some((item, listen_enable)) => {
                 debug!("Setting tray to active state with profile \"{}\"", name.as_ref());
                 *util::rwlock_write(listen_enable) = false; // set listen disable
                 item.set_active(true);
                 *util::rwlock_write(listen_enable) = true; // set listen enable
+                self.set_icon_state(IconState::Active);
             }
             None => warn!("Cannot find RadioMenuItem for profile named \"{}\"", name.as_ref()),
         }
     }
 
+    /// Replace the entries in the "Recent" submenu with the specified profile history.
+    ///
+    /// Clicking an entry selects its profile in the profile tree, which emits a `SwitchProfile` event.
+    /// Entries of profiles that are not in the tree are greyed out.
+    pub fn set_profile_history(&mut self, history: &ProfileHistory) {
+        let submenu = Menu::new();
+        for entry in history.iter() {
+            let time = i64::try_from(entry.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())
+                .ok()
+                .and_then(|secs| glib::DateTime::from_unix_local(secs).ok())
+                .and_then(|dt| dt.format("%b %e %H:%M").ok());
+            let label = match time {
+                Some(time) => format!("{} ({})", entry.profile, time),
+                None => entry.profile.clone(),
+            };
+            let item = MenuItem::with_label(&label);
+            match self.find_profile_item(&entry.profile) {
+                Some((radio_item, _)) => {
+                    let radio_item = radio_item.clone();
+                    item.connect_activate(move |_| radio_item.set_active(true));
+                }
+                None => item.set_sensitive(false),
+            }
+            submenu.append(&item);
+        }
+        submenu.show_all();
+        self.recent_item.set_submenu(Some(&submenu));
+        self.recent_item.set_sensitive(!history.is_empty());
+    }
+
+    /// Check the "Run Alongside" entries of the specified profiles and uncheck all others,
+    /// without emitting any `StartConcurrent` or `StopConcurrent` event.
+    pub fn set_concurrent_profiles(&mut self, names: &[String]) {
+        debug!("Setting tray profiles running alongside to {:?}", names);
+        for (item, listen_enable) in self.concurrent_items.iter() {
+            let checked = item
+                .label()
+                .is_some_and(|label| names.iter().any(|name| name == label.as_str()));
+            *util::rwlock_write(listen_enable) = false; // set listen disable
+            item.set_active(checked);
+            *util::rwlock_write(listen_enable) = true; // set listen enable
+        }
+    }
+
+    /// Annotate the label of each profile with the latency to its server, e.g. "(42 ms)".
+    ///
+    /// Profiles without a measurement are shown without annotation.
+    pub fn set_latencies(&mut self, latencies: &Latencies) {
+        for (item, _) in self.profile_items.iter() {
+            let name = item.widget_name();
+            let label = match latencies.get(name.as_str()) {
+                Some(Some(rtt)) => format!("{} ({} ms)", name, rtt.as_millis()),
+                Some(None) => format!("{} (unreachable)", name),
+                None => name.to_string(),
+            };
+            item.set_label(&label);
+        }
+    }
+
+    /// Replace the profile tree in the menu with that of a newly loaded `ProfileFolder`.
+    ///
+    /// No profile is selected afterwards; use `Self::notify_profile_switch` to select one.
+    #[cfg(feature = "runtime-api")]
+    pub fn reload_profiles(&mut self, profile_folder: &ProfileFolder, events_tx: Sender<AppEvent>) {
+        debug!("Reloading profiles in tray");
+        self.load_profiles(profile_folder, events_tx);
+        self.menu.show_all();
+    }
+
     /// Notify the tray about notification method change,
     /// without emitting a `SetNotify` event.
     #[cfg(feature = "runtime-api")]
     pub fn notify_notify_method_change(&mut self, method: NotifyMethod) {
-        let (method_item, listen_enable) = self
-            .notify_method_items
-            .iter()
-            .find(|(item, _)| {
-                let item_name = item
-                    .label()
-                    .unwrap() // variants must have a name (thus label)
-                    .to_string();
-                item_name == method.to_string()
-            })
-            .unwrap(); // RadioMenuItems are generated exhaustively
-
         debug!("Setting tray to notification method \"{}\"", method);
-        *util::rwlock_write(listen_enable) = false; // set listen disable
-        method_item.set_active(true);
-        *util::rwlock_write(listen_enable) = true; // set listen enable
+        set_active_by_label(&self.notify_method_items, method.to_string());
+    }
+
+    /// Notify the tray about log level change,
+    /// without emitting a `SetLogLevel` event.
+    #[cfg(feature = "runtime-api")]
+    pub fn notify_log_level_change(&mut self, level: LogLevel) {
+        debug!("Setting tray to log level \"{}\"", level);
+        set_active_by_label(&self.log_level_items, level.to_string());
+    }
+
+    /// Find the `ListeningRadioMenuItem` of the profile with the specified name.
+    fn find_profile_item(&self, name: &str) -> Option<&ListeningRadioMenuItem> {
+        // the label may be annotated, so match the widget name instead
+        self.profile_items
+            .iter()
+            .find(|(item, _)| item.widget_name().as_str() == name)
     }
 
     /// Append a separator to the tray item's menu.
@@ -193,25 +454,33 @@ impl TrayItem {
     }
     /// Load all `Profiles` from the root `ProfileFolder`,
     /// automatically generate the nested menu structure using `generate_profile_tree`,
-    /// and append them all to the tray item's menu as `RadioMenuItem`s.
+    /// and insert them all into the tray item's menu as `RadioMenuItem`s
+    /// at `Self::profile_entries_pos`.
     ///
     /// We unroll the first layer of the recursive call because we want to
     /// remove the topmost layer of nesting.
     ///
-    /// Also replaces `Self::profile_items` with the new list of `RadioMenuItem`s.
+    /// Also replaces `Self::profile_items` with the new list of `RadioMenuItem`s,
+    /// after removing the previously loaded ones (if any) from the menu.
     fn load_profiles(&mut self, profile_folder: &ProfileFolder, events_tx: Sender<AppEvent>) {
+        // remove old entries; they are destroyed once the last references are dropped below
+        for entry in self.profile_entries.drain(..) {
+            self.menu.remove(&entry);
+        }
+
         let radio_group = &self.manual_stop_item.0; // the ref used to group `RadioMenuItem`s
         let mut radio_menu_item_list = vec![];
+        let mut entries = vec![];
         match profile_folder {
             ProfileFolder::Group(g) => {
                 for cf in g.content.iter() {
                     let child = generate_profile_tree(cf, radio_group, events_tx.clone(), &mut radio_menu_item_list);
                     match child {
                         ProfileMenuItem::Profile(radio_item) => {
-                            self.menu.append(&radio_item.0); // build menu
+                            entries.push(radio_item.0.clone().upcast()); // build menu
                             radio_menu_item_list.push(radio_item); // save to list
                         }
-                        ProfileMenuItem::Group(item) => self.menu.append(&item), // build menu
+                        ProfileMenuItem::Group(item) => entries.push(item), // build menu
                     }
                 }
             }
@@ -220,15 +489,24 @@ impl TrayItem {
                     generate_profile_tree(profile, radio_group, events_tx, &mut radio_menu_item_list);
                 match profile_menu_item {
                     ProfileMenuItem::Profile(radio_item) => {
-                        self.menu.append(&radio_item.0); // build menu
+                        entries.push(radio_item.0.clone().upcast()); // build menu
                         radio_menu_item_list.push(radio_item); //  save to list
                     }
                     ProfileMenuItem::Group(_) => unreachable!("profile_menu_item should be a profile"),
                 }
             }
         }
-        // reset `self.profile_items` with temp `Vec`
+        for (offset, entry) in entries.iter().enumerate() {
+            self.menu.insert(entry, self.profile_entries_pos + offset as i32);
+        }
+        // reset `self.profile_items` & `self.profile_entries` with temp `Vec`s
         self.profile_items = radio_menu_item_list;
+        self.profile_entries = entries;
+
+        // the "Run Alongside" submenu lists all profiles flatly
+        let (submenu, concurrent_items) = generate_concurrent_selector(profile_folder, events_tx);
+        self.concurrent_item.set_submenu(Some(&submenu));
+        self.concurrent_items = concurrent_items;
     }
 
     /// Compose the menu to make ready for display.
@@ -252,11 +530,15 @@ fn generate_profile_tree(
 ) -> ProfileMenuItem {
     match profile_folder {
         ProfileFolder::Profile(p) => {
-            let profile = p.clone();
+            let profile = p.as_ref().clone();
             let enable_flag = Rc::new(RwLock::new(true));
             let enable_flag_mv = Rc::clone(&enable_flag);
             let menu_item = RadioMenuItem::with_label_from_widget(group, Some(&p.metadata.display_name));
+            // the label changes when annotated with the latency, so identify the item by its widget name
+            menu_item.set_widget_name(&p.metadata.display_name);
             menu_item.set_sensitive(true);
+            // not all tray hosts show tooltips for menu items
+            menu_item.set_tooltip_text(Some(&p.tooltip()));
             menu_item.connect_toggled(move |item| {
                 if item.is_active() && *util::rwlock_read(&enable_flag_mv) {
                     if let Err(_) = events_tx.send(AppEvent::SwitchProfile(profile.clone())) {
@@ -286,20 +568,65 @@ fn generate_profile_tree(
     }
 }
 
-/// Constructs the selection menu for `NotifyMethod` by enumerating its variants.
+/// Constructs a submenu with a `CheckMenuItem` for every profile,
+/// which starts or stops the profile alongside the active one when toggled.
+///
+/// Returns the submenu and all the generated `CheckMenuItem`s (alongside their enable flags).
+fn generate_concurrent_selector(
+    profile_folder: &ProfileFolder,
+    events_tx: Sender<AppEvent>,
+) -> (Menu, Vec<ListeningCheckMenuItem>) {
+    let submenu = Menu::new();
+    let items = profile_folder
+        .get_profiles()
+        .into_iter()
+        .map(|p| {
+            let profile = p.clone();
+            let enable_flag = Rc::new(RwLock::new(true));
+            let enable_flag_mv = Rc::clone(&enable_flag);
+            let events_tx = events_tx.clone();
+            let check_item = CheckMenuItem::with_label(&p.metadata.display_name);
+            check_item.set_tooltip_text(Some(&p.tooltip()));
+            check_item.connect_toggled(move |item| {
+                if !*util::rwlock_read(&enable_flag_mv) {
+                    return;
+                }
+                let event = match item.is_active() {
+                    true => AppEvent::StartConcurrent(profile.clone()),
+                    false => AppEvent::StopConcurrent(profile.metadata.display_name.clone()),
+                };
+                if let Err(err) = events_tx.send(event) {
+                    error!("Trying to send {:?} event, but all receivers have hung up.", err.0);
+                }
+            });
+            submenu.append(&check_item);
+            (check_item, enable_flag)
+        })
+        .collect();
+    submenu.show_all();
+    (submenu, items)
+}
+
+/// Constructs a selection menu for an enum by enumerating its variants.
 ///
 /// Returns the constructed `MenuItem` and all the generated `RadioMenuItem`s
 /// (alongside their enable flags) in a pair.
-fn generate_notify_method_selector(
-    initial: NotifyMethod,
+fn generate_enum_selector<T, F>(
+    label: &str,
+    initial: T,
+    to_event: F,
     events_tx: Sender<AppEvent>,
-) -> (MenuItem, Vec<ListeningRadioMenuItem>) {
+) -> (MenuItem, Vec<ListeningRadioMenuItem>)
+where
+    T: Sequence + fmt::Display + PartialEq + Copy + 'static,
+    F: Fn(T) -> AppEvent + Clone + 'static,
+{
     // create radio items
-    let radios: Vec<_> = enum_iterator::all::<NotifyMethod>()
-        .map(|method| {
-            let radio_item = RadioMenuItem::with_label(&method.to_string());
+    let radios: Vec<_> = enum_iterator::all::<T>()
+        .map(|variant| {
+            let radio_item = RadioMenuItem::with_label(&variant.to_string());
             radio_item.set_sensitive(true);
-            (radio_item, method)
+            (radio_item, variant)
         })
         .collect();
 
@@ -312,7 +639,7 @@ fn generate_notify_method_selector(
     // set initial value
     radios
         .iter()
-        .find(|(_, method)| *method == initial)
+        .find(|(_, variant)| *variant == initial)
         .unwrap() // we have one of every variant
         .0
         .set_active(true);
@@ -324,14 +651,16 @@ fn generate_notify_method_selector(
     // connect and store
     let connected_radios = radios
         .into_iter()
-        .map(|(radio_item, method)| {
+        .map(|(radio_item, variant)| {
             let enable_flag = Rc::new(RwLock::new(true));
             let enable_flag_mv = Rc::clone(&enable_flag);
             let events_tx = events_tx.clone();
+            let to_event = to_event.clone();
             radio_item.connect_toggled(move |radio| {
                 if radio.is_active() && *util::rwlock_read(&enable_flag_mv) {
-                    if let Err(_) = events_tx.send(AppEvent::SetNotify(method)) {
-                        error!("Trying to send SetNotify event, but all receivers have hung up.");
+                    let event = to_event(variant);
+                    if let Err(err) = events_tx.send(event) {
+                        error!("Trying to send {:?} event, but all receivers have hung up.", err.0);
                     }
                 }
             });
@@ -340,9 +669,112 @@ fn generate_notify_method_selector(
         .collect();
 
     // create parent
-    let parent = MenuItem::with_label("Notifications");
+    let parent = MenuItem::with_label(label);
     parent.set_sensitive(true);
     parent.set_submenu(Some(&submenu));
 
     (parent, connected_radios)
 }
+
+/// Set the `RadioMenuItem` with a matching label to active,
+/// without emitting an event.
+#[cfg(feature = "runtime-api")]
+fn set_active_by_label(items: &[ListeningRadioMenuItem], label: impl AsRef<str>) {
+    let (item, listen_enable) = items
+        .iter()
+        .find(|(item, _)| {
+            let item_name = item
+                .label()
+                .unwrap() // variants must have a name (thus label)
+                .to_string();
+            item_name == label.as_ref()
+        })
+        .unwrap(); // RadioMenuItems are generated exhaustively
+
+    *util::rwlock_write(listen_enable) = false; // set listen disable
+    item.set_active(true);
+    *util::rwlock_write(listen_enable) = true; // set listen enable
+}
+
+/// Adjust an icon theme directory for an icon size, and make sure that it's usable by `AppIndicator`.
+fn effective_icon_theme_dir(icon_theme_dir: Option<&Path>, icon_size: Option<u32>) -> Option<PathBuf> {
+    let icon_theme_dir = match (icon_theme_dir, icon_size) {
+        (Some(dir), Some(size)) => match sized_icon_dir(dir, size) {
+            Some(sized_dir) => {
+                debug!("Using tray icons of size {} from {:?}", size, sized_dir);
+                Some(sized_dir)
+            }
+            None => {
+                warn!("Cannot find icons of size {} in {:?}; using default sizing", size, dir);
+                Some(dir.to_path_buf())
+            }
+        },
+        (None, Some(size)) => {
+            warn!(
+                "Cannot use icons of size {} without an icon theme directory; using default sizing",
+                size
+            );
+            None
+        }
+        (dir, None) => dir.map(Path::to_path_buf),
+    };
+    // `AppIndicator` only accepts UTF-8 paths; the clap validator checks this,
+    // but not every caller goes through it
+    icon_theme_dir.filter(|dir| {
+        let is_utf8 = dir.to_str().is_some();
+        if !is_utf8 {
+            warn!(
+                "Icon theme directory {:?} is not valid UTF-8; using the default icon theme instead",
+                dir
+            );
+        }
+        is_utf8
+    })
+}
+
+/// Whether an icon can be found in `icon_theme_dir` if specified, or in the system icon theme.
+fn icon_exists(name: &str, icon_theme_dir: Option<&Path>) -> bool {
+    let in_dir = icon_theme_dir
+        .and_then(|dir| dir.read_dir().ok())
+        .map(|mut entries| {
+            entries.any(|ent_res| {
+                ent_res
+                    .ok()
+                    .filter(|ent| ent.path().file_stem().and_then(|s| s.to_str()) == Some(name))
+                    .is_some()
+            })
+        })
+        .unwrap_or(false);
+    in_dir || gtk::IconTheme::default().filter(|theme| theme.has_icon(name)).is_some()
+}
+
+/// Replace the variants that cannot be found with the active icon.
+///
+/// Icons are searched for in `icon_theme_dir` if specified, and in the system icon theme.
+fn resolve_icons(icons: &TrayIconSet, icon_theme_dir: Option<&Path>) -> TrayIconSet {
+    let resolve = |name: &String| match icon_exists(name, icon_theme_dir) {
+        true => name.clone(),
+        false => {
+            debug!("Tray icon \"{}\" not found; using \"{}\" instead", name, icons.active);
+            icons.active.clone()
+        }
+    };
+    TrayIconSet {
+        active: icons.active.clone(),
+        stopped: resolve(&icons.stopped),
+        error: resolve(&icons.error),
+        connecting: resolve(&icons.connecting),
+    }
+}
+
+/// Find the subdirectory of an icon theme directory that contains icons of a specific size.
+fn sized_icon_dir(dir: &Path, size: u32) -> Option<PathBuf> {
+    let size_dir = format!("{0}x{0}", size);
+    [
+        dir.join(&size_dir),
+        dir.join(&size_dir).join("apps"),
+        dir.join("hicolor").join(&size_dir).join("apps"),
+    ]
+    .into_iter()
+    .find(|candidate| candidate.is_dir())
+}
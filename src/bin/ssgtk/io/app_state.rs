@@ -1,14 +1,33 @@
 //! This module defines the application state, read from and saved to disk
 //! when the application in starting and stopping respectively.
 
-use std::{fmt, fs, io, path::Path, time::Duration};
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
 use serde::{Deserialize, Serialize};
-use shadowsocks_gtk_rs::{notify_method::NotifyMethod, util::leaky_bucket::NaiveLeakyBucketConfig};
+use shadowsocks_gtk_rs::{
+    consts::{BUS_BUFFER_SIZE, PROFILE_HISTORY_LEN, SSLOCAL_STOP_TIMEOUT_DEFAULT},
+    notify_method::NotifyMethod,
+    util::leaky_bucket::NaiveLeakyBucketConfig,
+};
+
+use crate::io::{kill_switch::KillSwitchConfig, log_file::LogFileConfig};
 
 #[derive(Debug)]
 pub enum AppStateError {
     ParseError(serde_yaml::Error),
+    /// The path points to a directory instead of a file.
+    IsDirectory(PathBuf),
+    /// The file does not exist.
+    NotFound(PathBuf),
+    /// The file exists but we are not allowed to access it.
+    PermissionDenied(PathBuf),
     IOError(io::Error),
 }
 
@@ -17,6 +36,9 @@ impl fmt::Display for AppStateError {
         use AppStateError::*;
         match self {
             ParseError(e) => write!(f, "AppStateError-ParseError: {}", e),
+            IsDirectory(p) => write!(f, "AppStateError-IsDirectory: {:?} is a directory, expected a file", p),
+            NotFound(p) => write!(f, "AppStateError-NotFound: {:?} does not exist", p),
+            PermissionDenied(p) => write!(f, "AppStateError-PermissionDenied: cannot access {:?}", p),
             IOError(e) => write!(f, "AppStateError-IOError: {}", e),
         }
     }
@@ -33,34 +55,260 @@ impl From<io::Error> for AppStateError {
     }
 }
 
+/// The names of the tray icons to use for each state of the application.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrayIconSet {
+    /// A profile is running.
+    pub active: String,
+    /// No profile is running.
+    pub stopped: String,
+    /// Auto-restart has stopped due to an error.
+    pub error: String,
+    /// A profile is being started.
+    pub connecting: String,
+}
+
+impl TrayIconSet {
+    /// Derive the variants from a base icon name, e.g. `<base>-stopped`.
+    ///
+    /// The base icon itself is used for the active state.
+    #[cfg(feature = "gui")]
+    pub fn from_base(base: &str) -> Self {
+        Self {
+            active: base.into(),
+            stopped: format!("{}-stopped", base),
+            error: format!("{}-error", base),
+            connecting: format!("{}-connecting", base),
+        }
+    }
+}
+
+/// How to retry resuming a profile on launch if starting it fails, e.g. because the network is not up yet.
+///
+/// This is separate from the auto-restart limit, which only applies once a profile is running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResumeRetryConfig {
+    /// How many times to retry; `0` disables retrying.
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubled for each subsequent one.
+    pub base_delay: Duration,
+}
+
+impl Default for ResumeRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+impl ResumeRetryConfig {
+    /// The delay before the specified retry, starting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay * 2u32.saturating_pow(attempt.saturating_sub(1))
+    }
+}
+
+/// A switch to a profile, as recorded in `ProfileHistory`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileHistoryEntry {
+    pub profile: String,
+    pub time: SystemTime,
+}
+
+/// The most recent profile switches, newest first.
+///
+/// At most `PROFILE_HISTORY_LEN` entries are kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ProfileHistory(VecDeque<ProfileHistoryEntry>);
+
+impl ProfileHistory {
+    /// Record a switch to the specified profile, forgetting the oldest switch if full.
+    ///
+    /// A repeated switch to the newest profile only updates its time.
+    pub fn push(&mut self, profile: impl Into<String>, time: SystemTime) {
+        let profile = profile.into();
+        match self.0.front_mut() {
+            Some(newest) if newest.profile == profile => newest.time = time,
+            _ => {
+                self.0.push_front(ProfileHistoryEntry { profile, time });
+                self.0.truncate(PROFILE_HISTORY_LEN);
+            }
+        }
+    }
+
+    /// Iterate over the switches, newest first.
+    #[cfg(feature = "gui")]
+    pub fn iter(&self) -> impl Iterator<Item = &ProfileHistoryEntry> {
+        self.0.iter()
+    }
+
+    /// Whether no switches have been recorded yet.
+    #[cfg(feature = "gui")]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
 /// Describes the state of the application.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AppState {
     /// `""` indicates none.
     pub most_recent_profile: String,
+    /// Always resume with this profile if set, instead of the most recent one.
+    #[serde(default)]
+    pub default_profile: Option<String>,
     pub restart_limit: NaiveLeakyBucketConfig,
     pub notify_method: NotifyMethod,
+    /// How long toast notifications stay on screen in milliseconds; `None` uses the server's default,
+    /// and `0` never expires.
+    #[serde(default)]
+    pub notify_timeout_ms: Option<u32>,
+    /// Keep error toasts on screen until dismissed, regardless of `notify_timeout_ms`.
+    #[serde(default)]
+    pub sticky_error_toasts: bool,
+    /// `None` indicates that the set should be derived from the `--icon-name` launch option.
+    #[serde(default)]
+    pub tray_icons: Option<TrayIconSet>,
+    /// The maximum size of the in-memory log backlog in bytes; `0` indicates unlimited.
+    #[serde(default = "default_backlog_limit")]
+    pub backlog_limit: u64,
+    /// How long to wait for `sslocal` to exit gracefully when stopping it, before killing it.
+    #[serde(default = "default_stop_timeout")]
+    pub stop_timeout: Duration,
+    /// How long `sslocal` is given to start listening before it's stopped as failed,
+    /// unless its profile sets `launch_timeout`; `None` indicates unlimited.
+    #[serde(default)]
+    pub launch_timeout: Option<Duration>,
+    /// How many lines of `sslocal`'s logs can be queued for the log viewer before they are dropped from it.
+    #[serde(default = "default_log_buffer_lines")]
+    pub log_buffer_lines: usize,
+    /// Whether the log viewer window was open, so that it can be reopened on launch.
+    #[serde(default)]
+    pub log_viewer_open: bool,
+    /// Ask for confirmation before quitting from the tray while `sslocal` is running.
+    #[serde(default)]
+    pub confirm_quit: bool,
+    #[serde(default)]
+    pub resume_retry: ResumeRetryConfig,
+    /// The most recent profile switches, shown in the tray.
+    #[serde(default)]
+    pub profile_history: ProfileHistory,
+    /// Block traffic with these firewall commands while no `sslocal` is running; disabled if `None`.
+    #[serde(default)]
+    pub kill_switch: Option<KillSwitchConfig>,
+    /// Also write `sslocal`'s logs to rotating files in a directory; disabled if `None`.
+    #[serde(default)]
+    pub log_file: Option<LogFileConfig>,
+}
+
+fn default_backlog_limit() -> u64 {
+    1024 * 1024
+}
+
+fn default_stop_timeout() -> Duration {
+    SSLOCAL_STOP_TIMEOUT_DEFAULT
+}
+
+fn default_log_buffer_lines() -> usize {
+    BUS_BUFFER_SIZE
 }
 
 impl Default for AppState {
     fn default() -> Self {
         Self {
             most_recent_profile: String::new(),
+            default_profile: None,
             restart_limit: NaiveLeakyBucketConfig::new(5, Duration::from_secs(30)),
             notify_method: NotifyMethod::Toast,
+            notify_timeout_ms: None,
+            sticky_error_toasts: false,
+            tray_icons: None,
+            backlog_limit: default_backlog_limit(),
+            stop_timeout: default_stop_timeout(),
+            launch_timeout: None,
+            log_buffer_lines: default_log_buffer_lines(),
+            log_viewer_open: false,
+            confirm_quit: false,
+            resume_retry: ResumeRetryConfig::default(),
+            profile_history: ProfileHistory::default(),
+            kill_switch: None,
+            log_file: None,
         }
     }
 }
 
 impl AppState {
     pub fn from_file(path: impl AsRef<Path>) -> Result<Self, AppStateError> {
-        let content = fs::read_to_string(path)?;
+        let path = path.as_ref();
+        if path.is_dir() {
+            return Err(AppStateError::IsDirectory(path.into()));
+        }
+        let content = fs::read_to_string(path).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => AppStateError::NotFound(path.into()),
+            io::ErrorKind::PermissionDenied => AppStateError::PermissionDenied(path.into()),
+            _ => err.into(),
+        })?;
         let state = serde_yaml::from_str(&content)?;
         Ok(state)
     }
+    /// Write to a temporary file first, then move it into place,
+    /// so that the file is never left half-written.
     pub fn write_to_file(&self, path: impl AsRef<Path>) -> Result<(), AppStateError> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            return Err(AppStateError::IsDirectory(path.into()));
+        }
         let content = serde_yaml::to_string(self)?;
-        fs::write(path, content)?;
+        let tmp_path = {
+            let mut tmp = path.as_os_str().to_owned();
+            tmp.push(".tmp");
+            PathBuf::from(tmp)
+        };
+        let write_synced = || -> io::Result<()> {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(content.as_bytes())?;
+            // make sure the content is on disk before it replaces the old file
+            file.sync_all()
+        };
+        write_synced()
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::PermissionDenied => AppStateError::PermissionDenied(path.into()),
+                _ => err.into(),
+            })?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use shadowsocks_gtk_rs::consts::PROFILE_HISTORY_LEN;
+
+    use super::ProfileHistory;
+
+    #[test]
+    fn profile_history_is_bounded_and_deduplicated() {
+        let t0 = SystemTime::UNIX_EPOCH;
+        let t1 = t0 + Duration::from_secs(1);
+        let mut history = ProfileHistory::default();
+
+        history.push("a", t0);
+        history.push("b", t0);
+        history.push("b", t1);
+        history.push("a", t1);
+        let names: Vec<_> = history.0.iter().map(|e| e.profile.as_str()).collect();
+        assert_eq!(names, ["a", "b", "a"]);
+        assert_eq!(history.0[1].time, t1);
+
+        for i in 0..PROFILE_HISTORY_LEN * 2 {
+            history.push(i.to_string(), t1);
+        }
+        assert_eq!(history.0.len(), PROFILE_HISTORY_LEN);
+        assert_eq!(history.0[0].profile, (PROFILE_HISTORY_LEN * 2 - 1).to_string());
+    }
+}
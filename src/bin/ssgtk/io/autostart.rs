@@ -0,0 +1,148 @@
+//! This module contains code that writes a desktop entry to the XDG autostart directory,
+//! so that `ssgtk` is started on login.
+
+use std::{
+    env,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use shadowsocks_gtk_rs::consts::*;
+use xdg::BaseDirectories;
+
+/// The path of the desktop entry in the XDG autostart directory.
+pub fn autostart_file_path() -> io::Result<PathBuf> {
+    // the autostart directory is shared, so don't use the prefixed `XDG_DIRS`
+    // this only fails if the home directory cannot be found
+    let xdg_dirs = BaseDirectories::new().map_err(|err| io::Error::new(io::ErrorKind::NotFound, err))?;
+    Ok(xdg_dirs
+        .get_config_home()
+        .join("autostart")
+        .join(format!("{}.desktop", APP_NAME)))
+}
+
+/// Write a desktop entry that starts the current executable with the specified
+/// profiles directory and app state path, returning where it was written.
+///
+/// Refuses to replace an existing file unless `force` is set.
+pub fn write_autostart(profiles_dir: &Path, app_state_path: &Path, force: bool) -> io::Result<PathBuf> {
+    let exe = env::current_exe()?;
+    let entry = desktop_entry(
+        &exe,
+        &[
+            "--profiles-dir".as_ref(),
+            absolute(profiles_dir)?.as_path(),
+            "--app-state".as_ref(),
+            absolute(app_state_path)?.as_path(),
+        ],
+    )?;
+
+    let path = autostart_file_path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .create_new(!force)
+        .truncate(true)
+        .open(&path)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => io::Error::new(
+                err.kind(),
+                format!("{:?} already exists; use --force to overwrite it", path),
+            ),
+            _ => err,
+        })?
+        .write_all(entry.as_bytes())?;
+    Ok(path)
+}
+
+/// Generate a desktop entry that runs `exe` with `args`.
+fn desktop_entry(exe: &Path, args: &[&Path]) -> io::Result<String> {
+    let exec = [exe]
+        .iter()
+        .chain(args)
+        .map(|arg| {
+            arg.to_str().map(quote_exec_arg).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{:?} is not valid UTF-8, so it cannot be put in a desktop entry", arg),
+                )
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?
+        .join(" ");
+
+    Ok(format!(
+        "[Desktop Entry]
+Type=Application
+Name=Shadowsocks GTK
+Comment=A desktop GUI frontend for shadowsocks-rust client implemented with gtk-rs.
+Icon=shadowsocks-gtk-rs
+Terminal=false
+Exec={}
+X-GNOME-Autostart-enabled=true
+",
+        exec
+    ))
+}
+
+/// Quote an argument for the `Exec` key of a desktop entry.
+///
+/// See https://specifications.freedesktop.org/desktop-entry-spec/latest/ar01s07.html.
+fn quote_exec_arg(arg: &str) -> String {
+    const RESERVED: &[char] = &[
+        ' ', '\t', '\n', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(', ')', '`',
+    ];
+
+    // field codes start with '%', so a literal one needs to be doubled
+    let arg = arg.replace('%', "%%");
+    let quoted = match arg.contains(RESERVED) {
+        true => {
+            let mut quoted = String::from('"');
+            for c in arg.chars() {
+                if matches!(c, '"' | '`' | '$' | '\\') {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted.push('"');
+            quoted
+        }
+        false => arg,
+    };
+    // the value of the key is itself a string, in which backslashes are escaped again
+    quoted.replace('\\', "\\\\")
+}
+
+/// Make a path absolute without requiring it to exist, since it's resolved on login.
+fn absolute(path: &Path) -> io::Result<PathBuf> {
+    match path.canonicalize() {
+        Ok(path) => Ok(path),
+        Err(_) if path.is_absolute() => Ok(path.into()),
+        Err(_) => Ok(env::current_dir()?.join(path)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::quote_exec_arg;
+
+    #[test]
+    fn exec_arg_quoting() {
+        let cases = [
+            ("/usr/bin/ssgtk", "/usr/bin/ssgtk"),
+            ("--profiles-dir", "--profiles-dir"),
+            ("/home/me/My Profiles", r#""/home/me/My Profiles""#),
+            ("100%", "100%%"),
+            (r#"say "hi""#, r#""say \\"hi\\"""#),
+            ("$HOME", r#""\\$HOME""#),
+            (r"a\b", r#""a\\\\b""#),
+        ];
+        for (arg, expected) in cases {
+            assert_eq!(quote_exec_arg(arg), expected, "arg: {:?}", arg);
+        }
+    }
+}
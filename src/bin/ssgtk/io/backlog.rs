@@ -0,0 +1,264 @@
+//! This module contains code that stores the combined logs of `sslocal`,
+//! either in memory or spooled to a temporary file,
+//! and optionally writes them to rotating log files too.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::{FileExt, OpenOptionsExt},
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use log::{debug, warn};
+use shadowsocks_gtk_rs::consts::XDG_DIRS;
+
+use crate::io::log_file::RotatingLogFile;
+
+/// Used to give each spool file a unique name.
+static SPOOL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The backlog history of `sslocal`'s output.
+#[derive(Debug, Default)]
+pub struct Backlog {
+    store: Store,
+    /// The maximum size in bytes when held in memory, beyond which the oldest lines are dropped.
+    ///
+    /// Does not apply once spooled.
+    limit: Option<usize>,
+    /// Also written to, if set; unaffected by `limit`.
+    log_file: Option<RotatingLogFile>,
+}
+
+/// Where the backlog is stored.
+///
+/// The spool file (if any) is removed when dropped.
+#[derive(Debug)]
+enum Store {
+    /// Everything is held in memory.
+    Memory(String),
+    /// Everything is written to a temporary file, and read back in pages on demand.
+    Spooled { file: File, path: PathBuf, len: u64 },
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::Memory(String::new())
+    }
+}
+
+impl Drop for Store {
+    fn drop(&mut self) {
+        if let Self::Spooled { path, .. } = self {
+            match fs::remove_file(&path) {
+                Ok(_) => debug!("Removed backlog spool file at {:?}", path),
+                Err(err) => warn!("Failed to remove backlog spool file at {:?}: {}", path, err),
+            }
+        }
+    }
+}
+
+impl Backlog {
+    /// Move the backlog into a temporary file, including the existing content.
+    ///
+    /// Does nothing if already spooled.
+    pub fn spool(&mut self) -> io::Result<()> {
+        let content = match &self.store {
+            Store::Memory(s) => s,
+            Store::Spooled { .. } => return Ok(()),
+        };
+        let file_name = format!(
+            "ssgtk-backlog-{}-{}.log",
+            process::id(),
+            SPOOL_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        // prefer the private runtime directory, same as the runtime API socket
+        let path = XDG_DIRS
+            .place_runtime_file(&file_name)
+            .unwrap_or_else(|_| std::env::temp_dir().join(&file_name));
+        // the backlog may contain sensitive information, so it's only readable by the owner
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        file.write_all(content.as_bytes())?;
+        debug!("Spooling backlog to {:?}", path);
+        self.store = Store::Spooled {
+            file,
+            path,
+            len: content.len() as u64,
+        };
+        Ok(())
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn is_spooled(&self) -> bool {
+        matches!(self.store, Store::Spooled { .. })
+    }
+
+    /// The maximum size in bytes when held in memory; `None` means unlimited.
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Set the maximum size in bytes when held in memory; `None` means unlimited.
+    ///
+    /// Takes effect immediately.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+        self.enforce_limit();
+    }
+
+    /// Also write everything appended from now on to a log file.
+    pub fn set_log_file(&mut self, log_file: RotatingLogFile) {
+        self.log_file = Some(log_file);
+    }
+
+    /// The log file also written to, if any.
+    #[cfg(feature = "gui")]
+    pub fn log_file(&self) -> Option<&RotatingLogFile> {
+        self.log_file.as_ref()
+    }
+
+    /// The total length of the backlog in bytes.
+    pub fn len(&self) -> u64 {
+        match &self.store {
+            Store::Memory(s) => s.len() as u64,
+            Store::Spooled { len, .. } => *len,
+        }
+    }
+
+    /// Append to the end of the backlog.
+    pub fn push_str(&mut self, s: &str) -> io::Result<()> {
+        match &mut self.store {
+            Store::Memory(content) => content.push_str(s),
+            Store::Spooled { file, len, .. } => {
+                file.write_all(s.as_bytes())?;
+                *len += s.len() as u64;
+            }
+        }
+        self.enforce_limit();
+        if let Some(log_file) = &mut self.log_file {
+            log_file.write_str(s)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the oldest complete lines until within the limit, if held in memory.
+    fn enforce_limit(&mut self) {
+        let (content, limit) = match (&mut self.store, self.limit) {
+            (Store::Memory(content), Some(limit)) if content.len() > limit => (content, limit),
+            _ => return,
+        };
+        let excess = content.len() - limit;
+        // '\n' is always a char boundary
+        let cut = match content.as_bytes()[excess..].iter().position(|&b| b == b'\n') {
+            Some(idx) => excess + idx + 1,
+            None => content.len(),
+        };
+        content.drain(..cut);
+    }
+
+    /// Read the page of up to `max_bytes` that ends at byte offset `end`.
+    ///
+    /// The page is trimmed to start at a line boundary.
+    /// Returns the byte offset at which the page starts, and the page itself.
+    pub fn read_page(&self, end: u64, max_bytes: u64) -> io::Result<(u64, String)> {
+        let end = end.min(self.len());
+        let mut start = end.saturating_sub(max_bytes);
+        let bytes = match &self.store {
+            Store::Memory(s) => s.as_bytes()[start as usize..end as usize].to_vec(),
+            Store::Spooled { file, .. } => {
+                let mut buf = vec![0; (end - start) as usize];
+                file.read_exact_at(&mut buf, start)?;
+                buf
+            }
+        };
+        // drop the partial line at the beginning
+        let skip = match (start, bytes.iter().position(|&b| b == b'\n')) {
+            (0, _) | (_, None) => 0,
+            (_, Some(idx)) => idx + 1,
+        };
+        start += skip as u64;
+        Ok((start, String::from_utf8_lossy(&bytes[skip..]).into_owned()))
+    }
+
+    /// Read the last `lines` complete lines, or everything if `None`.
+    #[cfg(feature = "runtime-api")]
+    pub fn tail(&self, lines: Option<usize>) -> io::Result<String> {
+        let lines = match lines {
+            Some(n) => n,
+            None => return self.read_page(self.len(), u64::MAX).map(|(_, page)| page),
+        };
+        // widen the page until it has enough lines, or is the entire backlog
+        let mut max_bytes = 64 * 1024;
+        let page = loop {
+            let (start, page) = self.read_page(self.len(), max_bytes)?;
+            if start == 0 || page.matches('\n').count() >= lines {
+                break page;
+            }
+            max_bytes *= 2;
+        };
+        let all_lines = page.split_inclusive('\n').collect::<Vec<_>>();
+        Ok(all_lines[all_lines.len().saturating_sub(lines)..].concat())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use super::{Backlog, Store};
+
+    #[test]
+    fn read_page_line_aligned() {
+        let mut backlog = Backlog::default();
+        backlog.push_str("first line\nsecond line\nthird\n").unwrap();
+        let (start, page) = backlog.read_page(backlog.len(), 15).unwrap();
+        assert_eq!(page, "third\n");
+        assert_eq!(start, 23);
+        let (start, page) = backlog.read_page(start, 100).unwrap();
+        assert_eq!(page, "first line\nsecond line\n");
+        assert_eq!(start, 0);
+    }
+    #[test]
+    fn spooled_matches_memory() {
+        let mut backlog = Backlog::default();
+        backlog.push_str("before spool\n").unwrap();
+        backlog.spool().unwrap();
+        backlog.push_str("after spool\n").unwrap();
+        match &backlog.store {
+            Store::Spooled { path, .. } => {
+                let mode = fs::metadata(path).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+            }
+            Store::Memory(_) => panic!("backlog is not spooled"),
+        }
+        let (start, page) = backlog.read_page(backlog.len(), u64::MAX).unwrap();
+        assert_eq!(start, 0);
+        assert_eq!(page, "before spool\nafter spool\n");
+    }
+    #[cfg(feature = "runtime-api")]
+    #[test]
+    fn tail_lines() {
+        let mut backlog = Backlog::default();
+        backlog.push_str("first line\nsecond line\nthird\n").unwrap();
+        assert_eq!(backlog.tail(Some(2)).unwrap(), "second line\nthird\n");
+        assert_eq!(backlog.tail(Some(0)).unwrap(), "");
+        assert_eq!(backlog.tail(Some(10)).unwrap(), "first line\nsecond line\nthird\n");
+        assert_eq!(backlog.tail(None).unwrap(), "first line\nsecond line\nthird\n");
+    }
+    #[test]
+    fn limit_drops_oldest_lines() {
+        let mut backlog = Backlog::default();
+        backlog.set_limit(Some(16));
+        backlog.push_str("first line\nsecond line\n").unwrap();
+        let (_, page) = backlog.read_page(backlog.len(), u64::MAX).unwrap();
+        assert_eq!(page, "second line\n");
+        backlog.push_str("a very long third line\n").unwrap();
+        assert_eq!(backlog.len(), 0);
+    }
+}
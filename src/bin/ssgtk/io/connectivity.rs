@@ -0,0 +1,269 @@
+//! This module contains code that tests network connectivity,
+//! both to the remote server and to the local `sslocal` listener.
+
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::Sender;
+use log::{debug, error, info, warn};
+
+use crate::{event::AppEvent, io::profile_loader::Profile};
+
+/// How long to wait for a freshly started `sslocal` to bind its listener.
+const LISTENER_WAIT_DEADLINE: Duration = Duration::from_secs(5);
+/// How often to retry connecting while waiting for a listener.
+const LISTENER_WAIT_INTERVAL: Duration = Duration::from_millis(100);
+/// The initial and maximum interval between attempts to resolve the server address
+/// while waiting for the network.
+const NETWORK_WAIT_INTERVAL: (Duration, Duration) = (Duration::from_millis(500), Duration::from_secs(8));
+
+/// Run a one-time sanity check on a running profile:
+/// connect to the server directly if its address is known,
+/// then probe the local SOCKS5 listener if there is one.
+///
+/// Returns a short description of what has been tested,
+/// or `None` if the profile has nothing that can be tested (e.g. a "config-file"-type profile
+/// without a `local_addr` override).
+pub fn self_test(profile: &Profile, timeout: Duration) -> io::Result<Option<String>> {
+    // "config-file"-type profiles don't specify a server address
+    let server = match profile.server_addr() {
+        Some((host, port)) => Some(test_server(host, port, timeout)?),
+        None => None,
+    };
+    let local = match profile.socks_addr() {
+        Some(local) => {
+            probe_socks5(local, timeout)?;
+            Some(local)
+        }
+        None => None,
+    };
+    let msg = match (server, local) {
+        (Some(server), Some(local)) => format!("Server {} reachable; SOCKS5 listener on {} responding", server, local),
+        (Some(server), None) => format!("Server {} reachable", server),
+        (None, Some(local)) => format!(
+            "SOCKS5 listener on {} responding (server address unknown; not tested)",
+            local
+        ),
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(msg))
+}
+
+/// Run `self_test` on a profile in a separate thread,
+/// sending the result as an `AppEvent::SelfTestResult`.
+///
+/// If there is nothing to test, this is only logged.
+pub fn spawn_self_test(profile: Profile, events_tx: Sender<AppEvent>) -> io::Result<()> {
+    thread::Builder::new().name("startup self-test".into()).spawn(move || {
+        if let Some(local) = profile.socks_addr() {
+            // failure is reported by the SOCKS5 probe below
+            let _ = wait_for_listener(local, LISTENER_WAIT_DEADLINE);
+        }
+        let profile_name = profile.metadata.display_name.clone();
+        let res = match self_test(&profile, Duration::from_secs(5)) {
+            Ok(Some(msg)) => Ok(msg),
+            Ok(None) => {
+                info!(
+                    "Self-test for profile \"{}\" is not applicable: \
+                    it has neither a known server address nor a known SOCKS5 listener",
+                    profile_name
+                );
+                return;
+            }
+            Err(err) => Err(err.to_string()),
+        };
+        if let Err(_) = events_tx.send(AppEvent::SelfTestResult { profile_name, res }) {
+            error!("Trying to send SelfTestResult event, but all receivers have hung up.");
+        }
+    })?;
+    Ok(())
+}
+
+/// Check connectivity through a newly started profile in a separate thread,
+/// by connecting to its `probe_target` through its SOCKS5 listener.
+///
+/// Sends an `AppEvent::ProbeFailed` if the check fails. Does nothing if the profile
+/// has no `probe_target` or no SOCKS5 listener.
+pub fn spawn_probe(profile: Profile, events_tx: Sender<AppEvent>) -> io::Result<()> {
+    let (local_addr, (host, port)) = match (profile.socks_addr(), profile.probe_target()) {
+        (Some(local), Some((host, port))) => (local, (host.to_string(), port)),
+        _ => return Ok(()),
+    };
+    thread::Builder::new()
+        .name("connectivity probe".into())
+        .spawn(move || {
+            // failure is reported by the probe below
+            let _ = wait_for_listener(local_addr, LISTENER_WAIT_DEADLINE);
+            let profile_name = profile.metadata.display_name.clone();
+            match probe_socks5_connect(local_addr, (&host, port), Duration::from_secs(10)) {
+                Ok(_) => debug!("Profile \"{}\" can reach {}:{}", profile_name, host, port),
+                Err(err) => {
+                    let err = format!("Cannot reach {}:{}: {}", host, port, err);
+                    if let Err(_) = events_tx.send(AppEvent::ProbeFailed { profile_name, err }) {
+                        error!("Trying to send ProbeFailed event, but all receivers have hung up.");
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Wait for a profile's `startup_delay` and `wait_for_network` in a separate thread,
+/// then send an `AppEvent::StartupWaitDone` so that it can be started.
+///
+/// `generation` is the profile manager's switch generation, passed back in the event.
+///
+/// `sslocal` is started regardless once waiting is over,
+/// even if the server address did not resolve in time.
+pub fn spawn_startup_wait(profile: Profile, generation: u64, events_tx: Sender<AppEvent>) -> io::Result<()> {
+    thread::Builder::new().name("startup wait".into()).spawn(move || {
+        let profile_name = profile.metadata.display_name.clone();
+        if let Some(delay) = profile.startup_delay() {
+            debug!("Waiting {:?} before starting profile \"{}\"", delay, profile_name);
+            thread::sleep(delay);
+        }
+        if let (Some(deadline), Some((host, port))) = (profile.wait_for_network(), profile.server_addr()) {
+            match wait_for_resolve(host, port, deadline) {
+                Ok(_) => debug!("Server address {}:{} resolved", host, port),
+                Err(err) => warn!(
+                    "Server address {}:{} did not resolve within {:?}; starting profile \"{}\" anyway: {}",
+                    host, port, deadline, profile_name, err
+                ),
+            }
+        }
+        if let Err(_) = events_tx.send(AppEvent::StartupWaitDone { profile, generation }) {
+            error!("Trying to send StartupWaitDone event, but all receivers have hung up.");
+        }
+    })?;
+    Ok(())
+}
+
+/// Repeatedly try to resolve a host with exponential backoff until it resolves,
+/// or until the deadline has passed, in which case the last error is returned.
+pub fn wait_for_resolve(host: &str, port: u16, deadline: Duration) -> io::Result<()> {
+    let (mut interval, max_interval) = NETWORK_WAIT_INTERVAL;
+    let start = Instant::now();
+    loop {
+        let err = match (host, port).to_socket_addrs().map(|mut addrs| addrs.next()) {
+            Ok(Some(_)) => return Ok(()),
+            Ok(None) => io::Error::new(io::ErrorKind::NotFound, "no addresses found"),
+            Err(err) => err,
+        };
+        let elapsed = start.elapsed();
+        if elapsed >= deadline {
+            return Err(err);
+        }
+        debug!(
+            "Cannot resolve {}:{} yet ({}); retrying in {:?}",
+            host, port, err, interval
+        );
+        thread::sleep(interval.min(deadline - elapsed));
+        interval = (interval * 2).min(max_interval);
+    }
+}
+
+/// Repeatedly try to connect to a local listener until it accepts a connection,
+/// or until the deadline has passed, in which case the last error is returned.
+///
+/// Useful for waiting on a freshly started `sslocal` to bind its listener.
+pub fn wait_for_listener(local_addr: SocketAddr, deadline: Duration) -> io::Result<()> {
+    let addr = loopback_if_unspecified(local_addr);
+    let start = Instant::now();
+    loop {
+        match TcpStream::connect_timeout(&addr, LISTENER_WAIT_INTERVAL) {
+            Ok(_) => return Ok(()),
+            Err(err) if start.elapsed() >= deadline => return Err(err),
+            Err(_) => thread::sleep(LISTENER_WAIT_INTERVAL),
+        }
+    }
+}
+
+/// Resolve the server's host and attempt a TCP connection to it directly.
+///
+/// Returns the first address that accepted the connection.
+pub fn test_server(host: &str, port: u16, timeout: Duration) -> io::Result<SocketAddr> {
+    let mut last_err = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(_) => return Ok(addr),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} did not resolve to any address", host),
+        )
+    }))
+}
+
+/// Connect to a local SOCKS5 listener and perform the method negotiation handshake.
+///
+/// If the listener is bound to an unspecified address, loopback is used instead.
+pub fn probe_socks5(local_addr: SocketAddr, timeout: Duration) -> io::Result<()> {
+    socks5_handshake(local_addr, timeout).map(|_| ())
+}
+
+/// Ask a local SOCKS5 listener to connect to a target host,
+/// which tests the entire path through `sslocal` and the server.
+pub fn probe_socks5_connect(local_addr: SocketAddr, (host, port): (&str, u16), timeout: Duration) -> io::Result<()> {
+    let host_len = u8::try_from(host.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Host name too long: {}", host)))?;
+    let mut stream = socks5_handshake(local_addr, timeout)?;
+
+    // version 5, CONNECT, reserved, domain name
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, host_len];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+    // only the version and reply fields matter
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    match reply {
+        [0x05, 0x00] => Ok(()),
+        [0x05, code] => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!(
+                "SOCKS5 CONNECT to {}:{} failed with reply code {:#04x}",
+                host, port, code
+            ),
+        )),
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected SOCKS5 CONNECT reply: {:?}", other),
+        )),
+    }
+}
+
+/// Perform the method negotiation handshake, returning the stream ready for a request.
+fn socks5_handshake(local_addr: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+    let addr = loopback_if_unspecified(local_addr);
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    // version 5, 1 method, "no authentication required"
+    stream.write_all(&[0x05, 0x01, 0x00])?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    match reply {
+        [0x05, 0x00] => Ok(stream),
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected SOCKS5 handshake reply from {}: {:?}", addr, other),
+        )),
+    }
+}
+
+/// A listener bound to an unspecified address is reachable via loopback.
+fn loopback_if_unspecified(local_addr: SocketAddr) -> SocketAddr {
+    match local_addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => (Ipv4Addr::LOCALHOST, local_addr.port()).into(),
+        IpAddr::V6(ip) if ip.is_unspecified() => (Ipv6Addr::LOCALHOST, local_addr.port()).into(),
+        _ => local_addr,
+    }
+}
@@ -0,0 +1,95 @@
+//! This module contains a logger that writes each record to stdout as a line of JSON,
+//! for consumption by log aggregators.
+
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use log::{Log, Metadata, Record};
+use serde_json::json;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// The format in which `ssgtk` writes its own logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[clap(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// Human-readable lines, coloured if stdout is a terminal.
+    Text,
+    /// One JSON object per line, with `level`, `target`, `timestamp`, and `message`.
+    Json,
+}
+
+/// A logger that writes one JSON object per record to stdout.
+#[derive(Debug)]
+pub struct JsonLogger {
+    /// Only records whose target starts with one of these are logged.
+    allowed_targets: Vec<&'static str>,
+}
+
+impl JsonLogger {
+    pub fn new(allowed_targets: &[&'static str]) -> Self {
+        Self {
+            allowed_targets: allowed_targets.to_vec(),
+        }
+    }
+
+    /// Format a record as a single line of JSON, without the trailing newline.
+    fn format(record: &Record, now: OffsetDateTime) -> String {
+        let timestamp = now.format(&Rfc3339).unwrap_or_default();
+        json!({
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "timestamp": timestamp,
+            "message": record.args().to_string(),
+        })
+        .to_string()
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // the level is filtered by `log::max_level`, so that it can be adjusted at runtime
+        self.allowed_targets.iter().any(|t| metadata.target().starts_with(t))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = Self::format(record, OffsetDateTime::now_utc());
+        // if stdout is gone, there's nowhere to report it anyway
+        let _ = writeln!(io::stdout().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use log::{Level, Log, Record};
+    use time::OffsetDateTime;
+
+    use super::JsonLogger;
+
+    #[test]
+    fn format_and_filter() {
+        let logger = JsonLogger::new(&["ssgtk"]);
+        let record = |target| {
+            Record::builder()
+                .level(Level::Warn)
+                .target(target)
+                .args(format_args!("said \"hi\""))
+                .build()
+        };
+        assert!(logger.enabled(record("ssgtk::app").metadata()));
+        assert!(!logger.enabled(record("glib").metadata()));
+
+        let line = JsonLogger::format(&record("ssgtk::app"), OffsetDateTime::UNIX_EPOCH);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["target"], "ssgtk::app");
+        assert_eq!(value["timestamp"], "1970-01-01T00:00:00Z");
+        assert_eq!(value["message"], "said \"hi\"");
+    }
+}
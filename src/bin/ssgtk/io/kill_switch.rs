@@ -0,0 +1,201 @@
+//! This module contains code that blocks traffic while no `sslocal` is running,
+//! so that nothing leaks out directly when the proxy is down.
+//!
+//! The firewall rules are installed & removed by user-configured commands
+//! (e.g. `nft` or `iptables`), so that users control exactly what runs.
+//! Changing firewall rules requires root privileges, so the commands usually need
+//! to go through `sudo` with a `NOPASSWD` rule. They run on the main thread,
+//! so they should not prompt for anything.
+
+use std::{io, panic, sync::Mutex};
+
+use duct::cmd;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+
+/// The commands that install & remove the blocking firewall rules.
+///
+/// Each command is a program followed by its arguments. The placeholders `{server_host}`
+/// & `{server_port}` are replaced with the server address of the most recent profile,
+/// e.g. so that reconnecting to it is still allowed while traffic is blocked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KillSwitchConfig {
+    /// Installs the blocking rules when no `sslocal` is running.
+    pub engage: Vec<String>,
+    /// Removes the blocking rules when a profile becomes active, and on quit.
+    pub release: Vec<String>,
+}
+
+/// The release command to run if the application panics while the kill switch is engaged.
+///
+/// Set while engaged, cleared once released.
+static RELEASE_ON_PANIC: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+/// Replace the placeholders in a command.
+///
+/// Returns an error if the command needs a server address, but none is known.
+fn fill_template(command: &[String], server: Option<(&str, u16)>) -> io::Result<Vec<String>> {
+    command
+        .iter()
+        .map(|arg| {
+            if !arg.contains("{server_host}") && !arg.contains("{server_port}") {
+                return Ok(arg.clone());
+            }
+            match server {
+                Some((host, port)) => Ok(arg
+                    .replace("{server_host}", host)
+                    .replace("{server_port}", &port.to_string())),
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "the command needs a server address, but no profile with one has been started",
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Run a command to completion, failing if it exits unsuccessfully.
+fn run(command: &[String]) -> io::Result<()> {
+    match command.split_first() {
+        Some((program, args)) => cmd(program, args).stdin_null().run().map(|_| ()),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, "command is empty")),
+    }
+}
+
+/// Blocks traffic by running the configured commands, keeping track of whether it's engaged.
+///
+/// Releases on drop if still engaged.
+#[derive(Debug)]
+pub struct KillSwitch {
+    config: KillSwitchConfig,
+    /// The filled-in release command while engaged, so that it undoes exactly what was installed.
+    pending_release: Option<Vec<String>>,
+    /// Whether the most recent failed attempt was to engage (or to release), if it has failed.
+    failed_attempt: Option<bool>,
+}
+
+impl Drop for KillSwitch {
+    fn drop(&mut self) {
+        if self.is_engaged() {
+            warn!("Kill switch is still engaged when dropped; releasing");
+            if let Err(err) = self.release() {
+                error!("Failed to release kill switch: {}", err);
+            }
+        }
+    }
+}
+
+impl KillSwitch {
+    /// Create a released kill switch, and make sure that it's released if the application panics.
+    pub fn new(config: KillSwitchConfig) -> Self {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            default_hook(info);
+            let release = RELEASE_ON_PANIC.lock().ok().and_then(|mut pending| pending.take());
+            if let Some(command) = release {
+                eprintln!("Releasing kill switch before exiting");
+                if let Err(err) = run(&command) {
+                    eprintln!("Failed to release kill switch: {}", err);
+                }
+            }
+        }));
+        Self {
+            config,
+            pending_release: None,
+            failed_attempt: None,
+        }
+    }
+
+    pub fn config(&self) -> &KillSwitchConfig {
+        &self.config
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        self.pending_release.is_some()
+    }
+
+    /// Engage if no `sslocal` is running, or release otherwise.
+    ///
+    /// A failed attempt is not retried until `active` changes, so that a broken command isn't run repeatedly.
+    pub fn sync(&mut self, active: bool, server: Option<(&str, u16)>) -> io::Result<()> {
+        let engage = !active;
+        if self.is_engaged() == engage || self.failed_attempt == Some(engage) {
+            return Ok(());
+        }
+        let res = match engage {
+            true => self.engage(server),
+            false => self.release(),
+        };
+        self.failed_attempt = res.is_err().then_some(engage);
+        res
+    }
+
+    /// Install the blocking rules, unless already engaged.
+    ///
+    /// `server` is the server address of the most recent profile, if any.
+    pub fn engage(&mut self, server: Option<(&str, u16)>) -> io::Result<()> {
+        if self.is_engaged() {
+            return Ok(());
+        }
+        let engage = fill_template(&self.config.engage, server)?;
+        let release = fill_template(&self.config.release, server)?;
+        info!("Engaging kill switch");
+        debug!("Running kill switch command: {:?}", engage);
+        run(&engage)?;
+        *RELEASE_ON_PANIC.lock().unwrap_or_else(|err| err.into_inner()) = Some(release.clone());
+        self.pending_release = Some(release);
+        Ok(())
+    }
+
+    /// Remove the blocking rules, unless already released.
+    pub fn release(&mut self) -> io::Result<()> {
+        let release = match &self.pending_release {
+            Some(command) => command,
+            None => return Ok(()),
+        };
+        info!("Releasing kill switch");
+        debug!("Running kill switch command: {:?}", release);
+        run(release)?;
+        self.pending_release = None;
+        *RELEASE_ON_PANIC.lock().unwrap_or_else(|err| err.into_inner()) = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::fill_template;
+
+    #[test]
+    fn fill_placeholders() {
+        let command: Vec<String> = [
+            "nft",
+            "add",
+            "rule",
+            "inet",
+            "ssgtk",
+            "out",
+            "ip",
+            "daddr",
+            "{server_host}",
+        ]
+        .into_iter()
+        .chain(["tcp", "dport", "{server_port}", "accept"])
+        .map(String::from)
+        .collect();
+        let filled = fill_template(&command, Some(("203.0.113.1", 8388))).unwrap();
+        assert_eq!(filled[8], "203.0.113.1");
+        assert_eq!(filled[11], "8388");
+
+        // no placeholders, so no server is needed
+        let plain = vec![
+            "nft".to_string(),
+            "flush".into(),
+            "table".into(),
+            "inet".into(),
+            "ssgtk".into(),
+        ];
+        assert_eq!(fill_template(&plain, None).unwrap(), plain);
+        assert!(fill_template(&command, None).is_err());
+    }
+}
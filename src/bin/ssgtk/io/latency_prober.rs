@@ -0,0 +1,186 @@
+//! This module contains code that periodically measures the latency to each profile's server,
+//! so that users can tell which server is the fastest before switching to it.
+//!
+//! The latency is the time it takes to establish a TCP connection to the server,
+//! excluding the DNS lookup. Servers are probed one at a time with a pause in between,
+//! so that probing many profiles does not cause a burst of connections.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{TcpStream, ToSocketAddrs},
+    sync::{Arc, RwLock},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::Sender;
+use log::{debug, error, trace, warn};
+use shadowsocks_gtk_rs::util;
+
+use crate::{event::AppEvent, io::profile_loader::ProfileFolder};
+
+/// How long to wait for a connection before considering the server unreachable.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long to pause between probing two servers.
+const PROBE_SPACING: Duration = Duration::from_millis(500);
+/// How often to check the halt flag while idle.
+const HALT_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The most recently probed latency to each profile's server, by display name.
+///
+/// `None` means the server is unreachable.
+pub type Latencies = BTreeMap<String, Option<Duration>>;
+
+/// A profile to probe, as `(display name, server host, server port)`.
+type Target = (String, String, u16);
+
+/// List the profiles that can be probed, i.e. those with a known server address.
+fn probe_targets(profile_folder: &ProfileFolder) -> Vec<Target> {
+    profile_folder
+        .get_profiles()
+        .into_iter()
+        .filter_map(|p| {
+            let (host, port) = p.server_addr()?;
+            Some((p.metadata.display_name.clone(), host.to_string(), port))
+        })
+        .collect()
+}
+
+/// Measure how long it takes to connect to a server, excluding the DNS lookup.
+fn probe(host: &str, port: u16) -> io::Result<Duration> {
+    let addr = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host resolved to no address"))?;
+    let start = Instant::now();
+    TcpStream::connect_timeout(&addr, PROBE_TIMEOUT)?;
+    Ok(start.elapsed())
+}
+
+/// Sleep for the specified duration, waking up regularly to check the halt flag.
+///
+/// Returns whether the halt flag has been set.
+fn sleep_or_halt(duration: Duration, halt_flag: &RwLock<bool>) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        if *util::rwlock_read(halt_flag) {
+            return true;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        thread::sleep(HALT_CHECK_INTERVAL.min(deadline - now));
+    }
+}
+
+/// A daemon that probes the server of every profile once per interval,
+/// sending an `AppEvent::LatenciesProbed` after each round.
+///
+/// Terminates the underlying thread when dropped.
+#[derive(Debug)]
+pub struct LatencyProber {
+    /// The profiles to probe in the next round.
+    targets: Arc<RwLock<Vec<Target>>>,
+    /// Only read by the tray & the runtime API.
+    #[cfg_attr(not(any(feature = "gui", feature = "runtime-api")), allow(dead_code))]
+    latencies: Arc<RwLock<Latencies>>,
+    /// Default: false. Set to true to halt the daemon on next poll.
+    halt_flag: Arc<RwLock<bool>>,
+    /// Wrapped in `Option` so that it can be joined on drop.
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for LatencyProber {
+    fn drop(&mut self) {
+        trace!("Latency prober is getting dropped");
+        *util::rwlock_write(&self.halt_flag) = true;
+        if let Some(handle) = self.handle.take() {
+            if let Err(err) = handle.join() {
+                warn!("Latency prober daemon thread has panicked unexpectedly: {:?}", err);
+            }
+        }
+    }
+}
+
+impl LatencyProber {
+    pub fn start(profile_folder: &ProfileFolder, interval: Duration, events_tx: Sender<AppEvent>) -> io::Result<Self> {
+        let targets = Arc::new(RwLock::new(probe_targets(profile_folder)));
+        let latencies = Arc::new(RwLock::new(Latencies::new()));
+        let halt_flag = Arc::new(RwLock::new(false));
+        let targets_clone = Arc::clone(&targets);
+        let latencies_clone = Arc::clone(&latencies);
+        let halt_flag_clone = Arc::clone(&halt_flag);
+
+        let handle = thread::Builder::new()
+            .name("latency prober daemon".into())
+            .spawn(move || loop {
+                let round = util::rwlock_read(&targets_clone).clone();
+                debug!("Probing the latency to {} server(s)", round.len());
+                let mut results = Latencies::new();
+                for (name, host, port) in round {
+                    let res = match probe(&host, port) {
+                        Ok(rtt) => Some(rtt),
+                        Err(err) => {
+                            debug!(
+                                "Server {}:{} of profile \"{}\" is unreachable: {}",
+                                host, port, name, err
+                            );
+                            None
+                        }
+                    };
+                    results.insert(name, res);
+                    if sleep_or_halt(PROBE_SPACING, &halt_flag_clone) {
+                        trace!("Latency prober halt flag has been set; daemon exiting");
+                        return;
+                    }
+                }
+                *util::rwlock_write(&latencies_clone) = results;
+                if let Err(_) = events_tx.send(AppEvent::LatenciesProbed) {
+                    error!("Trying to send LatenciesProbed event, but all receivers have hung up.");
+                    return;
+                }
+
+                if sleep_or_halt(interval, &halt_flag_clone) {
+                    trace!("Latency prober halt flag has been set; daemon exiting");
+                    return;
+                }
+            })?;
+
+        Ok(Self {
+            targets,
+            latencies,
+            halt_flag,
+            handle: Some(handle),
+        })
+    }
+
+    /// Probe the profiles of a newly loaded `ProfileFolder` from the next round on.
+    pub fn set_profiles(&self, profile_folder: &ProfileFolder) {
+        *util::rwlock_write(&self.targets) = probe_targets(profile_folder);
+    }
+
+    /// Get the results of the most recent completed round.
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    pub fn latencies(&self) -> Latencies {
+        util::rwlock_read(&self.latencies).clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use super::probe;
+
+    #[test]
+    fn probe_local_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(probe("127.0.0.1", port).is_ok());
+        // nothing listens on the port once the listener is closed
+        drop(listener);
+        assert!(probe("127.0.0.1", port).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! This module contains code that writes the combined logs of `sslocal` to files on disk,
+//! so that they are still available after `ssgtk` has been restarted.
+//!
+//! Logs are written to `sslocal.log` in the configured directory. Once it grows beyond
+//! the size limit, it's renamed to `sslocal.log.1`, the previous `sslocal.log.1` to
+//! `sslocal.log.2`, and so on; the oldest file beyond the count limit is overwritten.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+/// The name of the file currently being written to; rotated files get a numeric suffix.
+const LOG_FILE_NAME: &str = "sslocal.log";
+
+/// Where & how much to write logs to disk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogFileConfig {
+    /// The directory to write log files to; created if it does not exist.
+    pub dir: PathBuf,
+    /// Rotate once the current file would grow beyond this many bytes.
+    #[serde(default = "default_max_size")]
+    pub max_size: u64,
+    /// Keep at most this many rotated files, besides the current one.
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+}
+
+fn default_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_max_files() -> usize {
+    5
+}
+
+impl LogFileConfig {
+    /// The path of the current log file, or of a rotated one if `index` is not 0.
+    fn path(&self, index: usize) -> PathBuf {
+        match index {
+            0 => self.dir.join(LOG_FILE_NAME),
+            i => self.dir.join(format!("{}.{}", LOG_FILE_NAME, i)),
+        }
+    }
+}
+
+/// A log file that is rotated once it exceeds a size limit.
+#[derive(Debug)]
+pub struct RotatingLogFile {
+    config: LogFileConfig,
+    file: File,
+    /// The current size of `file` in bytes.
+    size: u64,
+}
+
+/// Open a log file for appending, creating it if necessary.
+fn open_append(path: &Path) -> io::Result<File> {
+    // the logs may contain sensitive information, so they're only readable by the owner
+    OpenOptions::new().append(true).create(true).mode(0o600).open(path)
+}
+
+impl RotatingLogFile {
+    /// Open the current log file in the configured directory, appending to it if it exists.
+    pub fn open(config: LogFileConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let path = config.path(0);
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        info!("Writing sslocal logs to {:?}", path);
+        Ok(Self { config, file, size })
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn config(&self) -> &LogFileConfig {
+        &self.config
+    }
+
+    /// Append to the current log file, rotating first if it would grow beyond the size limit.
+    ///
+    /// A single write larger than the limit is written whole to a fresh file.
+    pub fn write_str(&mut self, s: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + s.len() as u64 > self.config.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(s.as_bytes())?;
+        self.size += s.len() as u64;
+        Ok(())
+    }
+
+    /// Shift every rotated file up by one, move the current file to `.1`, and start a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        debug!("Rotating log files in {:?}", self.config.dir);
+        match self.config.max_files {
+            // nothing to keep
+            0 => fs::remove_file(self.config.path(0))?,
+            n => {
+                for i in (0..n).rev() {
+                    match fs::rename(self.config.path(i), self.config.path(i + 1)) {
+                        Ok(_) => {}
+                        // there are fewer rotated files than allowed
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+        }
+        self.file = open_append(&self.config.path(0))?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{LogFileConfig, RotatingLogFile};
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = std::env::temp_dir().join(format!("ssgtk-log-file-test-{}", std::process::id()));
+        let config = LogFileConfig {
+            dir: dir.clone(),
+            max_size: 20,
+            max_files: 2,
+        };
+        let mut log_file = RotatingLogFile::open(config.clone()).unwrap();
+        for line in ["first line\n", "second line\n", "third line\n", "fourth line\n"] {
+            log_file.write_str(line).unwrap();
+        }
+        assert_eq!(fs::read_to_string(config.path(0)).unwrap(), "fourth line\n");
+        assert_eq!(fs::read_to_string(config.path(1)).unwrap(), "third line\n");
+        assert_eq!(fs::read_to_string(config.path(2)).unwrap(), "second line\n");
+        // the oldest file beyond the count limit is overwritten
+        assert!(!config.path(3).exists());
+
+        // appends to the existing file when reopened
+        drop(log_file);
+        let mut log_file = RotatingLogFile::open(config.clone()).unwrap();
+        log_file.write_str("more\n").unwrap();
+        assert_eq!(fs::read_to_string(config.path(0)).unwrap(), "fourth line\nmore\n");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+//! This module contains code that migrates legacy profiles
+//! to the current `profile.yaml` format.
+//!
+//! Legacy profiles are flat, i.e. they do not have a `mode` tag,
+//! and may specify addresses as single strings (e.g. `"127.0.0.1:1080"`).
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info, warn};
+use serde_yaml::{Mapping, Value};
+use shadowsocks_gtk_rs::consts::*;
+
+/// The keys whose presence (without a `mode` tag) indicate a legacy profile.
+const LEGACY_KEYS: [&str; 3] = ["local_addr", "server_addr", "config_path"];
+
+/// Check whether a parsed `profile.yaml` is in the legacy flat format.
+pub fn is_legacy_format(value: &Value) -> bool {
+    match value.as_mapping() {
+        Some(map) => !map.contains_key("mode") && LEGACY_KEYS.iter().any(|&k| map.contains_key(k)),
+        None => false,
+    }
+}
+
+/// Convert a legacy profile into the current format.
+///
+/// Returns `None` if the profile is not in the legacy format.
+pub fn migrate_value(value: &Value) -> Option<Value> {
+    if !is_legacy_format(value) {
+        return None;
+    }
+    let old = value.as_mapping()?; // verified by `is_legacy_format`
+
+    // infer mode
+    let mode = match old.contains_key("config_path") {
+        true => "config-file",
+        false => "proxy",
+    };
+
+    // `mode` goes first for readability
+    let mut new = Mapping::new();
+    new.insert("mode".into(), mode.into());
+    for (k, v) in old.iter() {
+        let v = match k.as_str() {
+            Some("local_addr") => split_socket_addr(v),
+            Some("server_addr") => split_host_port(v),
+            _ => v.clone(),
+        };
+        new.insert(k.clone(), v);
+    }
+    Some(new.into())
+}
+
+/// Convert a `"<ip>:<port>"` string into a `[ip, port]` sequence.
+///
+/// Leaves the value unchanged if it is not such a string.
+fn split_socket_addr(value: &Value) -> Value {
+    match value.as_str().and_then(|s| s.parse::<SocketAddr>().ok()) {
+        Some(addr) => vec![Value::from(addr.ip().to_string()), Value::from(addr.port())].into(),
+        None => value.clone(),
+    }
+}
+
+/// Convert a `"<host>:<port>"` string into a `[host, port]` sequence.
+///
+/// IPv6 hosts are expected to be enclosed in brackets.
+/// Leaves the value unchanged if it is not such a string.
+fn split_host_port(value: &Value) -> Value {
+    let split = value.as_str().and_then(|s| {
+        let (host, port) = s.rsplit_once(':')?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = port.parse::<u16>().ok()?;
+        Some((host.to_string(), port))
+    });
+    match split {
+        Some((host, port)) if !host.is_empty() => vec![Value::from(host), Value::from(port)].into(),
+        _ => value.clone(),
+    }
+}
+
+/// Recursively migrate all legacy profiles within the specified directory,
+/// backing up each original file alongside it.
+///
+/// Returns the paths of the migrated files.
+pub fn migrate_recurse(path: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+    let mut migrated = vec![];
+    migrate_recurse_impl(path.as_ref(), &mut migrated)?;
+    Ok(migrated)
+}
+
+fn migrate_recurse_impl(path: &Path, migrated: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() || path.join(PROFILE_IGNORE_FILE_NAME).is_file() {
+        return Ok(());
+    }
+
+    let config_path = path.join(PROFILE_CONFIG_FILE_NAME);
+    if config_path.is_file() {
+        if migrate_file(&config_path)? {
+            migrated.push(config_path);
+        }
+        return Ok(());
+    }
+
+    for ent_res in path.read_dir()? {
+        migrate_recurse_impl(&ent_res?.path(), migrated)?;
+    }
+    Ok(())
+}
+
+/// Migrate a single `profile.yaml`, if it is in the legacy format.
+///
+/// Returns whether the file has been migrated.
+fn migrate_file(config_path: &Path) -> io::Result<bool> {
+    let invalid = |err: serde_yaml::Error| io::Error::new(io::ErrorKind::InvalidData, err);
+
+    let content = fs::read_to_string(config_path)?;
+    let value: Value = match serde_yaml::from_str(&content) {
+        Ok(v) => v,
+        Err(err) => {
+            warn!("Skipping {:?} because it cannot be parsed: {}", config_path, err);
+            return Ok(false);
+        }
+    };
+    let new_value = match migrate_value(&value) {
+        Some(v) => v,
+        None => {
+            debug!("{:?} is not in the legacy format; skipping", config_path);
+            return Ok(false);
+        }
+    };
+
+    // back up original
+    let backup_path = {
+        let mut path = config_path.as_os_str().to_owned();
+        path.push(".bak");
+        PathBuf::from(path)
+    };
+    fs::copy(config_path, &backup_path)?;
+
+    // write to a temporary file first, so that the profile is never left half-written
+    let new_content = serde_yaml::to_string(&new_value).map_err(invalid)?;
+    let tmp_path = {
+        let mut path = config_path.as_os_str().to_owned();
+        path.push(".tmp");
+        PathBuf::from(path)
+    };
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(new_content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, config_path)?;
+    info!("Migrated {:?}; original backed up to {:?}", config_path, backup_path);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::{is_legacy_format, migrate_value};
+
+    #[test]
+    fn migrate_legacy_proxy() {
+        let old: Value = serde_yaml::from_str(
+            "display_name: foo\n\
+            local_addr: \"[::1]:1080\"\n\
+            server_addr: \"www.example.org:443\"\n\
+            password: bar\n\
+            encrypt_method: aes-256-gcm\n",
+        )
+        .unwrap();
+        assert!(is_legacy_format(&old));
+        let new = migrate_value(&old).unwrap();
+        assert_eq!(new["mode"], Value::from("proxy"));
+        assert_eq!(
+            new["local_addr"],
+            Value::from(vec![Value::from("::1"), Value::from(1080)])
+        );
+        assert_eq!(
+            new["server_addr"],
+            Value::from(vec![Value::from("www.example.org"), Value::from(443)])
+        );
+        assert_eq!(new["display_name"], Value::from("foo"));
+        assert!(!is_legacy_format(&new));
+    }
+    #[test]
+    fn migrate_legacy_config_file() {
+        let old: Value = serde_yaml::from_str("config_path: ./ss.json5\n").unwrap();
+        let new = migrate_value(&old).unwrap();
+        assert_eq!(new["mode"], Value::from("config-file"));
+    }
+    #[test]
+    fn skip_current_format() {
+        let current: Value = serde_yaml::from_str("mode: config-file\nconfig_path: ./ss.json5\n").unwrap();
+        assert!(migrate_value(&current).is_none());
+    }
+}
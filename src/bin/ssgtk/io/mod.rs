@@ -2,8 +2,25 @@
 
 // public members
 pub mod app_state;
+pub mod autostart;
+pub mod backlog;
+pub mod connectivity;
+pub mod json_logger;
+pub mod kill_switch;
+pub mod latency_prober;
+pub mod log_file;
+pub mod migration;
+pub mod network_watcher;
+pub mod pid_registry;
 pub mod profile_loader;
+pub mod profile_watcher;
+pub mod resume_detector;
 #[cfg(feature = "runtime-api")]
 pub mod runtime_api;
+#[cfg(feature = "systemd")]
+pub mod sd_notify;
+pub mod ss_uri;
+pub mod sslocal_error;
+pub mod sslocal_manager;
 
 // private members with re-export
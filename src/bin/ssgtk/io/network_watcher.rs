@@ -0,0 +1,207 @@
+//! This module contains code that detects when the system's network changes,
+//! e.g. when a laptop switches to a different Wi-Fi network.
+//!
+//! The network is identified by the IPv4 addresses of the interfaces that are up,
+//! and by the default routes in `/proc/net/route` & `/proc/net/ipv6_route`.
+//! These are polled, so that no D-Bus or netlink connection is needed.
+//! IPv6 addresses are deliberately left out, since privacy extensions rotate them regularly.
+
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    net::Ipv4Addr,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{bounded as bounded_channel, RecvTimeoutError, Sender};
+use log::{debug, error, info, trace, warn};
+use nix::{ifaddrs::getifaddrs, net::if_::InterfaceFlags};
+
+use crate::event::AppEvent;
+
+/// How often to check the network.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The network needs to stay unchanged for this long after a change before it's reported,
+/// so that a flapping connection does not cause a restart storm.
+const SETTLE_TIME: Duration = Duration::from_secs(5);
+
+/// A snapshot of the network configuration; it changes when the network does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct NetworkFingerprint {
+    addrs: BTreeSet<(String, Ipv4Addr)>,
+    default_routes: BTreeSet<String>,
+}
+
+impl NetworkFingerprint {
+    fn read() -> io::Result<Self> {
+        let addrs = getifaddrs()?
+            .filter(|ifa| {
+                ifa.flags.contains(InterfaceFlags::IFF_UP | InterfaceFlags::IFF_RUNNING)
+                    && !ifa.flags.contains(InterfaceFlags::IFF_LOOPBACK)
+            })
+            .filter_map(|ifa| {
+                let ip = ifa.address?.as_sockaddr_in()?.ip();
+                Some((ifa.interface_name, Ipv4Addr::from(ip)))
+            })
+            .collect();
+        let mut default_routes = BTreeSet::new();
+        default_routes.extend(default_routes_v4(&fs::read_to_string("/proc/net/route")?));
+        // the file is absent if IPv6 is disabled
+        if let Ok(table) = fs::read_to_string("/proc/net/ipv6_route") {
+            default_routes.extend(default_routes_v6(&table));
+        }
+        Ok(Self { addrs, default_routes })
+    }
+
+    /// Whether there's a way out to the internet at all.
+    fn is_connected(&self) -> bool {
+        !self.default_routes.is_empty()
+    }
+}
+
+/// Parse the default routes out of `/proc/net/route`, as `<interface> <gateway>`.
+fn default_routes_v4(table: &str) -> Vec<String> {
+    table
+        .lines()
+        .skip(1) // header
+        .filter_map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            // Iface Destination Gateway Flags RefCnt Use Metric Mask ...
+            match fields.as_slice() {
+                [iface, "00000000", gateway, _, _, _, _, "00000000", ..] => Some(format!("{} {}", iface, gateway)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Parse the default routes out of `/proc/net/ipv6_route`, as `<interface> <next hop>`.
+fn default_routes_v6(table: &str) -> Vec<String> {
+    const ANY: &str = "00000000000000000000000000000000";
+    table
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            // destination, prefix length, source, prefix length, next hop, metric, refcnt, use, flags, iface
+            match fields.as_slice() {
+                // the kernel keeps an unreachable default route on the loopback interface
+                [dest, "00", _, _, next_hop, _, _, _, _, iface] if *dest == ANY && *iface != "lo" => {
+                    Some(format!("{} {}", iface, next_hop))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// A daemon that sends an `AppEvent::NetworkChanged` whenever the system's network changes,
+/// once the new network has settled.
+///
+/// Terminates the underlying thread when dropped.
+#[derive(Debug)]
+pub struct NetworkWatcher {
+    /// Nothing is ever sent; dropping it wakes the daemon up and halts it immediately.
+    halt_tx: Option<Sender<()>>,
+    /// Wrapped in `Option` so that it can be joined on drop.
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for NetworkWatcher {
+    fn drop(&mut self) {
+        trace!("Network watcher is getting dropped");
+        drop(self.halt_tx.take());
+        if let Some(handle) = self.handle.take() {
+            if let Err(err) = handle.join() {
+                warn!("Network watcher daemon thread has panicked unexpectedly: {:?}", err);
+            }
+        }
+    }
+}
+
+impl NetworkWatcher {
+    pub fn start(events_tx: Sender<AppEvent>) -> io::Result<Self> {
+        // the network that was last reported, or that we started with
+        let mut settled = NetworkFingerprint::read()?;
+        let (halt_tx, halt_rx) = bounded_channel::<()>(0);
+
+        let handle = thread::Builder::new()
+            .name("network watcher daemon".into())
+            .spawn(move || {
+                // the most recent change, and when it was seen
+                let mut pending: Option<(NetworkFingerprint, Instant)> = None;
+                loop {
+                    // sleep until the next poll, unless halted
+                    if let Err(RecvTimeoutError::Disconnected) = halt_rx.recv_timeout(POLL_INTERVAL) {
+                        trace!("Network watcher has been halted; daemon exiting");
+                        break;
+                    }
+
+                    let current = match NetworkFingerprint::read() {
+                        Ok(fp) => fp,
+                        Err(err) => {
+                            error!(
+                                "Failed to read network configuration: {}; network watcher stopping",
+                                err
+                            );
+                            break;
+                        }
+                    };
+                    // restart the settling time on every change
+                    match &pending {
+                        Some((fp, _)) if *fp == current => {}
+                        _ if current == settled => {
+                            if pending.take().is_some() {
+                                debug!("Network has changed back; ignoring");
+                            }
+                            continue;
+                        }
+                        _ => {
+                            debug!("Network has changed; waiting for it to settle");
+                            pending = Some((current, Instant::now()));
+                            continue;
+                        }
+                    }
+
+                    let (fp, since) = pending.as_ref().unwrap(); // matched above
+                    if since.elapsed() < SETTLE_TIME || !fp.is_connected() {
+                        continue;
+                    }
+                    settled = pending.take().unwrap().0;
+                    info!("Network has changed; requesting restart");
+                    if let Err(_) = events_tx.send(AppEvent::NetworkChanged) {
+                        error!("Trying to send NetworkChanged event, but all receivers have hung up.");
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            halt_tx: Some(halt_tx),
+            handle: Some(handle),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{default_routes_v4, default_routes_v6};
+
+    #[test]
+    fn parse_default_routes() {
+        let v4 = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+";
+        assert_eq!(default_routes_v4(v4), ["wlan0 0101A8C0"]);
+
+        let v6 = "\
+fe800000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001    wlan0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00000003    wlan0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo
+";
+        assert_eq!(default_routes_v6(v6), ["wlan0 fe800000000000000000000000000001"]);
+    }
+}
@@ -0,0 +1,87 @@
+//! This module contains code that records the PIDs of launched `sslocal` processes,
+//! so that leftovers can be detected after a crash.
+//!
+//! Each running instance of `ssgtk` has its own registry file,
+//! which is placed alongside the file that identifies the instance
+//! (the runtime API socket, or the app state file).
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use fs2::FileExt;
+use itertools::Itertools;
+use log::{trace, warn};
+use nix::{errno::Errno, sys::signal::kill, unistd::Pid};
+
+/// A file recording the PIDs of the `sslocal` processes launched by an instance of `ssgtk`.
+#[derive(Debug, Clone)]
+pub struct PidRegistry {
+    path: PathBuf,
+}
+
+impl PidRegistry {
+    /// Use the registry file for the instance identified by `key_path`.
+    pub fn for_key(key_path: impl AsRef<Path>) -> Self {
+        let mut path = key_path.as_ref().as_os_str().to_owned();
+        path.push(".pids");
+        Self { path: path.into() }
+    }
+
+    /// Record newly launched PIDs.
+    pub fn add(&self, pids: &[u32]) -> io::Result<()> {
+        trace!("Adding PIDs {:?} to registry {:?}", pids, self.path);
+        self.modify(|registered| registered.extend_from_slice(pids))
+    }
+
+    /// Forget PIDs that have been cleanly stopped.
+    pub fn remove(&self, pids: &[u32]) -> io::Result<()> {
+        trace!("Removing PIDs {:?} from registry {:?}", pids, self.path);
+        self.modify(|registered| registered.retain(|pid| !pids.contains(pid)))
+    }
+
+    /// Get the registered PIDs that are still alive, i.e. leftovers from a crash
+    /// if called before any `sslocal` has been launched.
+    ///
+    /// Dead PIDs are pruned from the registry.
+    pub fn leftovers(&self) -> io::Result<Vec<u32>> {
+        let mut alive = vec![];
+        self.modify(|registered| {
+            // EPERM means the process exists, but belongs to another user, e.g. launched with `pkexec`
+            registered.retain(|&pid| kill(Pid::from_raw(pid as i32), None) != Err(Errno::ESRCH));
+            alive = registered.clone();
+        })?;
+        Ok(alive)
+    }
+
+    /// Read, modify, then write back the registry, whilst holding an exclusive lock.
+    fn modify(&self, f: impl FnOnce(&mut Vec<u32>)) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false) // content is read first
+            .open(&self.path)?;
+        file.lock_exclusive()?;
+        let res = modify_locked(&mut file, f);
+        if let Err(err) = file.unlock() {
+            warn!("Failed to unlock PID registry {:?}: {}", self.path, err);
+        }
+        res
+    }
+}
+
+fn modify_locked(file: &mut File, f: impl FnOnce(&mut Vec<u32>)) -> io::Result<()> {
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    let mut pids: Vec<u32> = content.lines().filter_map(|l| l.trim().parse().ok()).collect();
+    f(&mut pids);
+
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    let new_content = pids.iter().unique().map(|pid| format!("{}\n", pid)).join("");
+    file.write_all(new_content.as_bytes())?;
+    file.flush()
+}
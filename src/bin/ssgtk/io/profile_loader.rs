@@ -1,14 +1,17 @@
 //! This module contains code that handles profile loading.
 
 use std::{
-    collections::HashSet,
-    ffi::OsString,
+    collections::{HashMap, HashSet},
+    env,
+    ffi::{OsStr, OsString},
     fmt,
     fs::read_to_string,
     io,
-    net::{IpAddr, Ipv6Addr},
+    net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
     os::unix::prelude::IntoRawFd,
     path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
 };
 
 use derivative::Derivative;
@@ -16,30 +19,331 @@ use duct::{cmd, Handle};
 use ipnet::IpNet;
 use itertools::Itertools;
 use log::{error, info, warn};
+use nix::{sys::signal::Signal, unistd::Pid};
 use serde::{Deserialize, Serialize};
-use shadowsocks_gtk_rs::consts::*;
+use shadowsocks_gtk_rs::{consts::*, profile_tree::ProfileTreeNode};
 use which::which;
 
+use crate::io::{migration, ss_uri::SsUri, sslocal_manager::ManagerAddr};
+
 /// Optional fields which allow a config to override its profile's default metadata.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MetadataOverride {
     display_name: Option<String>,
     pwd: Option<PathBuf>,
     bin_path: Option<PathBuf>,
+    quiet: Option<bool>,
+    url: Option<String>,
 }
 
 trait ToLaunchArgs {
     fn to_launch_args(&self) -> Vec<OsString>;
 }
 
+/// Translates options into the equivalent entries of an `sslocal` JSON config file.
+trait ToConfigEntries {
+    /// Add entries to the top-level config object and the `locals` entry,
+    /// returning notes for the options that cannot be translated.
+    fn to_config_entries(&self, config: &mut JsonMap, local: &mut JsonMap) -> Vec<String>;
+}
+
+type JsonMap = serde_json::Map<String, serde_json::Value>;
+
+/// Check whether a string looks like a URL, i.e. `<scheme>://<something>`.
+fn is_plausible_url(url: &str) -> bool {
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            !scheme.is_empty()
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+                && !rest.is_empty()
+                && !rest.contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+/// Expand environment variables referenced as `$VAR` or `${VAR}` in a string.
+///
+/// `$$` is a literal `$`, as is a `$` not followed by a variable name.
+/// Returns a description of the problem if a referenced variable is unset.
+fn expand_env_vars(s: &str) -> Result<String, String> {
+    fn is_name_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_'
+    }
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find('$') {
+        expanded.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        let (name, remainder) = if let Some(after) = after.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        } else if let Some(braced) = after.strip_prefix('{') {
+            match braced.split_once('}') {
+                Some((name, remainder)) if !name.is_empty() && name.chars().all(is_name_char) => (name, remainder),
+                _ => {
+                    return Err(format!(
+                        "malformed variable reference in {:?}; use $$ for a literal $",
+                        s
+                    ))
+                }
+            }
+        } else {
+            let len = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+            after.split_at(len)
+        };
+        if name.is_empty() {
+            expanded.push('$');
+        } else {
+            let value = env::var(name).map_err(|err| format!("cannot expand ${} in {:?}: {}", name, s, err))?;
+            expanded.push_str(&value);
+        }
+        rest = remainder;
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Expand environment variables in a path, as with `expand_env_vars`.
+///
+/// Paths that are not valid UTF-8 are left unchanged.
+fn expand_env_vars_in_path(path: &mut PathBuf) -> Result<(), String> {
+    if let Some(s) = path.to_str() {
+        *path = expand_env_vars(s)?.into();
+    }
+    Ok(())
+}
+
+/// Check whether a string is an IP address or looks like a hostname,
+/// i.e. dot-separated labels of alphanumerics, hyphens and underscores.
+pub fn is_plausible_host(host: &str) -> bool {
+    if host.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    // all-numeric hosts can only be IPv4 addresses, so these are typos (e.g. `192.168.1.256`)
+    if host.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return false;
+    }
+    let host = host.strip_suffix('.').unwrap_or(host); // fully qualified
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        })
+}
+
+/// The encryption methods supported by `sslocal`.
+pub const KNOWN_CIPHERS: &[&str] = &[
+    // AEAD
+    "aes-128-gcm",
+    "aes-256-gcm",
+    "chacha20-ietf-poly1305",
+    "aes-128-ccm",
+    "aes-256-ccm",
+    "aes-128-gcm-siv",
+    "aes-256-gcm-siv",
+    "xchacha20-ietf-poly1305",
+    "sm4-gcm",
+    "sm4-ccm",
+    // AEAD 2022
+    "2022-blake3-aes-128-gcm",
+    "2022-blake3-aes-256-gcm",
+    "2022-blake3-chacha20-poly1305",
+    "2022-blake3-chacha8-poly1305",
+    // stream (deprecated)
+    "rc4-md5",
+    "aes-128-ctr",
+    "aes-192-ctr",
+    "aes-256-ctr",
+    "aes-128-cfb",
+    "aes-192-cfb",
+    "aes-256-cfb",
+    "aes-128-cfb1",
+    "aes-192-cfb1",
+    "aes-256-cfb1",
+    "aes-128-cfb8",
+    "aes-192-cfb8",
+    "aes-256-cfb8",
+    "aes-128-cfb128",
+    "aes-192-cfb128",
+    "aes-256-cfb128",
+    "aes-128-ofb",
+    "aes-192-ofb",
+    "aes-256-ofb",
+    "camellia-128-ctr",
+    "camellia-192-ctr",
+    "camellia-256-ctr",
+    "camellia-128-cfb",
+    "camellia-192-cfb",
+    "camellia-256-cfb",
+    "camellia-128-cfb1",
+    "camellia-192-cfb1",
+    "camellia-256-cfb1",
+    "camellia-128-cfb8",
+    "camellia-192-cfb8",
+    "camellia-256-cfb8",
+    "camellia-128-cfb128",
+    "camellia-192-cfb128",
+    "camellia-256-cfb128",
+    "camellia-128-ofb",
+    "camellia-192-ofb",
+    "camellia-256-ofb",
+    "chacha20-ietf",
+    // no encryption
+    "plain",
+    "none",
+    "table",
+];
+
+/// Find the known cipher most similar to an unknown one, if any is reasonably close.
+///
+/// Separators are ignored, since they are the most common source of typos.
+pub fn closest_cipher(method: &str) -> Option<&'static str> {
+    fn normalize(s: &str) -> String {
+        s.chars()
+            .filter(char::is_ascii_alphanumeric)
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    }
+    let normalized = normalize(method);
+    let max_distance = (normalized.len() / 4).max(3);
+    KNOWN_CIPHERS
+        .iter()
+        .map(|&known| (known, strsim::levenshtein(&normalized, &normalize(known))))
+        .filter(|&(_, distance)| distance <= max_distance)
+        .min_by_key(|&(_, distance)| distance)
+        .map(|(known, _)| known)
+}
+
 /// Fields for a "Config file"-type ProfileConfig.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigFileOptions {
     config_path: PathBuf,
+    overrides: Option<ConfigFileOverrides>,
 }
 impl ToLaunchArgs for ConfigFileOptions {
     fn to_launch_args(&self) -> Vec<OsString> {
-        vec!["--config".into(), (&self.config_path).into()]
+        let mut args = vec!["--config".into(), (&self.config_path).into()];
+        // overrides must come after the config file to take precedence
+        if let Some(overrides) = &self.overrides {
+            args.append(&mut overrides.to_launch_args());
+        }
+        args
+    }
+}
+
+/// Settings that override those in the config file of a "Config file"-type ProfileConfig.
+///
+/// Only settings that `sslocal` allows to be overridden on the command line are accepted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFileOverrides {
+    local_addr: Option<(LocalIp, u16)>,
+}
+impl ToLaunchArgs for ConfigFileOverrides {
+    fn to_launch_args(&self) -> Vec<OsString> {
+        let mut args = vec![];
+        // local address
+        if let Some(local_addr) = &self.local_addr {
+            args.extend_from_slice(&["--local-addr".into(), format_local_addr(local_addr).into()]);
+        }
+        args
+    }
+}
+
+/// A local IP address to listen on, optionally with an IPv6 zone,
+/// e.g. `fe80::1%eth0` or `fe80::1%2` for a link-local address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct LocalIp {
+    ip: IpAddr,
+    /// An interface name or numeric scope ID.
+    zone: Option<String>,
+}
+
+impl TryFrom<String> for LocalIp {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let (ip_str, zone) = match s.split_once('%') {
+            Some((ip_str, zone)) => (ip_str, Some(zone)),
+            None => (s.as_str(), None),
+        };
+        let ip: IpAddr = ip_str
+            .parse()
+            .map_err(|err| format!("invalid local IP address \"{}\": {}", s, err))?;
+        match (ip, zone) {
+            (_, Some("")) => Err(format!("local IP address \"{}\" has an empty zone", s)),
+            (IpAddr::V4(_), Some(_)) => Err(format!("only IPv6 addresses can have a zone, but got \"{}\"", s)),
+            (ip, zone) => Ok(Self {
+                ip,
+                zone: zone.map(String::from),
+            }),
+        }
+    }
+}
+impl From<LocalIp> for String {
+    fn from(ip: LocalIp) -> Self {
+        ip.to_string()
+    }
+}
+
+impl fmt::Display for LocalIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.zone {
+            Some(zone) => write!(f, "{}%{}", self.ip, zone),
+            None => write!(f, "{}", self.ip),
+        }
+    }
+}
+
+impl LocalIp {
+    /// The numeric scope ID of the zone, resolving interface names to their index.
+    ///
+    /// `None` if there's no zone, or if the interface does not exist.
+    fn scope_id(&self) -> Option<u32> {
+        let zone = self.zone.as_deref()?;
+        zone.parse().ok().or_else(|| nix::net::if_::if_nametoindex(zone).ok())
+    }
+
+    /// Combine with a port into a socket address, with the zone resolved if possible.
+    fn with_port(&self, port: u16) -> SocketAddr {
+        match self.ip {
+            IpAddr::V4(v4) => SocketAddrV4::new(v4, port).into(),
+            IpAddr::V6(v6) => SocketAddrV6::new(v6, port, 0, self.scope_id().unwrap_or(0)).into(),
+        }
+    }
+}
+
+/// Format a local address as accepted by `sslocal`.
+///
+/// `sslocal` only accepts numeric scope IDs, so interface names are resolved to their index.
+fn format_local_addr((ip, port): &(LocalIp, u16)) -> String {
+    match (&ip.zone, ip.scope_id()) {
+        (Some(zone), None) => {
+            warn!(
+                "Cannot resolve the zone of local address {} to an interface; passing it to sslocal as is",
+                ip
+            );
+            format!("[{}%{}]:{}", ip.ip, zone, port)
+        }
+        _ => ip.with_port(*port).to_string(),
+    }
+}
+
+/// Format a remote address as accepted by `sslocal`.
+fn format_remote_addr((a, p): (&str, u16)) -> String {
+    match a.parse::<Ipv6Addr>() {
+        Ok(_) => format!("[{}]:{}", a, p), // IPv6
+        Err(_) => format!("{}:{}", a, p),  // Domain or IPv4
     }
 }
 
@@ -47,43 +351,104 @@ impl ToLaunchArgs for ConfigFileOptions {
 #[derive(Derivative, Clone, Serialize, Deserialize)]
 #[derivative(Debug)]
 pub struct ConnectOptions {
-    local_addr: (IpAddr, u16),
+    local_addr: (LocalIp, u16),
     server_addr: (String, u16),
+    /// Exactly one of `password`, `password_env` and `password_file` must be set.
+    ///
+    /// Once loaded, this holds the resolved password regardless of its source.
+    #[serde(default)]
     #[derivative(Debug(format_with = "password_omit"))]
-    password: String,
+    password: Option<String>,
+    /// Read the password from this environment variable when the profile is loaded.
+    #[serde(default)]
+    password_env: Option<String>,
+    /// Read the password from this file when the profile is loaded.
+    ///
+    /// Relative paths are resolved against the profile's directory.
+    #[serde(default)]
+    password_file: Option<PathBuf>,
     encrypt_method: String,
+    /// Accept an `encrypt_method` not in `KNOWN_CIPHERS`, e.g. one added by a newer `sslocal`.
+    #[serde(default)]
+    allow_unknown_cipher: bool,
+    /// The DNS servers `sslocal` uses itself, e.g. `"cloudflare"` or `"8.8.8.8,1.1.1.1"`.
+    #[serde(default)]
+    dns: Option<String>,
+    /// The DNS server to query through the remote server.
+    ///
+    /// Only applies to a local of protocol "dns", which no profile mode runs yet;
+    /// therefore setting it is a load error.
+    #[serde(default)]
+    remote_dns_addr: Option<(String, u16)>,
 }
 impl ToLaunchArgs for ConnectOptions {
     fn to_launch_args(&self) -> Vec<OsString> {
         let mut args = vec![];
         // local address
-        let local_addr = {
-            let (a, p) = self.local_addr;
-            match a {
-                IpAddr::V4(v4) => format!("{}:{}", v4, p),
-                IpAddr::V6(v6) => format!("[{}]:{}", v6, p),
-            }
-        };
-        args.extend_from_slice(&["--local-addr".into(), local_addr.into()]);
+        args.extend_from_slice(&["--local-addr".into(), format_local_addr(&self.local_addr).into()]);
         // server address
-        let server_addr = {
-            let (a, p) = &self.server_addr;
-            match a.parse::<Ipv6Addr>() {
-                Ok(_) => format!("[{}]:{}", a, p), // IPv6
-                Err(_) => format!("{}:{}", a, p),  // Domain or IPv4
-            }
-        };
-        args.extend_from_slice(&["--server-addr".into(), server_addr.into()]);
+        let (a, p) = &self.server_addr;
+        args.extend_from_slice(&["--server-addr".into(), format_remote_addr((a, *p)).into()]);
         // password
-        args.extend_from_slice(&["--password".into(), (&self.password).into()]);
+        if let Some(password) = &self.password {
+            args.extend_from_slice(&["--password".into(), password.into()]);
+        }
         // encrypt_method
         args.extend_from_slice(&["--encrypt-method".into(), (&self.encrypt_method).into()]);
+        // DNS
+        if let Some(dns) = &self.dns {
+            args.extend_from_slice(&["--dns".into(), dns.into()]);
+        }
         args
     }
 }
 
+impl ToConfigEntries for ConnectOptions {
+    fn to_config_entries(&self, config: &mut JsonMap, local: &mut JsonMap) -> Vec<String> {
+        let (local_ip, local_port) = &self.local_addr;
+        local.insert("local_address".into(), local_ip.to_string().into());
+        local.insert("local_port".into(), (*local_port).into());
+        let (server, server_port) = &self.server_addr;
+        config.insert("server".into(), server.as_str().into());
+        config.insert("server_port".into(), (*server_port).into());
+        if let Some(password) = &self.password {
+            config.insert("password".into(), password.as_str().into());
+        }
+        config.insert("method".into(), self.encrypt_method.as_str().into());
+        if let Some(dns) = &self.dns {
+            config.insert("dns".into(), dns.as_str().into());
+        }
+        vec![]
+    }
+}
+
+impl ConnectOptions {
+    /// Resolve the password from whichever of its sources is set, storing it in `password`.
+    ///
+    /// Returns a description of the problem if not exactly one source is set,
+    /// or if the source cannot be read.
+    fn resolve_password(&mut self, profile_dir: &Path) -> Result<(), String> {
+        let resolved = match (&self.password, &self.password_env, &self.password_file) {
+            (Some(_), None, None) => return Ok(()),
+            (None, Some(var), None) => {
+                env::var(var).map_err(|err| format!("cannot read password from ${}: {}", var, err))?
+            }
+            (None, None, Some(file)) => {
+                let file = profile_dir.join(file);
+                let content =
+                    read_to_string(&file).map_err(|err| format!("cannot read password from {:?}: {}", file, err))?;
+                content.trim_end_matches(&['\r', '\n'][..]).to_string()
+            }
+            (None, None, None) => return Err("none of password, password_env and password_file is set".into()),
+            _ => return Err("only one of password, password_env and password_file can be set".into()),
+        };
+        self.password = Some(resolved);
+        Ok(())
+    }
+}
+
 /// Helper function for `derivative(Debug)`.
-fn password_omit(_: &str, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+fn password_omit<T>(_: &T, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
     write!(fmt, "*hidden*")
 }
 
@@ -91,9 +456,32 @@ fn password_omit(_: &str, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProxyOptions {
     // TODO: Add protocol selection
+    /// A SIP003 plugin that `sslocal` runs, e.g. `obfs-local`.
+    plugin: Option<String>,
+    /// The options passed to `plugin`; only valid if `plugin` is set.
+    plugin_opts: Option<String>,
 }
 impl ToLaunchArgs for ProxyOptions {
     fn to_launch_args(&self) -> Vec<OsString> {
+        let mut args = vec![];
+        if let Some(plugin) = &self.plugin {
+            args.extend_from_slice(&["--plugin".into(), plugin.into()]);
+        }
+        if let Some(plugin_opts) = &self.plugin_opts {
+            args.extend_from_slice(&["--plugin-opts".into(), plugin_opts.into()]);
+        }
+        args
+    }
+}
+
+impl ToConfigEntries for ProxyOptions {
+    fn to_config_entries(&self, config: &mut JsonMap, _: &mut JsonMap) -> Vec<String> {
+        if let Some(plugin) = &self.plugin {
+            config.insert("plugin".into(), plugin.as_str().into());
+        }
+        if let Some(plugin_opts) = &self.plugin_opts {
+            config.insert("plugin_opts".into(), plugin_opts.as_str().into());
+        }
         vec![]
     }
 }
@@ -103,6 +491,66 @@ impl ToLaunchArgs for ProxyOptions {
 pub struct TunOptions {
     if_name: Option<String>,
     if_addr: Option<IpNet>,
+    bypass_hooks: Option<BypassHooks>,
+    /// How to gain the privileges needed to create the TUN interface; none by default.
+    privilege_escalation: Option<PrivilegeEscalation>,
+}
+
+/// A tool that launches `sslocal` with elevated privileges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum PrivilegeEscalation {
+    /// Launch `sslocal` directly, e.g. if it has `CAP_NET_ADMIN`, or if `ssgtk` itself runs as root.
+    #[serde(rename = "none")]
+    #[strum(serialize = "none")]
+    Direct,
+    /// Launch `sslocal` with `sudo`.
+    ///
+    /// There is no terminal to prompt for a password on, so this requires a `NOPASSWD` rule;
+    /// otherwise `sudo` fails immediately instead of hanging.
+    Sudo,
+    /// Launch `sslocal` with `pkexec`, which prompts for a password using the desktop's polkit agent.
+    Pkexec,
+}
+
+impl PrivilegeEscalation {
+    /// The escalation tool and its arguments, which precede `sslocal` and its own arguments.
+    ///
+    /// Returns `None` if `sslocal` should be launched directly.
+    fn wrapper(&self) -> Option<(&'static str, Vec<OsString>)> {
+        use PrivilegeEscalation::*;
+        match self {
+            Direct => None,
+            // never prompt; stdin is not a terminal
+            Sudo => Some(("sudo", vec!["--non-interactive".into(), "--".into()])),
+            Pkexec => Some(("pkexec", vec![])),
+        }
+    }
+
+    /// Send `signal` to a process launched with this tool, which may be running as another user.
+    pub fn kill(&self, pid: u32, signal: Signal) -> io::Result<()> {
+        match self.wrapper() {
+            Some((tool, mut tool_args)) => {
+                tool_args.extend([
+                    "kill".into(),
+                    format!("-{}", signal as i32).into(),
+                    pid.to_string().into(),
+                ]);
+                cmd(tool, tool_args).stdin_null().stdout_null().run().map(|_| ())
+            }
+            None => nix::sys::signal::kill(Pid::from_raw(pid as i32), signal).map_err(io::Error::from),
+        }
+    }
+}
+
+/// Commands that temporarily route traffic around the TUN interface, and undo it.
+///
+/// Each command is a program followed by its arguments, run in the profile's working directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BypassHooks {
+    bypass: Vec<String>,
+    restore: Vec<String>,
 }
 impl ToLaunchArgs for TunOptions {
     fn to_launch_args(&self) -> Vec<OsString> {
@@ -120,15 +568,100 @@ impl ToLaunchArgs for TunOptions {
     }
 }
 
+impl ToConfigEntries for TunOptions {
+    fn to_config_entries(&self, _: &mut JsonMap, local: &mut JsonMap) -> Vec<String> {
+        local.insert("protocol".into(), "tun".into());
+        if let Some(if_name) = &self.if_name {
+            local.insert("tun_interface_name".into(), if_name.as_str().into());
+        }
+        if let Some(if_addr) = &self.if_addr {
+            local.insert("tun_interface_address".into(), if_addr.to_string().into());
+        }
+        match self.privilege_escalation {
+            Some(tool) if tool != PrivilegeEscalation::Direct => vec![format!(
+                "`privilege_escalation` is specific to ssgtk and is omitted: {}",
+                tool
+            )],
+            _ => vec![],
+        }
+    }
+}
+
+/// What to do when a `sslocal` instance fails with a non-0 exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnFailure {
+    /// Restart, up to the auto-restart limit.
+    Restart,
+    /// Stop on the first failure.
+    Halt,
+}
+
+/// A command run around the lifetime of `sslocal`; see `AdvancedOptions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleHook {
+    PreUp,
+    PostDown,
+}
+
+impl fmt::Display for LifecycleHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PreUp => write!(f, "pre_up"),
+            Self::PostDown => write!(f, "post_down"),
+        }
+    }
+}
+
+/// How long a lifecycle hook may run before it's killed, unless the profile sets its own.
+const HOOK_TIMEOUT_DEFAULT: Duration = Duration::from_secs(10);
+
 /// Extra configs for advanced users.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AdvancedOptions {
     // IMPRV: more to come
     extra_args: Option<Vec<String>>,
+    /// Enables `sslocal`'s manager interface, from which traffic statistics are read.
+    manager_addr: Option<ManagerAddr>,
+    /// A subcommand verb inserted before all other arguments (e.g. `sslocal local ...`).
+    subcommand: Option<String>,
+    /// Stop `sslocal` if it has not started listening after this many seconds.
+    ///
+    /// Overrides the default in the app state; `0` means never.
+    launch_timeout: Option<u64>,
+    /// After starting, connect to this host through the SOCKS5 listener to check connectivity.
+    probe_target: Option<(String, u16)>,
+    /// Environment variables set for `sslocal`.
+    ///
+    /// These take precedence over inherited variables of the same name.
+    env: Option<HashMap<String, String>>,
+    /// Do not let `sslocal` inherit `ssgtk`'s environment variables; only `env` is set.
+    env_clear: Option<bool>,
+    /// What to do when `sslocal` fails; restarts by default.
+    on_failure: Option<OnFailure>,
+    /// When resuming on launch, wait this many milliseconds before starting `sslocal`.
+    startup_delay_ms: Option<u64>,
+    /// When resuming on launch, wait up to this many seconds for the server address to resolve
+    /// before starting `sslocal`, e.g. for the network to come up on boot.
+    wait_for_network: Option<u64>,
+    /// Run before starting `sslocal`, e.g. to set up routes; `sslocal` is not started if this fails.
+    ///
+    /// A program followed by its arguments, run in the profile's working directory.
+    pre_up: Option<Vec<String>>,
+    /// Run after `sslocal` has stopped, e.g. to clean up routes.
+    ///
+    /// A program followed by its arguments, run in the profile's working directory.
+    post_down: Option<Vec<String>>,
+    /// Kill `pre_up` or `post_down` if it has not finished after this many seconds.
+    hook_timeout: Option<u64>,
 }
 impl ToLaunchArgs for AdvancedOptions {
     fn to_launch_args(&self) -> Vec<OsString> {
         let mut args = vec![];
+        // manager address
+        if let Some(addr) = &self.manager_addr {
+            args.extend_from_slice(&["--manager-addr".into(), addr.to_string().into()]);
+        }
         // extra args
         if let Some(extra) = &self.extra_args {
             args.append(&mut extra.iter().map_into().collect())
@@ -137,6 +670,65 @@ impl ToLaunchArgs for AdvancedOptions {
     }
 }
 
+impl ToConfigEntries for AdvancedOptions {
+    fn to_config_entries(&self, config: &mut JsonMap, _: &mut JsonMap) -> Vec<String> {
+        let mut notes = vec![];
+        match &self.manager_addr {
+            Some(ManagerAddr::Udp(addr)) => {
+                config.insert("manager_address".into(), addr.ip().to_string().into());
+                config.insert("manager_port".into(), addr.port().into());
+            }
+            Some(ManagerAddr::Unix(path)) => {
+                config.insert("manager_address".into(), path.to_string_lossy().into());
+            }
+            None => {}
+        }
+        if let Some(extra) = &self.extra_args {
+            notes.push(format!(
+                "`extra_args` cannot be translated and are omitted: {:?}",
+                extra
+            ));
+        }
+        if let Some(verb) = &self.subcommand {
+            notes.push(format!("`subcommand` cannot be translated and is omitted: {:?}", verb));
+        }
+        if let Some(target) = &self.probe_target {
+            notes.push(format!(
+                "`probe_target` is specific to ssgtk and is omitted: {:?}",
+                target
+            ));
+        }
+        if let Some(env) = &self.env {
+            let keys = env.keys().sorted().join(", ");
+            notes.push(format!("`env` cannot be translated and is omitted: {}", keys));
+        }
+        if let Some(policy) = &self.on_failure {
+            notes.push(format!(
+                "`on_failure` is specific to ssgtk and is omitted: {:?}",
+                policy
+            ));
+        }
+        if let Some(delay) = &self.startup_delay_ms {
+            notes.push(format!(
+                "`startup_delay_ms` is specific to ssgtk and is omitted: {}",
+                delay
+            ));
+        }
+        if let Some(secs) = &self.wait_for_network {
+            notes.push(format!(
+                "`wait_for_network` is specific to ssgtk and is omitted: {}",
+                secs
+            ));
+        }
+        for (name, hook) in [("pre_up", &self.pre_up), ("post_down", &self.post_down)] {
+            if let Some(hook) = hook {
+                notes.push(format!("`{}` is specific to ssgtk and is omitted: {:?}", name, hook));
+            }
+        }
+        notes
+    }
+}
+
 /// The static configuration for a profile. Represents the file on disk faithfully.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "mode", rename_all = "kebab-case")] // See https://serde.rs/enum-representations.html#internally-tagged
@@ -183,6 +775,64 @@ impl ProfileConfig {
             Tun { metadata, .. } => metadata,
         }
     }
+    fn get_connect_options(&self) -> Option<&ConnectOptions> {
+        use ProfileConfig::*;
+        match self {
+            ConfigFile { .. } => None,
+            Proxy { conn_opts, .. } => Some(conn_opts),
+            Tun { conn_opts, .. } => Some(conn_opts),
+        }
+    }
+    fn get_connect_options_mut(&mut self) -> Option<&mut ConnectOptions> {
+        use ProfileConfig::*;
+        match self {
+            ConfigFile { .. } => None,
+            Proxy { conn_opts, .. } => Some(conn_opts),
+            Tun { conn_opts, .. } => Some(conn_opts),
+        }
+    }
+    /// Expand environment variables in the fields that support it,
+    /// i.e. `pwd`, `config_path`, and `extra_args`.
+    fn expand_env_vars(&mut self) -> Result<(), String> {
+        use ProfileConfig::*;
+        let (metadata, adv_opts) = match self {
+            ConfigFile {
+                metadata,
+                opts,
+                adv_opts,
+            } => {
+                expand_env_vars_in_path(&mut opts.config_path)?;
+                (metadata, adv_opts)
+            }
+            Proxy { metadata, adv_opts, .. } => (metadata, adv_opts),
+            Tun { metadata, adv_opts, .. } => (metadata, adv_opts),
+        };
+        if let Some(pwd) = &mut metadata.pwd {
+            expand_env_vars_in_path(pwd)?;
+        }
+        if let Some(extra) = &mut adv_opts.extra_args {
+            for arg in extra.iter_mut() {
+                *arg = expand_env_vars(arg)?;
+            }
+        }
+        Ok(())
+    }
+    fn mode_name(&self) -> &'static str {
+        use ProfileConfig::*;
+        match self {
+            ConfigFile { .. } => "config-file",
+            Proxy { .. } => "proxy",
+            Tun { .. } => "tun",
+        }
+    }
+    fn get_advanced_options(&self) -> &AdvancedOptions {
+        use ProfileConfig::*;
+        match self {
+            ConfigFile { adv_opts, .. } => adv_opts,
+            Proxy { adv_opts, .. } => adv_opts,
+            Tun { adv_opts, .. } => adv_opts,
+        }
+    }
     fn to_launch_args(&self) -> Vec<OsString> {
         use ProfileConfig::*;
         match self {
@@ -221,8 +871,14 @@ impl ProfileConfig {
 #[derive(Debug, Clone)]
 pub struct ProfileMetadata {
     pub display_name: String,
+    /// The config file this profile was loaded from.
+    pub config_file: PathBuf,
     pwd: PathBuf,
     bin_path: PathBuf,
+    /// Suppress notifications for this profile's events (they are still logged).
+    pub quiet: bool,
+    /// An associated link, e.g. the provider's dashboard.
+    pub url: Option<String>,
 }
 
 /// A complete `sslocal` launch profile.
@@ -233,13 +889,333 @@ pub struct Profile {
 }
 
 impl Profile {
+    /// The address of the remote server, if specified by this profile.
+    ///
+    /// Always `None` for "config-file"-type profiles.
+    pub fn server_addr(&self) -> Option<(&str, u16)> {
+        self.config
+            .get_connect_options()
+            .map(|opts| (opts.server_addr.0.as_str(), opts.server_addr.1))
+    }
+
+    /// A short description of what this profile connects to, for display in the tray.
+    ///
+    /// Never includes the password.
+    #[cfg(feature = "gui")]
+    pub fn tooltip(&self) -> String {
+        let details = match &self.config {
+            ProfileConfig::ConfigFile { opts, .. } => format!("Config file: {}", opts.config_path.display()),
+            ProfileConfig::Proxy { conn_opts, .. } | ProfileConfig::Tun { conn_opts, .. } => {
+                let (host, port) = &conn_opts.server_addr;
+                let host = match host.contains(':') {
+                    true => format!("[{}]", host), // IPv6
+                    false => host.clone(),
+                };
+                format!("Server: {}:{}\nCipher: {}", host, port, conn_opts.encrypt_method)
+            }
+        };
+        format!("Mode: {}\n{}", self.config.mode_name(), details)
+    }
+
+    /// The address `sslocal` listens on locally, if this is a "proxy"-type or "tun"-type profile.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.config
+            .get_connect_options()
+            .map(|opts| opts.local_addr.0.with_port(opts.local_addr.1))
+    }
+
+    /// The address of the local SOCKS5 listener, if this is a "proxy"-type profile,
+    /// or a "config-file"-type profile that overrides `local_addr`.
+    pub fn socks_addr(&self) -> Option<SocketAddr> {
+        match &self.config {
+            ProfileConfig::Proxy { conn_opts, .. } => Some(conn_opts.local_addr.0.with_port(conn_opts.local_addr.1)),
+            ProfileConfig::ConfigFile { opts, .. } => opts
+                .overrides
+                .as_ref()
+                .and_then(|o| o.local_addr.as_ref())
+                .map(|(ip, port)| ip.with_port(*port)),
+            _ => None,
+        }
+    }
+
+    /// The address of `sslocal`'s manager interface, if configured.
+    pub fn manager_addr(&self) -> Option<&ManagerAddr> {
+        self.config.get_advanced_options().manager_addr.as_ref()
+    }
+
+    /// How long `sslocal` is given to start listening, if limited.
+    ///
+    /// Falls back to `default` unless the profile sets its own, where `0` means unlimited.
+    pub fn launch_timeout(&self, default: Option<Duration>) -> Option<Duration> {
+        match self.config.get_advanced_options().launch_timeout {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => default,
+        }
+    }
+
+    /// How long to wait before starting `sslocal` when resuming on launch, if at all.
+    pub fn startup_delay(&self) -> Option<Duration> {
+        self.config
+            .get_advanced_options()
+            .startup_delay_ms
+            .map(Duration::from_millis)
+    }
+
+    /// How long to wait for the server address to resolve when resuming on launch, if at all.
+    pub fn wait_for_network(&self) -> Option<Duration> {
+        self.config
+            .get_advanced_options()
+            .wait_for_network
+            .map(Duration::from_secs)
+    }
+
+    /// The tool used to launch `sslocal` with elevated privileges, if any.
+    pub fn privilege_escalation(&self) -> Option<PrivilegeEscalation> {
+        match &self.config {
+            ProfileConfig::Tun {
+                opts:
+                    TunOptions {
+                        privilege_escalation: Some(tool),
+                        ..
+                    },
+                ..
+            } if *tool != PrivilegeEscalation::Direct => Some(*tool),
+            _ => None,
+        }
+    }
+
+    /// What to do when `sslocal` fails with this profile.
+    pub fn on_failure(&self) -> OnFailure {
+        self.config
+            .get_advanced_options()
+            .on_failure
+            .unwrap_or(OnFailure::Restart)
+    }
+
+    /// Run this profile's bypass hook (or restore hook, if `enable` is false), waiting for it to finish.
+    ///
+    /// Returns `None` if this is not a "tun"-type profile with bypass hooks configured.
+    #[cfg(feature = "runtime-api")]
+    pub fn run_bypass_hook(&self, enable: bool) -> Option<io::Result<()>> {
+        let hooks = match &self.config {
+            ProfileConfig::Tun {
+                opts:
+                    TunOptions {
+                        bypass_hooks: Some(hooks),
+                        ..
+                    },
+                ..
+            } => hooks,
+            _ => return None,
+        };
+        let hook = if enable { &hooks.bypass } else { &hooks.restore };
+        let res = match hook.split_first() {
+            Some((program, args)) => cmd(program, args)
+                .dir(&self.metadata.pwd)
+                .stdin_null()
+                .run()
+                .map(|_| ()),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "hook command is empty")),
+        };
+        Some(res)
+    }
+
+    /// Whether this profile has configured one of the lifecycle hooks.
+    pub fn has_lifecycle_hook(&self, hook: LifecycleHook) -> bool {
+        let opts = self.config.get_advanced_options();
+        match hook {
+            LifecycleHook::PreUp => opts.pre_up.is_some(),
+            LifecycleHook::PostDown => opts.post_down.is_some(),
+        }
+    }
+
+    /// Run one of this profile's lifecycle hooks, waiting for it to finish.
+    ///
+    /// The hook is killed if it has not finished within its timeout.
+    ///
+    /// Returns `None` if the hook is not configured;
+    /// otherwise the combined `stdout` & `stderr` of the hook, and whether it has succeeded.
+    pub fn run_lifecycle_hook(&self, hook: LifecycleHook) -> Option<(String, io::Result<()>)> {
+        let opts = self.config.get_advanced_options();
+        let command = match hook {
+            LifecycleHook::PreUp => opts.pre_up.as_ref()?,
+            LifecycleHook::PostDown => opts.post_down.as_ref()?,
+        };
+        let timeout = opts.hook_timeout.map_or(HOOK_TIMEOUT_DEFAULT, Duration::from_secs);
+
+        let (program, args) = match command.split_first() {
+            Some(split) => split,
+            None => {
+                let err = io::Error::new(io::ErrorKind::InvalidInput, "hook command is empty");
+                return Some((String::new(), Err(err)));
+            }
+        };
+        let handle = cmd(program, args)
+            .dir(&self.metadata.pwd)
+            .stdin_null()
+            .stderr_to_stdout()
+            .stdout_capture()
+            .unchecked() // checked below, so that the output is kept
+            .start();
+        let handle = match handle {
+            Ok(handle) => handle,
+            Err(err) => return Some((String::new(), Err(err))),
+        };
+
+        let deadline = Instant::now() + timeout;
+        let timed_out = loop {
+            match handle.try_wait() {
+                Ok(Some(_)) => break false,
+                Ok(None) if Instant::now() >= deadline => {
+                    if let Err(err) = handle.kill() {
+                        return Some((String::new(), Err(err)));
+                    }
+                    break true;
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(50)),
+                Err(err) => return Some((String::new(), Err(err))),
+            }
+        };
+        let res = handle.wait().map(|out| {
+            let output = String::from_utf8_lossy(&out.stdout).into_owned();
+            let res = match (timed_out, out.status.success()) {
+                (true, _) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("did not finish within {:?}", timeout),
+                )),
+                (false, true) => Ok(()),
+                (false, false) => Err(io::Error::other(format!("exited with {}", out.status))),
+            };
+            (output, res)
+        });
+        Some(res.unwrap_or_else(|err| (String::new(), Err(err))))
+    }
+
+    /// The host to connect to through the SOCKS5 listener after starting, if configured.
+    pub fn probe_target(&self) -> Option<(&str, u16)> {
+        self.config
+            .get_advanced_options()
+            .probe_target
+            .as_ref()
+            .map(|(host, port)| (host.as_str(), *port))
+    }
+
+    /// Generate an `sslocal` config file equivalent to this profile, for use outside of `ssgtk`.
+    ///
+    /// Options that cannot be translated are noted as comments at the top.
+    /// Fails for "config-file"-type profiles, which already have a config file.
+    pub fn export_sslocal_config(&self) -> io::Result<String> {
+        let mut config = JsonMap::new();
+        let mut local = JsonMap::new();
+        let notes = match &self.config {
+            ProfileConfig::ConfigFile { opts, .. } => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "Profile \"{}\" already uses the config file {:?}",
+                        self.metadata.display_name, opts.config_path
+                    ),
+                ));
+            }
+            ProfileConfig::Proxy {
+                conn_opts,
+                opts,
+                adv_opts,
+                ..
+            } => {
+                let mut notes = conn_opts.to_config_entries(&mut config, &mut local);
+                notes.append(&mut opts.to_config_entries(&mut config, &mut local));
+                notes.append(&mut adv_opts.to_config_entries(&mut config, &mut local));
+                notes
+            }
+            ProfileConfig::Tun {
+                conn_opts,
+                opts,
+                adv_opts,
+                ..
+            } => {
+                let mut notes = conn_opts.to_config_entries(&mut config, &mut local);
+                notes.append(&mut opts.to_config_entries(&mut config, &mut local));
+                notes.append(&mut adv_opts.to_config_entries(&mut config, &mut local));
+                notes
+            }
+        };
+        config.insert("locals".into(), vec![serde_json::Value::from(local)].into());
+
+        let mut out = format!("// Exported from profile \"{}\" by ssgtk\n", self.metadata.display_name);
+        for note in notes {
+            out.push_str(&format!("// Note: {}\n", note));
+        }
+        out.push_str(&serde_json::to_string_pretty(&config)?);
+        out.push('\n');
+        Ok(out)
+    }
+
+    /// Get the `ss://` URI of the server this profile connects to, e.g. to share it with a phone.
+    ///
+    /// Only "proxy"-type profiles without a plugin can be exported, because the other ones
+    /// rely on settings that the URI cannot carry.
+    pub fn ss_uri(&self) -> io::Result<SsUri> {
+        match &self.config {
+            ProfileConfig::Proxy { opts, .. } if opts.plugin.is_some() => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Profile \"{}\" uses a plugin, which cannot be exported as a URI yet",
+                    self.metadata.display_name
+                ),
+            )),
+            ProfileConfig::Proxy { conn_opts, .. } => Ok(SsUri {
+                method: conn_opts.encrypt_method.clone(),
+                password: conn_opts.password.clone().unwrap_or_default(), // resolved on load
+                host: conn_opts.server_addr.0.clone(),
+                port: conn_opts.server_addr.1,
+                tag: Some(self.metadata.display_name.clone()),
+            }),
+            other => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Profile \"{}\" is a \"{}\"-type profile; only \"proxy\"-type profiles can be exported as a URI",
+                    self.metadata.display_name,
+                    other.mode_name()
+                ),
+            )),
+        }
+    }
+
     /// Run `sslocal` using the settings specified by this profile.
     ///
     /// If `stdout` or `stderr` is `None`, the corresponding output
     /// is redirected to`/dev/null` (discarded) by default.
     pub fn run_sslocal(&self, stdout: Option<impl IntoRawFd>, stderr: Option<impl IntoRawFd>) -> io::Result<Handle> {
         let ProfileMetadata { pwd, bin_path, .. } = &self.metadata;
-        let mut expr = cmd(bin_path, self.config.to_launch_args()).dir(pwd).stdin_null();
+        let adv_opts = self.config.get_advanced_options();
+        let args = {
+            let mut args: Vec<OsString> = vec![];
+            // subcommand verb must precede all flags
+            if let Some(verb) = &adv_opts.subcommand {
+                args.push(verb.into());
+            }
+            args.append(&mut self.config.to_launch_args());
+            args
+        };
+        let mut expr = match self.privilege_escalation().and_then(|tool| tool.wrapper()) {
+            // the escalation tool runs `sslocal` and its arguments
+            Some((tool, mut tool_args)) => {
+                tool_args.push(bin_path.into());
+                tool_args.extend(args);
+                cmd(tool, tool_args)
+            }
+            None => cmd(bin_path, args),
+        }
+        .dir(pwd)
+        .stdin_null();
+        let env = adv_opts.env.clone().unwrap_or_default();
+        expr = match adv_opts.env_clear {
+            Some(true) => expr.full_env(env),
+            // set variables override inherited ones
+            _ => env.into_iter().fold(expr, |expr, (key, val)| expr.env(key, val)),
+        };
         expr = match stdout {
             Some(fd) => expr.stdout_file(fd),
             None => expr.stdout_null(),
@@ -266,6 +1242,20 @@ pub enum ProfileLoadError {
     NotDirectory(String),
     /// The profile's config file cannot be parsed.
     ConfigParseError(serde_yaml::Error),
+    /// The profile's config file is in the legacy format, and needs to be migrated.
+    LegacyFormat(PathBuf),
+    /// The profile's server address is empty or is not a valid IP address or hostname.
+    BadServerAddr(String),
+    /// The profile's encryption method is not supported by `sslocal`.
+    UnknownCipher(String),
+    /// The profile's password sources are ambiguous, missing, or cannot be read.
+    BadPassword(String),
+    /// The profile sets plugin options without a plugin.
+    BadPlugin(String),
+    /// The profile sets an option that its mode does not run.
+    UnsupportedOption(String),
+    /// The profile references an environment variable that is unset, or references it incorrectly.
+    BadVariable(String),
     /// Cannot resolve a binary for this profile.
     BadBinary(which::Error),
     /// At least two profiles share the same name.
@@ -287,6 +1277,17 @@ impl fmt::Display for ProfileLoadError {
         match self {
             NotDirectory(s) => write!(f, "{}-NotDirectory: {}", prefix, s),
             ConfigParseError(e) => write!(f, "{}-ConfigParseError: {}", prefix, e),
+            LegacyFormat(p) => write!(
+                f,
+                "{}-LegacyFormat: {:?} is in the legacy format; run `ssgtk migrate` to update it",
+                prefix, p
+            ),
+            BadServerAddr(s) => write!(f, "{}-BadServerAddr: {}", prefix, s),
+            UnknownCipher(s) => write!(f, "{}-UnknownCipher: {}", prefix, s),
+            BadPassword(s) => write!(f, "{}-BadPassword: {}", prefix, s),
+            BadPlugin(s) => write!(f, "{}-BadPlugin: {}", prefix, s),
+            UnsupportedOption(s) => write!(f, "{}-UnsupportedOption: {}", prefix, s),
+            BadVariable(s) => write!(f, "{}-BadVariable: {}", prefix, s),
             BadBinary(e) => write!(f, "{}-BadBinary: {}", prefix, e),
             NameConflict(s) => write!(f, "{}-NameConflict: {}", prefix, s),
             NoConfigFile(s) => write!(f, "{}-NoConfigFile: {}", prefix, s),
@@ -312,11 +1313,50 @@ impl From<io::Error> for ProfileLoadError {
     }
 }
 
+/// Lists the child directories of a group directory in display order.
+///
+/// Children listed in the group's order file come first, in the listed order;
+/// the rest follow in alphabetical order. Blank lines and lines starting with `#`
+/// in the order file are skipped, and names that do not match any child directory
+/// are ignored with a warning.
+fn ordered_subdir_paths(path: &Path) -> Result<Vec<PathBuf>, ProfileLoadError> {
+    let mut paths = vec![];
+    for ent_res in path.read_dir()? {
+        let ent = ent_res?;
+        if ent.file_name() != PROFILE_GROUP_ORDER_FILE_NAME {
+            paths.push(ent.path());
+        }
+    }
+    // all paths share the same parent, so this sorts them by directory name
+    paths.sort();
+
+    let order_path = path.join(PROFILE_GROUP_ORDER_FILE_NAME);
+    if !order_path.is_file() {
+        return Ok(paths);
+    }
+    let mut ordered = vec![];
+    for name in read_to_string(&order_path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    {
+        match paths.iter().position(|p| p.file_name() == Some(OsStr::new(name))) {
+            Some(idx) => ordered.push(paths.remove(idx)),
+            None => warn!(
+                "{:?} lists \"{}\", but there is no such directory or it is listed twice; ignoring",
+                order_path, name
+            ),
+        }
+    }
+    ordered.extend(paths);
+    Ok(ordered)
+}
+
 #[derive(Derivative, Clone)]
 #[derivative(Debug)]
 pub enum ProfileFolder {
     #[derivative(Debug = "transparent")]
-    Profile(Profile),
+    Profile(Box<Profile>),
     #[derivative(Debug = "transparent")]
     Group(ProfileGroup),
 }
@@ -330,8 +1370,17 @@ impl ProfileFolder {
     /// then run the program as if there are no existing configs.
     pub fn from_path_recurse(path: impl AsRef<Path>) -> Result<Self, ProfileLoadError> {
         let mut seen_names = HashSet::new();
-        Self::from_path_recurse_impl(path.as_ref(), &mut seen_names)?
-            .ok_or(ProfileLoadError::EmptyGroup(path.as_ref().to_string_lossy().into()))
+        let folder = Self::from_path_recurse_impl(path.as_ref(), &mut seen_names)?
+            .ok_or(ProfileLoadError::EmptyGroup(path.as_ref().to_string_lossy().into()))?;
+
+        // only one instance runs at a time, so this is most likely a copy-paste mistake
+        for (addr, first, second) in folder.duplicate_local_addrs() {
+            warn!(
+                "Profiles \"{}\" and \"{}\" both listen on {}; is this intended?",
+                first, second, addr
+            );
+        }
+        Ok(folder)
     }
 
     /// Returns Ok(None) when this directory is ignored.
@@ -363,38 +1412,117 @@ impl ProfileFolder {
         let config_path = path.join(PROFILE_CONFIG_FILE_NAME);
         if config_path.is_file() {
             // config
-            let content = read_to_string(config_path)?;
-            let config: ProfileConfig = serde_yaml::from_str(&content)?;
+            let content = read_to_string(&config_path)?;
+            let mut config: ProfileConfig = match serde_yaml::from_str(&content) {
+                Ok(config) => config,
+                Err(err) => {
+                    // probe for the legacy format to give a more helpful error
+                    return match serde_yaml::from_str::<serde_yaml::Value>(&content) {
+                        Ok(v) if migration::is_legacy_format(&v) => Err(ProfileLoadError::LegacyFormat(config_path)),
+                        _ => Err(err.into()),
+                    };
+                }
+            };
 
             // metadata
             let metadata = {
+                if let Err(err) = config.expand_env_vars() {
+                    let display_name = config.get_metadata_override().display_name.as_ref();
+                    return Err(ProfileLoadError::BadVariable(format!(
+                        "profile \"{}\": {}",
+                        display_name.unwrap_or(&default_display_name),
+                        err
+                    )));
+                }
                 let mo = config.get_metadata_override().clone();
 
                 let display_name = mo.display_name.unwrap_or(default_display_name);
                 if let Some(_) = seen_names.replace(display_name.clone()) {
                     return Err(ProfileLoadError::NameConflict(display_name));
                 }
+                if let Some((host, _)) = config.get_connect_options().map(|opts| &opts.server_addr) {
+                    if !is_plausible_host(host) {
+                        return Err(ProfileLoadError::BadServerAddr(format!(
+                            "profile \"{}\" has an invalid server host {:?}",
+                            display_name, host
+                        )));
+                    }
+                }
+                if let Some(opts) = config.get_connect_options() {
+                    let method = &opts.encrypt_method;
+                    if !opts.allow_unknown_cipher && !KNOWN_CIPHERS.contains(&method.as_str()) {
+                        let suggestion = match closest_cipher(method) {
+                            Some(known) => format!("; did you mean \"{}\"?", known),
+                            None => String::new(),
+                        };
+                        return Err(ProfileLoadError::UnknownCipher(format!(
+                            "profile \"{}\" has an unknown encrypt_method {:?}{}",
+                            display_name, method, suggestion
+                        )));
+                    }
+                }
+                if let ProfileConfig::Proxy { opts, .. } = &config {
+                    if opts.plugin_opts.is_some() && opts.plugin.is_none() {
+                        return Err(ProfileLoadError::BadPlugin(format!(
+                            "profile \"{}\" sets plugin_opts without plugin",
+                            display_name
+                        )));
+                    }
+                }
+                if config
+                    .get_connect_options()
+                    .is_some_and(|opts| opts.remote_dns_addr.is_some())
+                {
+                    return Err(ProfileLoadError::UnsupportedOption(format!(
+                        "profile \"{}\" sets remote_dns_addr, which only applies to a local of protocol \"dns\"",
+                        display_name
+                    )));
+                }
+                if let Some(url) = &mo.url {
+                    if !is_plausible_url(url) {
+                        warn!("Profile \"{}\" has an implausible URL: {}", display_name, url);
+                    }
+                }
                 let pwd = mo.pwd.unwrap_or(path.clone());
                 let bin_path = mo
                     .bin_path
                     // which(&str) & which(Path) works differently
-                    .map(|p| which(p))
+                    .map(which)
                     .unwrap_or(which(SSLOCAL_LOOKUP_NAME_DEFAULT))?;
 
                 ProfileMetadata {
                     display_name,
+                    config_file: config_path,
                     pwd,
                     bin_path,
+                    quiet: mo.quiet.unwrap_or(false),
+                    url: mo.url,
                 }
             };
 
-            return Ok(Some(Self::Profile(Profile { metadata, config })));
+            // password
+            if let Some(opts) = config.get_connect_options_mut() {
+                opts.resolve_password(&path).map_err(|err| {
+                    ProfileLoadError::BadPassword(format!("profile \"{}\": {}", metadata.display_name, err))
+                })?;
+            }
+
+            let profile = Profile { metadata, config };
+            if let Some(tool) = profile.privilege_escalation() {
+                if profile.config.get_advanced_options().env.is_some() {
+                    warn!(
+                        "Profile \"{}\" sets `env`, but {} resets the environment of sslocal; it may not take effect",
+                        profile.metadata.display_name, tool
+                    );
+                }
+            }
+            return Ok(Some(Self::Profile(Box::new(profile))));
         }
 
         // otherwise, check if it contains files at all
         // if so consider it a profile that's missing the config file.
         let has_files = path.read_dir()?.any(|ent_res| match ent_res {
-            Ok(ent) => ent.path().is_file(),
+            Ok(ent) => ent.path().is_file() && ent.file_name() != PROFILE_GROUP_ORDER_FILE_NAME,
             Err(err) => {
                 warn!("Cannot open a file or directory: {}", err);
                 false
@@ -406,9 +1534,8 @@ impl ProfileFolder {
 
         // otherwise, consider it a group
         let mut subdirs = vec![];
-        for ent_res in path.read_dir()? {
+        for subdir_path in ordered_subdir_paths(&path)? {
             // recursively load all subdirectories
-            let subdir_path = ent_res?.path();
             match Self::from_path_recurse_impl(&subdir_path, seen_names) {
                 Ok(Some(cf)) => subdirs.push(cf),
                 Ok(None) => info!("Ignored a directory and its children: {:?}", subdir_path),
@@ -445,19 +1572,526 @@ impl ProfileFolder {
     pub fn get_profiles(&self) -> Vec<&Profile> {
         use ProfileFolder::*;
         match self {
-            Profile(p) => vec![p],
+            Profile(p) => vec![p.as_ref()],
             Group(g) => g.content.iter().flat_map(|pf| pf.get_profiles()).collect(),
         }
     }
 
+    /// Find the profile after (or before, if `forward` is unset) the one named `current`,
+    /// in the order they are displayed, wrapping around at either end.
+    ///
+    /// If `current` is `None` or cannot be found, returns the first (or last) profile.
+    #[cfg(feature = "runtime-api")]
+    pub fn cycle_profile(&self, current: Option<&str>, forward: bool) -> Option<&Profile> {
+        let profiles = self.get_profiles();
+        let len = profiles.len();
+        let idx = match current.and_then(|name| profiles.iter().position(|p| p.metadata.display_name == name)) {
+            Some(idx) if forward => (idx + 1) % len,
+            Some(idx) => (idx + len - 1) % len,
+            None if forward => 0,
+            None => len.checked_sub(1)?,
+        };
+        profiles.get(idx).copied()
+    }
+
+    /// Find all pairs of nested profiles that listen on the same local address,
+    /// returned as `(address, first profile name, second profile name)`.
+    fn duplicate_local_addrs(&self) -> Vec<(SocketAddr, String, String)> {
+        let mut seen: HashMap<SocketAddr, &str> = HashMap::new();
+        let mut duplicates = vec![];
+        for profile in self.get_profiles() {
+            let name = profile.metadata.display_name.as_str();
+            if let Some(addr) = profile.local_addr() {
+                match seen.get(&addr) {
+                    Some(first) => duplicates.push((addr, first.to_string(), name.to_string())),
+                    None => {
+                        seen.insert(addr, name);
+                    }
+                }
+            }
+        }
+        duplicates
+    }
+
     /// Recursively searches all the nested profiles within this `ProfileFolder`
     /// for a `Profile` with a matching name.
     pub fn lookup(&self, name: impl AsRef<str>) -> Option<&Profile> {
         use ProfileFolder::*;
         match self {
-            Profile(p) if p.metadata.display_name == name.as_ref() => Some(p),
+            Profile(p) if p.metadata.display_name == name.as_ref() => Some(p.as_ref()),
             Profile(_) => None,
             Group(g) => g.content.iter().find_map(|pf| pf.lookup(name.as_ref())),
         }
     }
+
+    /// Recursively searches all the nested profiles within this `ProfileFolder`
+    /// for the `Profile` loaded from the specified config file.
+    pub fn lookup_by_config_file(&self, path: impl AsRef<Path>) -> Option<&Profile> {
+        self.get_profiles()
+            .into_iter()
+            .find(|p| p.metadata.config_file == path.as_ref())
+    }
+
+    /// Recursively searches all the nested profiles within this `ProfileFolder`
+    /// for all `Profile`s whose name contains `pattern` (case-insensitive).
+    #[cfg(feature = "runtime-api")]
+    pub fn find(&self, pattern: impl AsRef<str>) -> Vec<&Profile> {
+        let pattern = pattern.as_ref().to_lowercase();
+        self.get_profiles()
+            .into_iter()
+            .filter(|p| p.metadata.display_name.to_lowercase().contains(&pattern))
+            .collect()
+    }
+}
+
+impl From<&ProfileFolder> for ProfileTreeNode {
+    fn from(folder: &ProfileFolder) -> Self {
+        match folder {
+            ProfileFolder::Profile(p) => Self::Profile {
+                name: p.metadata.display_name.clone(),
+                mode: p.config.mode_name().into(),
+                quiet: p.metadata.quiet,
+                url: p.metadata.url.clone(),
+            },
+            ProfileFolder::Group(g) => Self::Group {
+                name: g.display_name.clone(),
+                children: g.content.iter().map_into().collect(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, process};
+
+    use super::{
+        closest_cipher, expand_env_vars, is_plausible_host, is_plausible_url, ConnectOptions, LifecycleHook,
+        PrivilegeEscalation, ProfileFolder, ProfileLoadError, ProxyOptions, ToLaunchArgs,
+    };
+
+    #[test]
+    fn plausible_host_table() {
+        let cases = [
+            // IP addresses
+            ("127.0.0.1", true),
+            ("8.8.8.8", true),
+            ("::1", true),
+            ("2001:db8::53", true),
+            // hostnames
+            ("localhost", true),
+            ("example.org", true),
+            ("example.org.", true),
+            ("my-server_1.example.org", true),
+            ("1password.com", true),
+            // typos
+            ("", false),
+            ("192.168.1.256", false),
+            ("10.0.0", false),
+            ("10.0.0.", false),
+            ("1.2.3.4.5", false),
+            ("example..org", false),
+            ("-example.org", false),
+            ("example .org", false),
+            ("example.org:8388", false),
+        ];
+        for (host, expected) in cases {
+            assert_eq!(is_plausible_host(host), expected, "host: {:?}", host);
+        }
+    }
+    #[test]
+    fn plausible_url_table() {
+        let cases = [
+            ("https://example.org", true),
+            ("https://example.org/status?id=1", true),
+            ("mailto+x://admin", true),
+            ("tg://resolve?domain=example", true),
+            ("", false),
+            ("example.org", false),
+            ("://example.org", false),
+            ("https://", false),
+            ("ht tp://example.org", false),
+            ("https://example.org/a b", false),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(is_plausible_url(url), expected, "url: {:?}", url);
+        }
+    }
+    #[test]
+    fn closest_cipher_table() {
+        let cases = [
+            ("aes-256-gcm ", Some("aes-256-gcm")),
+            ("AES-256-GCM", Some("aes-256-gcm")),
+            ("chacha20poly1305", Some("chacha20-ietf-poly1305")),
+            ("aes-256-gmc", Some("aes-256-gcm")),
+            ("2022-blake3-aes-256", Some("2022-blake3-aes-256-gcm")),
+            ("foobar", None),
+        ];
+        for (method, expected) in cases {
+            assert_eq!(closest_cipher(method), expected, "method: {:?}", method);
+        }
+    }
+    #[test]
+    fn expand_env_vars_table() {
+        std::env::set_var("SSGTK_TEST_EXPAND", "/home/user");
+        std::env::remove_var("SSGTK_TEST_UNSET");
+        let cases = [
+            ("--no-vars", Some("--no-vars")),
+            ("$SSGTK_TEST_EXPAND/acl", Some("/home/user/acl")),
+            ("${SSGTK_TEST_EXPAND}_x", Some("/home/user_x")),
+            ("$SSGTK_TEST_EXPAND_x", None),
+            ("$$SSGTK_TEST_EXPAND", Some("$SSGTK_TEST_EXPAND")),
+            ("$$$SSGTK_TEST_EXPAND", Some("$/home/user")),
+            ("cost: 5$ / $", Some("cost: 5$ / $")),
+            ("$SSGTK_TEST_UNSET", None),
+            ("${SSGTK_TEST_EXPAND", None),
+            ("${}", None),
+            ("$(whoami)", Some("$(whoami)")),
+        ];
+        for (s, expected) in cases {
+            assert_eq!(expand_env_vars(s).ok().as_deref(), expected, "s: {:?}", s);
+        }
+    }
+    #[test]
+    fn local_addr_launch_args() {
+        let cases = [
+            ("127.0.0.1", "127.0.0.1:1080"),
+            ("::", "[::]:1080"),
+            ("2001:db8::1", "[2001:db8::1]:1080"),
+            ("fe80::1%3", "[fe80::1%3]:1080"),
+            ("fe80::1%lo", "[fe80::1%1]:1080"), // loopback is always index 1 on Linux
+        ];
+        for (ip, expected) in cases {
+            let yaml = format!(
+                r#"
+local_addr: ["{}", 1080]
+server_addr: ["www.example.org", 443]
+password: "foobar"
+encrypt_method: "aes-256-gcm"
+"#,
+                ip
+            );
+            let opts: ConnectOptions = serde_yaml::from_str(&yaml).unwrap();
+            let args = opts.to_launch_args();
+            let pos = args.iter().position(|a| a == "--local-addr").unwrap();
+            assert_eq!(args[pos + 1], expected, "ip: {:?}", ip);
+        }
+        for bad_ip in ["127.0.0.1%eth0", "fe80::1%"] {
+            let yaml = format!(r#"local_addr: ["{}", 1080]"#, bad_ip);
+            assert!(
+                serde_yaml::from_str::<ConnectOptions>(&yaml).is_err(),
+                "ip: {:?}",
+                bad_ip
+            );
+        }
+    }
+    #[test]
+    fn password_sources() {
+        let dir = std::env::temp_dir().join(format!("ssgtk-password-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("secret"), "from-file\n").unwrap();
+        let parse = |password_yaml: &str| {
+            let yaml = format!(
+                r#"
+local_addr: ["127.0.0.1", 1080]
+server_addr: ["www.example.org", 443]
+encrypt_method: "aes-256-gcm"
+{}
+"#,
+                password_yaml
+            );
+            serde_yaml::from_str::<ConnectOptions>(&yaml).unwrap()
+        };
+
+        // relative to the profile's directory, without the trailing newline
+        let mut opts = parse(r#"password_file: "secret""#);
+        opts.resolve_password(&dir).unwrap();
+        let args = opts.to_launch_args();
+        let pos = args.iter().position(|a| a == "--password").unwrap();
+        assert_eq!(args[pos + 1], "from-file");
+        assert!(!format!("{:?}", opts).contains("from-file"));
+
+        // ambiguous
+        let mut opts = parse("password: \"foobar\"\npassword_file: \"secret\"");
+        assert!(opts.resolve_password(&dir).is_err());
+
+        // missing
+        assert!(parse("").resolve_password(&dir).is_err());
+        assert!(parse(r#"password_file: "missing""#).resolve_password(&dir).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+    #[cfg(feature = "gui")]
+    #[test]
+    fn tooltip_omits_password() {
+        let dir = std::env::temp_dir().join(format!("ssgtk-tooltip-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let yaml = r#"
+mode: "proxy"
+display_name: "Tooltip"
+bin_path: "/bin/sh"
+local_addr: ["127.0.0.1", 1080]
+server_addr: ["www.example.org", 443]
+password: "foobar"
+encrypt_method: "aes-256-gcm"
+"#;
+        fs::write(dir.join("profile.yaml"), yaml).unwrap();
+
+        let folder = ProfileFolder::from_path_recurse(&dir).unwrap();
+        assert_eq!(
+            folder.lookup("Tooltip").unwrap().tooltip(),
+            "Mode: proxy\nServer: www.example.org:443\nCipher: aes-256-gcm"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn export_tun_profile() {
+        let dir = std::env::temp_dir().join(format!("ssgtk-export-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let yaml = r#"
+mode: "tun"
+display_name: "Exported"
+bin_path: "/bin/sh"
+local_addr: ["127.0.0.1", 1080]
+server_addr: ["www.example.org", 443]
+password: "foobar"
+encrypt_method: "aes-256-gcm"
+if_name: "tun0"
+if_addr: "10.13.37.1/24"
+manager_addr: "127.0.0.1:6100"
+extra_args: ["--log-without-time"]
+"#;
+        fs::write(dir.join("profile.yaml"), yaml).unwrap();
+
+        // `display_name` takes precedence over the directory's name
+        let folder = ProfileFolder::from_path_recurse(&dir).unwrap();
+        assert!(folder.lookup(dir.file_name().unwrap().to_str().unwrap()).is_none());
+        let exported = folder.lookup("Exported").unwrap().export_sslocal_config().unwrap();
+        let (comments, json): (Vec<_>, Vec<_>) = exported.lines().partition(|l| l.starts_with("//"));
+        assert_eq!(comments[0], "// Exported from profile \"Exported\" by ssgtk");
+        assert!(comments[1].contains("--log-without-time"));
+        // tun settings cannot be carried by a URI
+        let uri_res = folder.lookup("Exported").unwrap().ss_uri();
+        assert_eq!(uri_res.unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+
+        let config: serde_json::Value = serde_json::from_str(&json.join("\n")).unwrap();
+        assert_eq!(config["server"], "www.example.org");
+        assert_eq!(config["server_port"], 443);
+        assert_eq!(config["password"], "foobar");
+        assert_eq!(config["method"], "aes-256-gcm");
+        assert_eq!(config["manager_address"], "127.0.0.1");
+        assert_eq!(config["manager_port"], 6100);
+        let local = &config["locals"][0];
+        assert_eq!(local["local_address"], "127.0.0.1");
+        assert_eq!(local["local_port"], 1080);
+        assert_eq!(local["protocol"], "tun");
+        assert_eq!(local["tun_interface_name"], "tun0");
+        assert_eq!(local["tun_interface_address"], "10.13.37.1/24");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn duplicate_local_addrs() {
+        let dir = std::env::temp_dir().join(format!("ssgtk-dup-addr-test-{}", process::id()));
+        for (name, port) in [("A", 1080), ("B", 1081), ("C", 1080)] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+            let yaml = format!(
+                r#"
+mode: "proxy"
+bin_path: "/bin/sh"
+local_addr: ["127.0.0.1", {}]
+server_addr: ["www.example.org", 443]
+password: "foobar"
+encrypt_method: "aes-256-gcm"
+"#,
+                port
+            );
+            fs::write(dir.join(name).join("profile.yaml"), yaml).unwrap();
+        }
+
+        // duplicates are not an error
+        let mut dups = ProfileFolder::from_path_recurse(&dir).unwrap().duplicate_local_addrs();
+        assert_eq!(dups.len(), 1);
+        let (addr, first, second) = dups.remove(0);
+        assert_eq!(addr, "127.0.0.1:1080".parse().unwrap());
+        let mut names = [first, second];
+        names.sort();
+        assert_eq!(names, ["A".to_string(), "C".to_string()]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn remote_dns_addr_rejected() {
+        let yaml = r#"
+mode: "tun"
+bin_path: "/bin/sh"
+local_addr: ["127.0.0.1", 1080]
+server_addr: ["www.example.org", 443]
+password: "foobar"
+encrypt_method: "aes-256-gcm"
+dns: "cloudflare"
+"#;
+        let opts: ConnectOptions = serde_yaml::from_str(yaml).unwrap();
+        let args = opts.to_launch_args();
+        let pos = args.iter().position(|a| a == "--dns").unwrap();
+        assert_eq!(args[pos + 1], "cloudflare");
+
+        // no mode runs a local of protocol "dns"
+        let dir = std::env::temp_dir().join(format!("ssgtk-dns-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let yaml = format!("{}remote_dns_addr: [\"8.8.8.8\", 53]\n", yaml);
+        fs::write(dir.join("profile.yaml"), yaml).unwrap();
+        assert!(matches!(
+            ProfileFolder::from_path_recurse(&dir),
+            Err(ProfileLoadError::UnsupportedOption(_))
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn plugin_options() {
+        let with_opts = "plugin: \"obfs-local\"\nplugin_opts: \"obfs=tls;obfs-host=www.example.org\"";
+        let args = serde_yaml::from_str::<ProxyOptions>(with_opts)
+            .unwrap()
+            .to_launch_args();
+        let pos = args.iter().position(|a| a == "--plugin").unwrap();
+        assert_eq!(args[pos + 1], "obfs-local");
+        let pos = args.iter().position(|a| a == "--plugin-opts").unwrap();
+        assert_eq!(args[pos + 1], "obfs=tls;obfs-host=www.example.org");
+
+        // no plugin, no flags
+        let args = serde_yaml::from_str::<ProxyOptions>("{}").unwrap().to_launch_args();
+        assert!(args.is_empty());
+
+        // options without a plugin
+        let dir = std::env::temp_dir().join(format!("ssgtk-plugin-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let yaml = r#"
+mode: "proxy"
+bin_path: "/bin/sh"
+local_addr: ["127.0.0.1", 1080]
+server_addr: ["www.example.org", 443]
+password: "foobar"
+encrypt_method: "aes-256-gcm"
+plugin_opts: "obfs=tls"
+"#;
+        fs::write(dir.join("profile.yaml"), yaml).unwrap();
+        assert!(matches!(
+            ProfileFolder::from_path_recurse(&dir),
+            Err(ProfileLoadError::BadPlugin(_))
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+    #[cfg(feature = "runtime-api")]
+    #[test]
+    fn cycle_profile() {
+        let dir = std::env::temp_dir().join(format!("ssgtk-cycle-test-{}", process::id()));
+        for (name, port) in [("A", 1080), ("B", 1081), ("C", 1082)] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+            let yaml = format!(
+                r#"
+mode: "proxy"
+bin_path: "/bin/sh"
+local_addr: ["127.0.0.1", {}]
+server_addr: ["www.example.org", 443]
+password: "foobar"
+encrypt_method: "aes-256-gcm"
+"#,
+                port
+            );
+            fs::write(dir.join(name).join("profile.yaml"), yaml).unwrap();
+        }
+        let folder = ProfileFolder::from_path_recurse(&dir).unwrap();
+
+        let cycle = |current, forward| {
+            folder
+                .cycle_profile(current, forward)
+                .map(|p| p.metadata.display_name.as_str())
+        };
+        assert_eq!(cycle(Some("A"), true), Some("B"));
+        assert_eq!(cycle(Some("C"), true), Some("A"));
+        assert_eq!(cycle(Some("A"), false), Some("C"));
+        assert_eq!(cycle(None, true), Some("A"));
+        assert_eq!(cycle(None, false), Some("C"));
+        assert_eq!(cycle(Some("Missing"), true), Some("A"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn privilege_escalation_wrapper() {
+        let parse = |s| serde_yaml::from_str::<PrivilegeEscalation>(s).unwrap();
+        assert_eq!(parse("none").wrapper(), None);
+        assert_eq!(
+            parse("sudo").wrapper(),
+            Some(("sudo", vec!["--non-interactive".into(), "--".into()]))
+        );
+        assert_eq!(parse("pkexec").wrapper(), Some(("pkexec", vec![])));
+        assert!(serde_yaml::from_str::<PrivilegeEscalation>("doas").is_err());
+    }
+    #[test]
+    fn group_order_file() {
+        let dir = std::env::temp_dir().join(format!("ssgtk-order-test-{}", process::id()));
+        for (name, port) in [("A", 1080), ("B", 1081), ("C", 1082), ("D", 1083)] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+            let yaml = format!(
+                r#"
+mode: "proxy"
+bin_path: "/bin/sh"
+local_addr: ["127.0.0.1", {}]
+server_addr: ["www.example.org", 443]
+password: "foobar"
+encrypt_method: "aes-256-gcm"
+"#,
+                port
+            );
+            fs::write(dir.join(name).join("profile.yaml"), yaml).unwrap();
+        }
+        fs::write(dir.join(".order"), "# favourites first\nC\n\nMissing\nA\nC\n").unwrap();
+
+        let names = match ProfileFolder::from_path_recurse(&dir).unwrap() {
+            ProfileFolder::Group(g) => g
+                .content
+                .iter()
+                .map(|pf| match pf {
+                    ProfileFolder::Profile(p) => p.metadata.display_name.clone(),
+                    ProfileFolder::Group(g) => g.display_name.clone(),
+                })
+                .collect::<Vec<_>>(),
+            ProfileFolder::Profile(_) => panic!("base directory should be a group"),
+        };
+        assert_eq!(names, ["C", "A", "B", "D"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lifecycle_hooks() {
+        let dir = std::env::temp_dir().join(format!("ssgtk-hook-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let yaml = r#"
+mode: "proxy"
+display_name: "Hooked"
+bin_path: "/bin/sh"
+local_addr: ["127.0.0.1", 1080]
+server_addr: ["www.example.org", 443]
+password: "foobar"
+encrypt_method: "aes-256-gcm"
+pre_up: ["sh", "-c", "echo up; echo oops >&2"]
+post_down: ["sleep", "5"]
+hook_timeout: 1
+"#;
+        fs::write(dir.join("profile.yaml"), yaml).unwrap();
+        let folder = ProfileFolder::from_path_recurse(&dir).unwrap();
+        let profile = folder.lookup("Hooked").unwrap();
+
+        let (output, res) = profile.run_lifecycle_hook(LifecycleHook::PreUp).unwrap();
+        assert!(res.is_ok());
+        assert_eq!(output, "up\noops\n");
+        let (_, res) = profile.run_lifecycle_hook(LifecycleHook::PostDown).unwrap();
+        assert_eq!(res.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
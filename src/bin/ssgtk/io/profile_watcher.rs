@@ -0,0 +1,169 @@
+//! This module contains code that detects when profile config files are edited,
+//! so that the changes can be applied without restarting the application.
+//!
+//! The modification times of all config files in the profiles directory are polled,
+//! so that no inotify instance is needed and newly created profiles are picked up too.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use crossbeam_channel::{bounded as bounded_channel, RecvTimeoutError, Sender};
+use log::{debug, error, info, trace, warn};
+use shadowsocks_gtk_rs::consts::PROFILE_CONFIG_FILE_NAME;
+
+use crate::event::AppEvent;
+
+/// How often to check the config files.
+///
+/// A change is only reported once it has been unchanged for a whole interval,
+/// so that an editor writing a file in several steps only causes one report.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The modification times of all config files under a directory.
+type Snapshot = BTreeMap<PathBuf, SystemTime>;
+
+/// Recursively record the modification times of all config files under a directory.
+fn snapshot(dir: &Path) -> io::Result<Snapshot> {
+    fn snapshot_impl(dir: &Path, snapshot: &mut Snapshot) -> io::Result<()> {
+        for ent in dir.read_dir()? {
+            let path = ent?.path();
+            if path.is_dir() {
+                snapshot_impl(&path, snapshot)?;
+            } else if path.file_name().is_some_and(|name| name == PROFILE_CONFIG_FILE_NAME) {
+                match fs::metadata(&path).and_then(|meta| meta.modified()) {
+                    Ok(mtime) => {
+                        snapshot.insert(path, mtime);
+                    }
+                    // the file could have been removed in the meantime
+                    Err(err) => debug!("Cannot read the modification time of {:?}: {}", path, err),
+                }
+            }
+        }
+        Ok(())
+    }
+    let mut snapshot = Snapshot::new();
+    snapshot_impl(dir, &mut snapshot)?;
+    Ok(snapshot)
+}
+
+/// List the config files that have been created, modified, or removed between two snapshots.
+fn changed_paths(old: &Snapshot, new: &Snapshot) -> Vec<PathBuf> {
+    let modified_or_removed = old.iter().filter(|(path, mtime)| new.get(*path) != Some(mtime));
+    let created = new.iter().filter(|(path, _)| !old.contains_key(*path));
+    modified_or_removed
+        .chain(created)
+        .map(|(path, _)| path.clone())
+        .collect()
+}
+
+/// A daemon that sends an `AppEvent::ProfileFilesChanged` whenever
+/// config files in the profiles directory are edited.
+///
+/// Terminates the underlying thread when dropped.
+#[derive(Debug)]
+pub struct ProfileWatcher {
+    /// Nothing is ever sent; dropping it wakes the daemon up and halts it immediately.
+    halt_tx: Option<Sender<()>>,
+    /// Wrapped in `Option` so that it can be joined on drop.
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for ProfileWatcher {
+    fn drop(&mut self) {
+        trace!("Profile watcher is getting dropped");
+        drop(self.halt_tx.take());
+        if let Some(handle) = self.handle.take() {
+            if let Err(err) = handle.join() {
+                warn!("Profile watcher daemon thread has panicked unexpectedly: {:?}", err);
+            }
+        }
+    }
+}
+
+impl ProfileWatcher {
+    pub fn start(profiles_dir: impl AsRef<Path>, events_tx: Sender<AppEvent>) -> io::Result<Self> {
+        // canonicalise to match the paths of loaded profiles
+        let profiles_dir = profiles_dir.as_ref().canonicalize()?;
+        // the files that were last reported, or that we started with
+        let mut settled = snapshot(&profiles_dir)?;
+        let (halt_tx, halt_rx) = bounded_channel::<()>(0);
+
+        let handle = thread::Builder::new()
+            .name("profile watcher daemon".into())
+            .spawn(move || {
+                // the most recent change, which is reported once it's seen again unchanged
+                let mut pending: Option<Snapshot> = None;
+                loop {
+                    // sleep until the next poll, unless halted
+                    if let Err(RecvTimeoutError::Disconnected) = halt_rx.recv_timeout(POLL_INTERVAL) {
+                        trace!("Profile watcher has been halted; daemon exiting");
+                        break;
+                    }
+
+                    let current = match snapshot(&profiles_dir) {
+                        Ok(snapshot) => snapshot,
+                        Err(err) => {
+                            // e.g. a directory is being renamed; try again next time
+                            debug!("Failed to scan the profiles directory: {}", err);
+                            continue;
+                        }
+                    };
+                    if current == settled {
+                        pending = None;
+                        continue;
+                    }
+                    if pending.as_ref() != Some(&current) {
+                        debug!("Profile files have changed; waiting for writes to finish");
+                        pending = Some(current);
+                        continue;
+                    }
+
+                    let paths = changed_paths(&settled, &current);
+                    settled = current;
+                    pending = None;
+                    info!("{} profile file(s) have changed", paths.len());
+                    if let Err(_) = events_tx.send(AppEvent::ProfileFilesChanged { paths }) {
+                        error!("Trying to send ProfileFilesChanged event, but all receivers have hung up.");
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            halt_tx: Some(halt_tx),
+            handle: Some(handle),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{changed_paths, Snapshot};
+
+    #[test]
+    fn changed_paths_between_snapshots() {
+        let t0 = SystemTime::UNIX_EPOCH;
+        let t1 = t0 + Duration::from_secs(1);
+        let old: Snapshot = [("kept", t0), ("edited", t0), ("removed", t0)]
+            .into_iter()
+            .map(|(p, t)| (PathBuf::from(p), t))
+            .collect();
+        let new: Snapshot = [("kept", t0), ("edited", t1), ("created", t1)]
+            .into_iter()
+            .map(|(p, t)| (PathBuf::from(p), t))
+            .collect();
+        let mut changed = changed_paths(&old, &new);
+        changed.sort();
+        assert_eq!(changed, ["created", "edited", "removed"].map(PathBuf::from));
+    }
+}
@@ -0,0 +1,95 @@
+//! This module contains code that detects when the system resumes from suspend.
+//!
+//! `CLOCK_MONOTONIC` does not advance while the system is suspended,
+//! whereas `CLOCK_BOOTTIME` does. Therefore a growth in the difference
+//! between the two indicates that the system has just resumed.
+
+use std::{
+    io,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crossbeam_channel::{bounded as bounded_channel, RecvTimeoutError, Sender};
+use log::{error, info, trace, warn};
+use nix::time::{clock_gettime, ClockId};
+
+use crate::event::AppEvent;
+
+/// How often to check the clocks.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Suspensions shorter than this are ignored.
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// A daemon that sends an `AppEvent::Restart` whenever the system resumes from suspend.
+///
+/// Terminates the underlying thread when dropped.
+#[derive(Debug)]
+pub struct ResumeDetector {
+    /// Nothing is ever sent; dropping it wakes the daemon up and halts it immediately.
+    halt_tx: Option<Sender<()>>,
+    /// Wrapped in `Option` so that it can be joined on drop.
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for ResumeDetector {
+    fn drop(&mut self) {
+        trace!("Resume detector is getting dropped");
+        drop(self.halt_tx.take());
+        if let Some(handle) = self.handle.take() {
+            if let Err(err) = handle.join() {
+                warn!("Resume detector daemon thread has panicked unexpectedly: {:?}", err);
+            }
+        }
+    }
+}
+
+impl ResumeDetector {
+    pub fn start(events_tx: Sender<AppEvent>) -> io::Result<Self> {
+        let mut last_gap = suspended_total()?;
+        let (halt_tx, halt_rx) = bounded_channel::<()>(0);
+
+        let handle = thread::Builder::new()
+            .name("resume detector daemon".into())
+            .spawn(move || loop {
+                // sleep until the next poll, unless halted
+                if let Err(RecvTimeoutError::Disconnected) = halt_rx.recv_timeout(POLL_INTERVAL) {
+                    trace!("Resume detector has been halted; daemon exiting");
+                    break;
+                }
+
+                let gap = match suspended_total() {
+                    Ok(gap) => gap,
+                    Err(err) => {
+                        error!("Failed to read system clocks: {}; resume detector stopping", err);
+                        break;
+                    }
+                };
+                let suspended = gap.saturating_sub(last_gap);
+                last_gap = gap;
+                if suspended >= SUSPEND_THRESHOLD {
+                    info!(
+                        "System resumed after being suspended for {:?}; requesting restart",
+                        suspended
+                    );
+                    if let Err(_) = events_tx.send(AppEvent::Restart) {
+                        error!("Trying to send Restart event, but all receivers have hung up.");
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            halt_tx: Some(halt_tx),
+            handle: Some(handle),
+        })
+    }
+}
+
+/// The total amount of time the system has spent suspended since boot.
+fn suspended_total() -> io::Result<Duration> {
+    let boottime = Duration::from(clock_gettime(ClockId::CLOCK_BOOTTIME)?);
+    let monotonic = Duration::from(clock_gettime(ClockId::CLOCK_MONOTONIC)?);
+    Ok(boottime.saturating_sub(monotonic))
+}
@@ -6,25 +6,33 @@
 
 use std::{
     fmt,
-    fs::{self, File},
-    io::{self, BufRead, BufReader},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
     os::unix::net::{UnixListener, UnixStream},
     path::{Path, PathBuf},
+    process,
     sync::{Arc, RwLock},
     thread::{self, JoinHandle},
     time::Duration,
 };
 
-use crossbeam_channel::Sender;
+use crossbeam_channel::{unbounded as unbounded_channel, RecvTimeoutError, Sender};
 use fs2::FileExt;
 use log::{debug, error, trace, warn};
 use shadowsocks_gtk_rs::{runtime_api_msg::APICommand, util};
 
+/// How much longer than the stop timeout to wait for a reply to `APICommand::StopWait`.
+///
+/// Leaves room for the `post_down` hook and the final teardown.
+const STOP_WAIT_REPLY_MARGIN: Duration = Duration::from_secs(15);
+
 #[derive(Debug)]
 enum CmdError {
     IOError(io::Error),
     ParseError(json5::Error),
     SendError,
+    Unconfirmed,
+    ReplyTimeout,
 }
 
 impl fmt::Display for CmdError {
@@ -34,6 +42,8 @@ impl fmt::Display for CmdError {
             IOError(e) => write!(f, "CmdError-IOError: {}", e),
             ParseError(e) => write!(f, "CmdError-ParseError: {}", e),
             SendError => write!(f, "CmdError-SendError: Command receiver has hung up"),
+            Unconfirmed => write!(f, "CmdError-Unconfirmed: Quit requires confirmation"),
+            ReplyTimeout => write!(f, "CmdError-ReplyTimeout: Timed out waiting for a reply to the command"),
         }
     }
 }
@@ -49,41 +59,73 @@ impl From<json5::Error> for CmdError {
     }
 }
 
-/// An active listener on a unix socket that handles
-/// incoming connections and commands.
+/// An error that prevents the runtime API listener from starting.
+#[derive(Debug)]
+pub enum APIListenerError {
+    /// The socket's lock is held by another live instance.
+    AlreadyRunning {
+        socket_path: PathBuf,
+        /// The PID of the other instance, if it could be read from the lock file.
+        pid: Option<u32>,
+    },
+    IOError(io::Error),
+}
+
+impl fmt::Display for APIListenerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use APIListenerError::*;
+        match self {
+            AlreadyRunning {
+                socket_path,
+                pid: Some(pid),
+            } => write!(
+                f,
+                "APIListenerError-AlreadyRunning: another ssgtk (PID {}) is already running on {:?}",
+                pid, socket_path
+            ),
+            AlreadyRunning { socket_path, pid: None } => write!(
+                f,
+                "APIListenerError-AlreadyRunning: another ssgtk is already running on {:?}",
+                socket_path
+            ),
+            IOError(e) => write!(f, "APIListenerError-IOError: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for APIListenerError {
+    fn from(err: io::Error) -> Self {
+        Self::IOError(err)
+    }
+}
+
+/// A command received by the runtime API, alongside a channel
+/// through which a reply can be sent back to the client.
 ///
-/// Terminates the underlying listener thread when dropped.
+/// Commands that do not produce a reply should simply drop `reply_tx`.
 #[derive(Debug)]
-pub struct APIListener {
+pub struct APIRequest {
+    pub cmd: APICommand,
+    pub reply_tx: Sender<String>,
+}
+
+/// A unix socket bound by the runtime API, alongside its lock file.
+///
+/// Removes the socket file and releases the lock when dropped.
+#[derive(Debug)]
+struct BoundSocket {
     /// Saved so that we can remove it on drop.
     lock_file_path: PathBuf,
     /// Saved so that we can unlock on drop.
     lock_file: File,
     /// Saved so that we can remove it on drop.
     socket_path: PathBuf,
-    /// Default: false. Set to true to halt the listener on next poll.
-    halt_flag: Arc<RwLock<bool>>,
-    /// Wrapped in `Option` so that it can be joined on drop.
-    listener_handle: Option<JoinHandle<()>>,
+    /// Wrapped in `Option` so that it can be moved into the listener thread.
+    listener: Option<UnixListener>,
 }
 
-impl Drop for APIListener {
+impl Drop for BoundSocket {
     fn drop(&mut self) {
-        trace!("Runtime API listener is getting dropped");
-
-        // notify listener halt
-        *util::rwlock_write(&self.halt_flag) = true;
-
-        // wait for daemon threads to finish
-        if let Some(handle) = self.listener_handle.take() {
-            if let Err(err) = handle.join() {
-                warn!(
-                    "Runtime API's listener daemon thread has panicked unexpectedly: {:?}",
-                    err
-                );
-            };
-        }
-
         // remove socket file
         match fs::remove_file(&self.socket_path) {
             Ok(_) => debug!("Removed socket file at {:?}", &self.socket_path),
@@ -105,8 +147,12 @@ impl Drop for APIListener {
     }
 }
 
-impl APIListener {
-    pub fn start(bind_addr: impl AsRef<Path>, cmds_tx: Sender<APICommand>) -> io::Result<Self> {
+impl BoundSocket {
+    /// Lock and bind to a unix socket.
+    ///
+    /// The PID of this process is written to the lock file,
+    /// so that it can be reported if another instance tries to bind to the same socket.
+    fn bind(bind_addr: impl AsRef<Path>) -> Result<Self, APIListenerError> {
         // try to lock lock file
         let lock_file_path = {
             let mut path = bind_addr.as_ref().as_os_str().to_owned();
@@ -114,11 +160,30 @@ impl APIListener {
             PathBuf::from(path)
         };
         trace!("Creating and locking lock file at {:?}", lock_file_path);
-        let lock_file = File::create(&lock_file_path)?;
+        // don't truncate yet; it may contain the PID of the instance holding the lock
+        let mut lock_file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_file_path)?;
         if let Err(err) = lock_file.try_lock_exclusive() {
+            if err.raw_os_error() == fs2::lock_contended_error().raw_os_error() {
+                let mut content = String::new();
+                let pid = lock_file
+                    .read_to_string(&mut content)
+                    .ok()
+                    .and_then(|_| content.trim().parse().ok());
+                return Err(APIListenerError::AlreadyRunning {
+                    socket_path: bind_addr.as_ref().to_path_buf(),
+                    pid,
+                });
+            }
             error!("Failed to obtain lock on lock file {:?}: {}", lock_file_path, err);
-            return Err(err);
+            return Err(err.into());
         }
+        lock_file.set_len(0)?;
+        write!(lock_file, "{}", process::id())?;
 
         let socket_path = bind_addr.as_ref().to_path_buf();
         let listener = {
@@ -137,58 +202,219 @@ impl APIListener {
             listener.set_nonblocking(true)?;
             listener
         };
-        let halt_flag = RwLock::new(false).into();
-        let halt_flag_clone = Arc::clone(&halt_flag);
-
-        let listener_handle = thread::Builder::new()
-            .name("Runtime API Listener".into())
-            .spawn(move || loop {
-                thread::sleep(Duration::from_millis(10)); // 100fps
-
-                // check for halt
-                if *util::rwlock_read(&halt_flag_clone) {
-                    trace!("Runtime API halt flag has been set; daemon exiting");
-                    break;
-                }
-
-                // handle connection errors
-                let (stream, peer_addr) = match listener.accept() {
-                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue, // no connections, skip
-                    Err(err) => {
-                        warn!("Runtime API connection error: {}", err);
-                        continue;
-                    }
-                    Ok(client) => client,
-                };
-
-                // handle client
-                trace!("Accepted an incoming connection from {:?}", peer_addr);
-                if let Err(err) = handle_client(stream, &cmds_tx) {
-                    warn!("Runtime API command error: {}", err);
-                }
-            })?
-            .into();
-
-        let ret = Self {
+
+        Ok(Self {
             lock_file_path,
             lock_file,
             socket_path,
-            halt_flag,
-            listener_handle,
+            listener: Some(listener),
+        })
+    }
+}
+
+/// An active listener on one or more unix sockets that handles
+/// incoming connections and commands.
+///
+/// Terminates the underlying listener threads when dropped.
+#[derive(Debug)]
+pub struct APIListener {
+    /// Saved so that they can be cleaned up on drop.
+    sockets: Vec<BoundSocket>,
+    /// Default: false. Set to true to halt the listeners on next poll.
+    halt_flag: Arc<RwLock<bool>>,
+    /// Joined on drop.
+    listener_handles: Vec<JoinHandle<()>>,
+}
+
+impl Drop for APIListener {
+    fn drop(&mut self) {
+        trace!("Runtime API listener is getting dropped");
+
+        // notify listener halt
+        *util::rwlock_write(&self.halt_flag) = true;
+
+        // wait for daemon threads to finish
+        for handle in self.listener_handles.drain(..) {
+            if let Err(err) = handle.join() {
+                warn!(
+                    "Runtime API's listener daemon thread has panicked unexpectedly: {:?}",
+                    err
+                );
+            };
+        }
+
+        // remove socket and lock files
+        self.sockets.clear();
+    }
+}
+
+impl APIListener {
+    /// Start listening on one or more unix sockets, all of which feed into the same `cmds_tx`.
+    ///
+    /// If `require_quit_confirm` is set, `APICommand::Quit` is rejected
+    /// and only `APICommand::QuitConfirmed` will quit the application.
+    ///
+    /// `stop_timeout` is how long `sslocal` is given to exit when stopped;
+    /// replies to `APICommand::StopWait` are awaited for that long plus a margin.
+    pub fn start(
+        bind_addrs: &[impl AsRef<Path>],
+        require_quit_confirm: bool,
+        stop_timeout: Duration,
+        cmds_tx: Sender<APIRequest>,
+    ) -> Result<Self, APIListenerError> {
+        // bind all sockets first, so that nothing is left running if any fails
+        let mut sockets = bind_addrs
+            .iter()
+            .map(BoundSocket::bind)
+            .collect::<Result<Vec<_>, _>>()?;
+        let listeners: Vec<_> = sockets
+            .iter_mut()
+            .map(|socket| (socket.listener.take().unwrap(), socket.socket_path.clone())) // only taken once here
+            .collect();
+
+        // if a thread fails to spawn, dropping this halts and joins those already started
+        let mut ret = Self {
+            sockets,
+            halt_flag: Arc::new(RwLock::new(false)),
+            listener_handles: vec![],
         };
+        for (listener, socket_path) in listeners {
+            let halt_flag_clone = Arc::clone(&ret.halt_flag);
+            let cmds_tx = cmds_tx.clone();
+
+            let handle = thread::Builder::new()
+                .name(format!("Runtime API Listener on {:?}", socket_path))
+                .spawn(move || loop {
+                    thread::sleep(Duration::from_millis(10)); // 100fps
+
+                    // check for halt
+                    if *util::rwlock_read(&halt_flag_clone) {
+                        trace!(
+                            "Runtime API halt flag has been set; daemon on {:?} exiting",
+                            socket_path
+                        );
+                        break;
+                    }
+
+                    // handle connection errors
+                    let (stream, peer_addr) = match listener.accept() {
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue, // no connections, skip
+                        Err(err) => {
+                            warn!("Runtime API connection error: {}", err);
+                            continue;
+                        }
+                        Ok(client) => client,
+                    };
+
+                    // handle client on its own thread, so that an idle client doesn't hold up the others
+                    trace!("Accepted an incoming connection from {:?}", peer_addr);
+                    let cmds_tx = cmds_tx.clone();
+                    let spawn_res = thread::Builder::new()
+                        .name("Runtime API client handler".into())
+                        .spawn(move || {
+                            if let Err(err) = handle_client(stream, require_quit_confirm, stop_timeout, &cmds_tx) {
+                                warn!("Runtime API command error: {}", err);
+                            }
+                        });
+                    if let Err(err) = spawn_res {
+                        warn!("Failed to spawn a handler for a runtime API client: {}", err);
+                    }
+                })?;
+            ret.listener_handles.push(handle);
+        }
+
         Ok(ret)
     }
 }
 
-/// Handles a single client connect request.
-fn handle_client(stream: UnixStream, cmds_tx: &Sender<APICommand>) -> Result<(), CmdError> {
+/// Handles a single client connection, which may send multiple commands, one per line.
+///
+/// Commands are handled in order. A bad command is reported to the client and skipped.
+/// The connection is closed at EOF, or once the client has been idle for too long.
+fn handle_client(
+    stream: UnixStream,
+    require_quit_confirm: bool,
+    stop_timeout: Duration,
+    cmds_tx: &Sender<APIRequest>,
+) -> Result<(), CmdError> {
+    // idle timeout between lines
     stream.set_read_timeout(Some(Duration::from_secs(3)))?;
-    let cmd = {
-        let mut reader = BufReader::new(stream);
+    stream.set_write_timeout(Some(Duration::from_secs(3)))?;
+    let mut reader = BufReader::new(&stream);
+    loop {
         let mut line = String::new();
-        reader.read_line(&mut line)?;
-        json5::from_str::<APICommand>(&line)?
+        match reader.read_line(&mut line) {
+            Ok(0) => break, // EOF
+            Ok(_) => {}
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                trace!("Runtime API client has been idle for too long; closing connection");
+                break;
+            }
+            Err(err) => return Err(err.into()),
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        match handle_command(&stream, &line, require_quit_confirm, stop_timeout, cmds_tx) {
+            Ok(_) => {}
+            // nothing further can be handled either
+            Err(err @ (CmdError::IOError(_) | CmdError::SendError)) => return Err(err),
+            Err(err) => warn!("Runtime API command error: {}", err),
+        }
+    }
+    Ok(())
+}
+
+/// Handles a single line sent by the client, writing the reply (if any) back to it.
+fn handle_command(
+    mut stream: &UnixStream,
+    line: &str,
+    require_quit_confirm: bool,
+    stop_timeout: Duration,
+    cmds_tx: &Sender<APIRequest>,
+) -> Result<(), CmdError> {
+    let cmd = match json5::from_str::<APICommand>(line) {
+        Ok(cmd) => cmd,
+        Err(err) => {
+            writeln!(stream, "Error: cannot parse command: {}", err)?;
+            return Err(err.into());
+        }
     };
     debug!("Runtime API received a command: {}", cmd);
-    cmds_tx.send(cmd).map_err(|_| CmdError::SendError)
+    if require_quit_confirm && matches!(cmd, APICommand::Quit) {
+        writeln!(
+            stream,
+            "Quit rejected: confirmation is required; send `quit-confirmed` instead"
+        )?;
+        return Err(CmdError::Unconfirmed);
+    }
+    // some commands only reply once they're done, which can take a while
+    let reply_timeout = match cmd {
+        APICommand::StopWait => stop_timeout + STOP_WAIT_REPLY_MARGIN,
+        APICommand::Bypass { .. } => Duration::from_secs(60), // hooks may prompt for a password
+        APICommand::TestProfile(_) => Duration::from_secs(15),
+        _ => Duration::from_secs(3),
+    };
+    let (reply_tx, reply_rx) = unbounded_channel();
+    cmds_tx
+        .send(APIRequest { cmd, reply_tx })
+        .map_err(|_| CmdError::SendError)?;
+
+    // send reply, if any
+    match reply_rx.recv_timeout(reply_timeout) {
+        Ok(reply) => {
+            writeln!(stream, "{}", reply)?;
+            stream.flush()?;
+        }
+        Err(RecvTimeoutError::Disconnected) => trace!("Command produced no reply"),
+        Err(RecvTimeoutError::Timeout) => {
+            // tell the client, so that it doesn't mistake silence for success
+            writeln!(
+                stream,
+                "Error: timed out waiting for a reply; the command may not have completed"
+            )?;
+            return Err(CmdError::ReplyTimeout);
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,42 @@
+//! This module contains code that implements the systemd service notification protocol,
+//! so that `ssgtk` can be run as a `Type=notify` service.
+//!
+//! See `sd_notify(3)` for details.
+
+use std::{env, io, os::unix::net::UnixDatagram};
+
+use log::trace;
+use nix::sys::socket::{sendto, socket, AddressFamily, MsgFlags, SockFlag, SockType, UnixAddr};
+
+/// The environment variable systemd uses to pass the notification socket.
+const NOTIFY_SOCKET_VAR: &str = "NOTIFY_SOCKET";
+
+/// Send a state string (e.g. `READY=1`) to the service manager.
+///
+/// This is a no-op if `NOTIFY_SOCKET` is not set, i.e. if not run by systemd.
+pub fn notify(state: &str) -> io::Result<()> {
+    let socket_path = match env::var_os(NOTIFY_SOCKET_VAR) {
+        Some(path) => path,
+        None => {
+            trace!("{} is not set, skipping notification {:?}", NOTIFY_SOCKET_VAR, state);
+            return Ok(());
+        }
+    };
+    trace!("Sending notification {:?} to {:?}", state, socket_path);
+
+    match socket_path.to_str().and_then(|s| s.strip_prefix('@')) {
+        // abstract namespace socket
+        Some(name) => {
+            let addr = UnixAddr::new_abstract(name.as_bytes())?;
+            let fd = socket(AddressFamily::Unix, SockType::Datagram, SockFlag::SOCK_CLOEXEC, None)?;
+            let res = sendto(fd, state.as_bytes(), &addr, MsgFlags::empty());
+            let _ = nix::unistd::close(fd);
+            res?;
+        }
+        // filesystem socket
+        None => {
+            UnixDatagram::unbound()?.send_to(state.as_bytes(), socket_path)?;
+        }
+    }
+    Ok(())
+}
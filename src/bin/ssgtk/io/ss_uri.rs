@@ -0,0 +1,397 @@
+//! This module contains code that converts between `ss://` URIs and profiles,
+//! e.g. to import a server shared by a phone app.
+//!
+//! Both the SIP002 form (`ss://<userinfo>@<host>:<port>#<tag>`) and the legacy form
+//! (`ss://<base64 of method:password@host:port>#<tag>`) are accepted.
+//! See https://shadowsocks.org/doc/sip002.html
+
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    net::Ipv6Addr,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use serde_yaml::{Mapping, Value};
+use shadowsocks_gtk_rs::consts::PROFILE_CONFIG_FILE_NAME;
+
+use crate::io::profile_loader::{closest_cipher, is_plausible_host, KNOWN_CIPHERS};
+
+/// The server details encoded in an `ss://` URI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SsUri {
+    pub method: String,
+    pub password: String,
+    pub host: String,
+    pub port: u16,
+    /// The `#tag` fragment, which names the server.
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SsUriError {
+    /// The URI does not start with `ss://`.
+    NotSsUri,
+    /// The URI is not in either accepted form.
+    Malformed(String),
+    /// The URI's encryption method is not supported by `sslocal`.
+    UnknownCipher(String),
+    /// The URI uses a SIP003 plugin, which importing does not support yet.
+    Plugin(String),
+}
+
+impl fmt::Display for SsUriError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SsUriError::*;
+
+        let prefix = "SsUriError";
+        match self {
+            NotSsUri => write!(f, "{}-NotSsUri: the URI must start with \"ss://\"", prefix),
+            Malformed(s) => write!(f, "{}-Malformed: {}", prefix, s),
+            UnknownCipher(s) => write!(f, "{}-UnknownCipher: {}", prefix, s),
+            Plugin(s) => write!(f, "{}-Plugin: plugin {:?} is not supported", prefix, s),
+        }
+    }
+}
+
+impl From<SsUriError> for io::Error {
+    fn from(err: SsUriError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, err.to_string())
+    }
+}
+
+/// Decode base64 in either the standard or the URL-safe alphabet, with or without padding.
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a') as u32 + 26),
+            b'0'..=b'9' => Some((c - b'0') as u32 + 52),
+            b'+' | b'-' => Some(62),
+            b'/' | b'_' => Some(63),
+            _ => None,
+        }
+    }
+    let s = s.trim_end_matches('=').as_bytes();
+    if s.len() % 4 == 1 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(s.len() * 3 / 4);
+    for chunk in s.chunks(4) {
+        let mut acc = 0;
+        for &c in chunk {
+            acc = acc << 6 | value(c)?;
+        }
+        acc <<= 6 * (4 - chunk.len()) as u32;
+        bytes.extend_from_slice(&acc.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(bytes)
+}
+
+/// Decode `%XX` escapes in a URI component.
+fn decode_percent(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        match b {
+            b'%' => {
+                let hex = tail.get(..2).and_then(|h| std::str::from_utf8(h).ok())?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Encode bytes as URL-safe base64 without padding, as SIP002 recommends.
+fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut encoded = String::with_capacity((bytes.len() * 4).div_ceil(3));
+    for chunk in bytes.chunks(3) {
+        let mut buf = [0; 4];
+        buf[1..=chunk.len()].copy_from_slice(chunk);
+        let acc = u32::from_be_bytes(buf);
+        for i in 0..=chunk.len() {
+            encoded.push(ALPHABET[(acc >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+/// Escape all characters in a URI component except the unreserved ones.
+fn encode_percent(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Split `<host>:<port>`, where an IPv6 host is enclosed in brackets.
+fn split_host_port(s: &str) -> Option<(String, u16)> {
+    let (host, port) = s.rsplit_once(':')?;
+    let host = match host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        Some(v6) => v6.parse::<Ipv6Addr>().ok()?.to_string(),
+        None => host.to_string(),
+    };
+    Some((host, port.parse().ok()?))
+}
+
+impl fmt::Display for SsUri {
+    /// Format as a SIP002 URI.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let userinfo = format!("{}:{}", self.method, self.password);
+        // SIP002 requires AEAD 2022 user info to be percent-encoded instead of base64
+        let userinfo = match self.method.starts_with("2022-") {
+            true => format!("{}:{}", encode_percent(&self.method), encode_percent(&self.password)),
+            false => encode_base64(userinfo.as_bytes()),
+        };
+        let host = match self.host.parse::<Ipv6Addr>() {
+            Ok(_) => format!("[{}]", self.host),
+            Err(_) => self.host.clone(),
+        };
+        write!(f, "ss://{}@{}:{}", userinfo, host, self.port)?;
+        if let Some(tag) = &self.tag {
+            write!(f, "#{}", encode_percent(tag))?;
+        }
+        Ok(())
+    }
+}
+
+impl SsUri {
+    /// Parse an `ss://` URI, checking that its cipher is supported.
+    pub fn parse(uri: &str) -> Result<Self, SsUriError> {
+        // the URI contains the password, so it's not included in errors
+        let malformed = |what: &str| SsUriError::Malformed(format!("{} in the URI", what));
+
+        let uri = uri.trim();
+        let body = match uri.get(..5) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("ss://") => &uri[5..],
+            _ => return Err(SsUriError::NotSsUri),
+        };
+        let (body, tag) = match body.split_once('#') {
+            Some((body, tag)) => {
+                let tag = decode_percent(tag).ok_or_else(|| malformed("bad escape in the tag"))?;
+                (body, Some(tag).filter(|t| !t.is_empty()))
+            }
+            None => (body, None),
+        };
+        let (body, query) = match body.split_once('?') {
+            Some((body, query)) => (body, Some(query)),
+            None => (body, None),
+        };
+        let body = body.strip_suffix('/').unwrap_or(body);
+
+        let (userinfo, host_port) = match body.rsplit_once('@') {
+            // SIP002; the userinfo is base64 of `method:password`, or percent-encoded for AEAD 2022
+            Some((userinfo, host_port)) => {
+                let userinfo = match decode_percent(userinfo).filter(|u| u.contains(':')) {
+                    Some(plain) => plain,
+                    None => decode_base64(userinfo)
+                        .and_then(|bytes| String::from_utf8(bytes).ok())
+                        .ok_or_else(|| malformed("bad base64 in the user info"))?,
+                };
+                (userinfo, host_port.to_string())
+            }
+            // legacy; the whole body is base64 of `method:password@host:port`
+            None => {
+                let decoded = decode_base64(body)
+                    .and_then(|bytes| String::from_utf8(bytes).ok())
+                    .ok_or_else(|| malformed("bad base64"))?;
+                let (userinfo, host_port) = decoded.rsplit_once('@').ok_or_else(|| malformed("no server address"))?;
+                (userinfo.to_string(), host_port.to_string())
+            }
+        };
+        let (method, password) = userinfo.split_once(':').ok_or_else(|| malformed("no password"))?;
+        let (host, port) = split_host_port(&host_port).ok_or_else(|| malformed("bad server address"))?;
+        if !is_plausible_host(&host) {
+            return Err(malformed("bad server host"));
+        }
+
+        // only `plugin` is defined by SIP002
+        for param in query.into_iter().flat_map(|q| q.split('&')) {
+            if let Some(plugin) = param.strip_prefix("plugin=") {
+                let plugin = decode_percent(plugin).unwrap_or_else(|| plugin.into());
+                return Err(SsUriError::Plugin(plugin));
+            }
+        }
+
+        if !KNOWN_CIPHERS.contains(&method) {
+            let suggestion = match closest_cipher(method) {
+                Some(known) => format!("; did you mean \"{}\"?", known),
+                None => String::new(),
+            };
+            return Err(SsUriError::UnknownCipher(format!(
+                "unknown encrypt method {:?}{}",
+                method, suggestion
+            )));
+        }
+
+        Ok(Self {
+            method: method.into(),
+            password: password.into(),
+            host,
+            port,
+            tag,
+        })
+    }
+
+    /// The name of the imported profile: the tag if set, otherwise the server address.
+    pub fn display_name(&self) -> String {
+        self.tag
+            .clone()
+            .unwrap_or_else(|| format!("{}-{}", self.host, self.port))
+    }
+
+    /// Build the content of a "proxy"-type `profile.yaml` that connects to this server.
+    pub fn to_profile_yaml(&self, local_port: u16) -> String {
+        let pair = |a: Value, b: Value| Value::Sequence(vec![a, b]);
+        let mut map = Mapping::new();
+        map.insert("mode".into(), "proxy".into());
+        map.insert("display_name".into(), self.display_name().into());
+        map.insert("local_addr".into(), pair("127.0.0.1".into(), local_port.into()));
+        map.insert("server_addr".into(), pair(self.host.as_str().into(), self.port.into()));
+        map.insert("password".into(), self.password.as_str().into());
+        map.insert("encrypt_method".into(), self.method.as_str().into());
+        serde_yaml::to_string(&map).expect("a mapping of strings and integers always serialises")
+    }
+
+    /// Write a new profile directory for this server under `profiles_dir`,
+    /// returning the path of its config file.
+    ///
+    /// Fails if a directory of the same name already exists.
+    pub fn write_profile(&self, profiles_dir: impl AsRef<Path>, local_port: u16) -> io::Result<PathBuf> {
+        // the display name is set in the config, so the directory name only needs to be safe
+        let dir_name: String = self
+            .display_name()
+            .chars()
+            .map(|c| if c == '/' || c.is_control() { '-' } else { c })
+            .collect();
+        let dir_name = match dir_name.trim_start_matches('.') {
+            "" => "Imported".to_string(),
+            name => name.to_string(),
+        };
+        let dir = profiles_dir.as_ref().join(dir_name);
+        fs::create_dir(&dir)?; // fails if it exists
+        let config_path = dir.join(PROFILE_CONFIG_FILE_NAME);
+        // the config contains the plaintext password
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&config_path)?
+            .write_all(self.to_profile_yaml(local_port).as_bytes())?;
+        Ok(config_path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_base64, encode_base64, SsUri, SsUriError};
+
+    #[test]
+    fn base64_alphabets_and_padding() {
+        assert_eq!(decode_base64("YWVzLTEyOC1nY206dGVzdA").unwrap(), b"aes-128-gcm:test");
+        assert_eq!(decode_base64("YWVzLTEyOC1nY206dGVzdA==").unwrap(), b"aes-128-gcm:test");
+        assert_eq!(decode_base64("-_8").unwrap(), decode_base64("+/8=").unwrap());
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert!(decode_base64("YWVzL").is_none());
+        assert!(decode_base64("YW*z").is_none());
+
+        for len in 0..8 {
+            let bytes: Vec<u8> = (0..len).map(|i| 0xf0 ^ i).collect();
+            assert_eq!(decode_base64(&encode_base64(&bytes)).unwrap(), bytes, "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn parse_uri_forms() {
+        let expected = SsUri {
+            method: "aes-128-gcm".into(),
+            password: "test".into(),
+            host: "192.168.100.1".into(),
+            port: 8888,
+            tag: Some("Example 1".into()),
+        };
+        // SIP002
+        let parsed = SsUri::parse("ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888#Example%201").unwrap();
+        assert_eq!(parsed, expected);
+        // SIP002 with trailing slash and empty query
+        let parsed = SsUri::parse("ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888/?#Example%201").unwrap();
+        assert_eq!(parsed, expected);
+        // legacy
+        let parsed = SsUri::parse("ss://YWVzLTEyOC1nY206dGVzdEAxOTIuMTY4LjEwMC4xOjg4ODg#Example%201").unwrap();
+        assert_eq!(parsed, expected);
+
+        // AEAD 2022 with percent-encoded user info, and IPv6
+        let parsed = SsUri::parse(
+            "ss://2022-blake3-aes-256-gcm:YctPZ6U7xPPcU%2Bgp3u%2BE1hm8KH8aPjDnHUmBDi8yFGs%3D@[2001:db8::1]:8888",
+        )
+        .unwrap();
+        assert_eq!(parsed.method, "2022-blake3-aes-256-gcm");
+        assert_eq!(parsed.password, "YctPZ6U7xPPcU+gp3u+E1hm8KH8aPjDnHUmBDi8yFGs=");
+        assert_eq!(parsed.host, "2001:db8::1");
+        assert_eq!(parsed.tag, None);
+        assert_eq!(parsed.display_name(), "2001:db8::1-8888");
+    }
+
+    #[test]
+    fn format_round_trip() {
+        for uri in [
+            "ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888#Example%201",
+            "ss://2022-blake3-aes-256-gcm:YctPZ6U7xPPcU%2Bgp3u%2BE1hm8KH8aPjDnHUmBDi8yFGs%3D@[2001:db8::1]:8888",
+        ] {
+            let parsed = SsUri::parse(uri).unwrap();
+            assert_eq!(parsed.to_string(), uri);
+        }
+    }
+
+    #[test]
+    fn reject_bad_uris() {
+        assert_eq!(SsUri::parse("http://example.org"), Err(SsUriError::NotSsUri));
+        // bf-cfb:test@192.168.100.1:8888
+        assert!(matches!(
+            SsUri::parse("ss://YmYtY2ZiOnRlc3RAMTkyLjE2OC4xMDAuMTo4ODg4"),
+            Err(SsUriError::UnknownCipher(_))
+        ));
+        assert!(matches!(
+            SsUri::parse("ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888/?plugin=obfs-local%3Bobfs%3Dhttp"),
+            Err(SsUriError::Plugin(p)) if p == "obfs-local;obfs=http"
+        ));
+        for bad in [
+            "ss://",
+            "ss://not*base64",
+            "ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1",
+            "ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:99999",
+            "ss://YWVzLTEyOC1nY20@192.168.100.1:8888",
+            "ss://YWVzLTEyOC1nY206dGVzdA@bad host:8888",
+        ] {
+            assert!(
+                matches!(SsUri::parse(bad), Err(SsUriError::Malformed(_))),
+                "uri: {:?}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn profile_yaml_round_trip() {
+        let uri = SsUri::parse("ss://YWVzLTEyOC1nY206dGVzdA@example.org:8388#Home").unwrap();
+        let yaml: serde_yaml::Value = serde_yaml::from_str(&uri.to_profile_yaml(1080)).unwrap();
+        assert_eq!(yaml["mode"], "proxy");
+        assert_eq!(yaml["display_name"], "Home");
+        assert_eq!(yaml["server_addr"][0], "example.org");
+        assert_eq!(yaml["server_addr"][1], 8388);
+        assert_eq!(yaml["local_addr"][1], 1080);
+        assert_eq!(yaml["password"], "test");
+        assert_eq!(yaml["encrypt_method"], "aes-128-gcm");
+    }
+}
@@ -0,0 +1,93 @@
+//! This module contains code that recognises common `sslocal` failures from its output,
+//! so that they can be explained to the user in plain terms.
+
+use std::fmt;
+
+/// A category of common `sslocal` failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SslocalErrorKind {
+    /// The local address cannot be bound.
+    BindError,
+    /// The server rejects or cannot understand our traffic.
+    CipherMismatch,
+    /// The server's hostname cannot be resolved.
+    DnsFailure,
+    /// The server actively refuses the connection.
+    ConnectionRefused,
+}
+
+impl fmt::Display for SslocalErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SslocalErrorKind::*;
+        let msg = match self {
+            BindError => {
+                "sslocal cannot bind its local address.\n\
+                Check that no other program is using the port in `local_addr`, \
+                and that ports below 1024 are not used without the necessary privileges."
+            }
+            CipherMismatch => {
+                "sslocal cannot communicate with the server.\n\
+                Check that `password` and `encrypt_method` match the server's configuration."
+            }
+            DnsFailure => {
+                "sslocal cannot resolve the server's hostname.\n\
+                Check `server_addr` for typos, and that your network connection and DNS are working."
+            }
+            ConnectionRefused => {
+                "The server has refused the connection.\n\
+                Check that the port in `server_addr` is correct, and that the server is running."
+            }
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl SslocalErrorKind {
+    /// Classify a single line of `sslocal`'s output, if it is a recognised failure.
+    fn classify_line(line: &str) -> Option<Self> {
+        use SslocalErrorKind::*;
+        let line = line.to_lowercase();
+        let matches_any = |patterns: &[&str]| patterns.iter().any(|p| line.contains(p));
+
+        if matches_any(&["address already in use", "cannot assign requested address"])
+            || (line.contains("bind") && line.contains("permission denied"))
+        {
+            Some(BindError)
+        } else if matches_any(&[
+            "failed to lookup address",
+            "name or service not known",
+            "temporary failure in name resolution",
+            "no address associated with hostname",
+        ]) {
+            Some(DnsFailure)
+        } else if line.contains("connection refused") {
+            Some(ConnectionRefused)
+        } else if matches_any(&["invalid tag", "decrypt", "unsupported method", "unsupported cipher"]) {
+            Some(CipherMismatch)
+        } else {
+            None
+        }
+    }
+
+    /// Classify a failure from `sslocal`'s recent output.
+    ///
+    /// The most recent recognised line takes precedence.
+    pub fn classify(output: &str) -> Option<Self> {
+        output.lines().rev().find_map(Self::classify_line)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SslocalErrorKind;
+
+    #[test]
+    fn classify_recent_first() {
+        let output = "\
+            INFO  shadowsocks local 1.15.0 build 2022-07-31T13:16:17.181575212+00:00\n\
+            ERROR tcp tunnel 127.0.0.1:51234 -> example.org:443 (proxied) closed with error: Connection refused (os error 111)\n\
+            ERROR failed to bind 0.0.0.0:1080, error: Address already in use (os error 98)\n";
+        assert_eq!(SslocalErrorKind::classify(output), Some(SslocalErrorKind::BindError));
+        assert_eq!(SslocalErrorKind::classify("INFO listening on 127.0.0.1:1080"), None);
+    }
+}
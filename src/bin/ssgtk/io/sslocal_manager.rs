@@ -0,0 +1,158 @@
+//! This module contains code that speaks `sslocal`'s manager protocol,
+//! used to retrieve traffic statistics.
+//!
+//! The protocol is a simple datagram exchange: we send `ping`,
+//! and the manager replies with `stat: {"<port>": <bytes>, ...}`.
+
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    net::{SocketAddr, UdpSocket},
+    os::unix::net::UnixDatagram,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use shadowsocks_gtk_rs::consts::XDG_DIRS;
+
+/// Makes the reply socket path of each query unique, since several instances may be polled at once.
+static QUERY_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A path to bind the reply socket of a Unix manager query to, unique to this query.
+///
+/// It's in the XDG runtime directory, which is only accessible to the current user.
+fn reply_socket_path() -> io::Result<PathBuf> {
+    let n = QUERY_COUNTER.fetch_add(1, Ordering::Relaxed);
+    XDG_DIRS.place_runtime_file(format!("manager-reply-{}-{}.sock", process::id(), n))
+}
+
+/// The address of `sslocal`'s manager interface.
+///
+/// Either an `<IP>:<port>` UDP address, or a path to a Unix socket.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ManagerAddr {
+    Udp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl TryFrom<String> for ManagerAddr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if let Ok(addr) = s.parse() {
+            return Ok(Self::Udp(addr));
+        }
+        // without this, `<hostname>:<port>` would be silently taken as a socket path
+        let looks_like_host_port = !s.contains('/')
+            && matches!(s.rsplit_once(':'), Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok());
+        if looks_like_host_port {
+            return Err(format!(
+                "manager address \"{}\" is not supported: use an IP address (e.g. \"127.0.0.1:6100\") \
+                instead of a hostname, or a path containing '/' for a Unix socket",
+                s
+            ));
+        }
+        Ok(Self::Unix(s.into()))
+    }
+}
+impl From<ManagerAddr> for String {
+    fn from(addr: ManagerAddr) -> Self {
+        addr.to_string()
+    }
+}
+
+impl fmt::Display for ManagerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManagerAddr::Udp(addr) => write!(f, "{}", addr),
+            ManagerAddr::Unix(path) => write!(f, "{}", path.to_string_lossy()),
+        }
+    }
+}
+
+impl ManagerAddr {
+    /// Send a `ping` to the manager and parse its `stat` reply,
+    /// returning the total bytes transferred across all ports.
+    pub fn query_bytes_transferred(&self, timeout: Duration) -> io::Result<u64> {
+        let mut buf = [0u8; 4096];
+        let len = match self {
+            ManagerAddr::Udp(addr) => {
+                let bind_addr: SocketAddr = match addr {
+                    SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+                    SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+                };
+                let socket = UdpSocket::bind(bind_addr)?;
+                socket.set_read_timeout(Some(timeout))?;
+                socket.send_to(b"ping", addr)?;
+                socket.recv(&mut buf)?
+            }
+            ManagerAddr::Unix(path) => {
+                // datagram replies can only be received on a bound socket
+                let local_path = reply_socket_path()?;
+                // may be left over by a crashed process with the same PID
+                let _ = fs::remove_file(&local_path);
+                let socket = UnixDatagram::bind(&local_path)?;
+                let res = (|| {
+                    socket.set_read_timeout(Some(timeout))?;
+                    socket.send_to(b"ping", path)?;
+                    socket.recv(&mut buf)
+                })();
+                let _ = fs::remove_file(&local_path);
+                res?
+            }
+        };
+        parse_stat_reply(&String::from_utf8_lossy(&buf[..len]))
+    }
+}
+
+/// Parse a reply in the form of `stat: {"<port>": <bytes>, ...}`.
+fn parse_stat_reply(reply: &str) -> io::Result<u64> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let body = reply
+        .trim()
+        .strip_prefix("stat:")
+        .ok_or_else(|| invalid(format!("Unexpected manager reply: {}", reply)))?;
+    // JSON is a subset of YAML
+    let per_port: HashMap<String, u64> =
+        serde_yaml::from_str(body).map_err(|err| invalid(format!("Malformed manager stat: {}", err)))?;
+    Ok(per_port.values().sum())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_stat_reply, ManagerAddr};
+
+    #[test]
+    fn manager_addr_parse() {
+        let parse = |s: &str| ManagerAddr::try_from(s.to_string());
+        assert_eq!(
+            parse("127.0.0.1:6100"),
+            Ok(ManagerAddr::Udp(([127, 0, 0, 1], 6100).into()))
+        );
+        assert!(matches!(parse("[::1]:6100"), Ok(ManagerAddr::Udp(_))));
+        assert_eq!(
+            parse("/run/ss-manager.sock"),
+            Ok(ManagerAddr::Unix("/run/ss-manager.sock".into()))
+        );
+        assert_eq!(
+            parse("ss-manager.sock"),
+            Ok(ManagerAddr::Unix("ss-manager.sock".into()))
+        );
+        assert!(parse("localhost:6100").is_err());
+    }
+    #[test]
+    fn stat_reply_sum() {
+        assert_eq!(parse_stat_reply(r#"stat: {"8388": 1024, "8389": 976}"#).unwrap(), 2000);
+        assert_eq!(parse_stat_reply("stat: {}\n").unwrap(), 0);
+    }
+    #[test]
+    fn stat_reply_malformed() {
+        assert!(parse_stat_reply("pong").is_err());
+        assert!(parse_stat_reply(r#"stat: {"8388": "lots"}"#).is_err());
+        assert!(parse_stat_reply(r#"stat: {"8388": 1024"#).is_err());
+    }
+}
@@ -1,14 +1,34 @@
-use gui::app::{self, AppStartError};
-use log::{error, SetLoggerError};
-use notify_rust::Urgency;
-use shadowsocks_gtk_rs::consts::*;
+use std::{fs::OpenOptions, io::Write, os::unix::fs::OpenOptionsExt};
 
-use crate::gui::notification::notify_toast;
+use app::AppStartError;
+use clap::IntoApp;
+use clap_def::{CliArgs, SubCmd};
+#[cfg(feature = "runtime-api")]
+use io::runtime_api::APIListenerError;
+use io::{
+    autostart,
+    json_logger::{JsonLogger, LogFormat},
+    migration,
+    profile_loader::ProfileFolder,
+    ss_uri::SsUri,
+};
+#[cfg(feature = "gui")]
+use log::error;
+use log::SetLoggerError;
+#[cfg(feature = "gui")]
+use notify_rust::{Timeout, Urgency};
+use shadowsocks_gtk_rs::{consts::*, profile_tree::ProfileTreeNode};
 
+#[cfg(feature = "gui")]
+use crate::notification::notify_toast;
+
+mod app;
 mod clap_def;
 mod event;
+#[cfg(feature = "gui")]
 mod gui;
 mod io;
+mod notification;
 mod profile_manager;
 
 fn main() -> Result<(), AppStartError> {
@@ -16,23 +36,128 @@ fn main() -> Result<(), AppStartError> {
     let args = clap_def::parse_and_validate();
 
     // init logger
-    logger_init(args.verbose as i32 - args.quiet as i32).unwrap(); // never produces error on first call of init
+    logger_init(args.verbose as i32 - args.quiet as i32, args.log_format).unwrap(); // never produces error on first call of init
+
+    // run one-off subcommands
+    if let Some(sub_cmd) = &args.sub_cmd {
+        return run_sub_cmd(sub_cmd, &args);
+    }
 
     // start app
     let start_res = app::run(&args);
+    #[cfg(feature = "runtime-api")]
+    if let Err(AppStartError::APIListenerError(APIListenerError::AlreadyRunning { socket_path, pid })) = &start_res {
+        let owner = match pid {
+            Some(pid) => format!("Another ssgtk (PID {})", pid),
+            None => "Another ssgtk".into(),
+        };
+        let msg = format!(
+            "{} is already running on {:?}; use ssgtkctl to control it, or use a different --api-socket",
+            owner, socket_path
+        );
+        eprintln!("{}", msg);
+        #[cfg(feature = "gui")]
+        let _ = notify_toast(Urgency::Normal, Timeout::Default, "Already Running", &msg);
+        std::process::exit(1);
+    }
+    #[cfg(feature = "gui")]
+    if let Err(err @ AppStartError::NoDisplay(_)) = &start_res {
+        // toasts are of no use without a display, so only print the diagnostic
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+    #[cfg(feature = "gui")]
     if let Err(ref err) = start_res {
         error!("ssgtk failed to load, sending notification");
         let text_2 = format!("Error: {}", err);
         // if this fails, too bad
-        let _ = notify_toast(Urgency::Critical, "Failed to start", &text_2);
+        let _ = notify_toast(Urgency::Critical, Timeout::Default, "Failed to start", &text_2);
     }
     start_res
 }
 
-fn logger_init(relative_verbosity: i32) -> Result<(), SetLoggerError> {
+fn run_sub_cmd(sub_cmd: &SubCmd, args: &CliArgs) -> Result<(), AppStartError> {
+    match sub_cmd {
+        SubCmd::Migrate => {
+            let migrated = migration::migrate_recurse(&args.profiles_dir)?;
+            match migrated.len() {
+                0 => println!("No legacy profiles found in {:?}", args.profiles_dir),
+                n => println!("Migrated {} legacy profile(s)", n),
+            }
+            Ok(())
+        }
+        SubCmd::Export { profile_name, output } => {
+            let profiles = ProfileFolder::from_path_recurse(&args.profiles_dir)?;
+            let profile = profiles.lookup(profile_name).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Cannot find a profile named \"{}\"", profile_name),
+                )
+            })?;
+            let config = profile.export_sslocal_config()?;
+            match output {
+                Some(path) => {
+                    // the config contains the plaintext password
+                    OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .mode(0o600)
+                        .open(path)?
+                        .write_all(config.as_bytes())?;
+                    println!("Exported profile \"{}\" to {:?}", profile_name, path);
+                }
+                None => print!("{}", config),
+            }
+            Ok(())
+        }
+        SubCmd::ImportUri { uri, local_port } => {
+            let parsed = SsUri::parse(uri).map_err(std::io::Error::from)?;
+            let config_path = parsed.write_profile(&args.profiles_dir, *local_port)?;
+            println!("Imported profile \"{}\" to {:?}", parsed.display_name(), config_path);
+            Ok(())
+        }
+        SubCmd::ExportUri { profile_name } => {
+            let profiles = ProfileFolder::from_path_recurse(&args.profiles_dir)?;
+            let profile = profiles.lookup(profile_name).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Cannot find a profile named \"{}\"", profile_name),
+                )
+            })?;
+            println!("{}", profile.ss_uri()?);
+            Ok(())
+        }
+        SubCmd::Tree { json } => {
+            let tree = ProfileTreeNode::from(&ProfileFolder::from_path_recurse(&args.profiles_dir)?);
+            match json {
+                true => println!("{}", serde_json::to_string_pretty(&tree).map_err(std::io::Error::from)?),
+                false => print!("{}", tree),
+            }
+            Ok(())
+        }
+        SubCmd::WriteAutostart { force } => {
+            let path = autostart::write_autostart(&args.profiles_dir, &args.app_state_path, *force)?;
+            println!("Wrote autostart entry to {:?}", path);
+            Ok(())
+        }
+        SubCmd::GenerateCompletions { shell } => {
+            clap_complete::generate(*shell, &mut CliArgs::command(), "ssgtk", &mut std::io::stdout());
+            Ok(())
+        }
+    }
+}
+
+fn logger_init(relative_verbosity: i32, format: LogFormat) -> Result<(), SetLoggerError> {
     use log::LevelFilter::*;
     use simplelog::{ColorChoice, ConfigBuilder, TermLogger, TerminalMode};
 
+    // only log our own crates
+    const ALLOWED_TARGETS: [&str; 2] = [
+        "shadowsocks-gtk-rs", // crate lib
+        "ssgtk",              // crate bin
+    ];
+
     let level_filter = match DEFAULT_LOG_LEVEL + relative_verbosity {
         0 => Error,
         1 => Warn,
@@ -42,9 +167,18 @@ fn logger_init(relative_verbosity: i32) -> Result<(), SetLoggerError> {
         _ => Off, // negative == disable logging
     };
 
-    let logger_config = ConfigBuilder::new()
-        .add_filter_allow_str("shadowsocks-gtk-rs") // crate lib
-        .add_filter_allow_str("ssgtk") // crate bin
-        .build();
-    TermLogger::init(level_filter, logger_config, TerminalMode::Stdout, ColorChoice::Auto)
+    // the logger itself lets everything through, so that the level
+    // can be adjusted at runtime using `log::set_max_level`
+    match format {
+        LogFormat::Text => {
+            let mut builder = ConfigBuilder::new();
+            for target in ALLOWED_TARGETS {
+                builder.add_filter_allow_str(target);
+            }
+            TermLogger::init(Trace, builder.build(), TerminalMode::Stdout, ColorChoice::Auto)?;
+        }
+        LogFormat::Json => log::set_boxed_logger(Box::new(JsonLogger::new(&ALLOWED_TARGETS)))?,
+    }
+    log::set_max_level(level_filter);
+    Ok(())
 }
@@ -1,5 +1,13 @@
-use gtk::{prelude::*, ButtonsType, MessageDialog, MessageType};
-use log::{debug, error, info, warn};
+//! This module contains code that notifies the user of events.
+//!
+//! Without the "gui" feature, all notifications are logged.
+
+#[cfg(feature = "gui")]
+use gtk::{prelude::*, ButtonsType, MessageDialog, MessageType, ResponseType};
+#[cfg(feature = "gui")]
+use log::debug;
+use log::{error, info, warn};
+#[cfg(feature = "gui")]
 use notify_rust::{error as notify_error, Hint, Notification, NotificationHandle, Timeout, Urgency};
 use shadowsocks_gtk_rs::notify_method::NotifyMethod;
 
@@ -13,6 +21,7 @@ pub enum Level {
     Error,
 }
 
+#[cfg(feature = "gui")]
 impl Into<MessageType> for Level {
     fn into(self) -> MessageType {
         use MessageType::*;
@@ -23,6 +32,7 @@ impl Into<MessageType> for Level {
         }
     }
 }
+#[cfg(feature = "gui")]
 impl Into<Urgency> for Level {
     fn into(self) -> Urgency {
         use Urgency::*;
@@ -34,19 +44,55 @@ impl Into<Urgency> for Level {
     }
 }
 
+/// How long toast notifications stay on screen before expiring.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ToastTimeout {
+    /// `None` uses the notification server's default; `Some(0)` never expires.
+    pub ms: Option<u32>,
+    /// Keep error-level toasts until dismissed, regardless of `ms`.
+    pub sticky_errors: bool,
+}
+
+#[cfg(feature = "gui")]
+impl ToastTimeout {
+    /// The timeout of a toast with the specified level.
+    fn for_level(&self, level: Level) -> Timeout {
+        match (level, self.ms) {
+            (Level::Error, _) if self.sticky_errors => Timeout::Never,
+            (_, None) => Timeout::Default,
+            (_, Some(0)) => Timeout::Never,
+            (_, Some(ms)) => Timeout::Milliseconds(ms),
+        }
+    }
+}
+
 /// Send a simple text notification, using the specified method.
-pub fn notify(method: NotifyMethod, level: Level, text_1: impl AsRef<str>, text_2: impl AsRef<str>) {
+///
+/// `timeout` only applies to `NotifyMethod::Toast`.
+#[cfg_attr(not(feature = "gui"), allow(unused_variables))]
+pub fn notify(
+    method: NotifyMethod,
+    timeout: ToastTimeout,
+    level: Level,
+    text_1: impl AsRef<str>,
+    text_2: impl AsRef<str>,
+) {
     use NotifyMethod::*;
     match method {
         Disable => {} // do nothing
         Log => notify_log(level, text_1.as_ref(), text_2.as_ref()),
+        #[cfg(feature = "gui")]
         Prompt => notify_nonblocking_prompt(level.into(), text_1.as_ref(), text_2.as_ref()),
+        #[cfg(feature = "gui")]
         Toast => {
-            let res = notify_toast(level.into(), text_1.as_ref(), text_2.as_ref());
+            let res = notify_toast(level.into(), timeout.for_level(level), text_1.as_ref(), text_2.as_ref());
             if let Err(err) = res {
                 error!("Failed to show toast notification: {}", err);
             }
         }
+        // there is nothing to show these with
+        #[cfg(not(feature = "gui"))]
+        Prompt | Toast => notify_log(level, text_1.as_ref(), text_2.as_ref()),
     }
 }
 
@@ -61,6 +107,7 @@ pub fn notify_log(level: Level, text_1: &str, text_2: &str) {
 }
 
 /// Notification impl for `NotifyMethod::Prompt`.
+#[cfg(feature = "gui")]
 pub fn notify_nonblocking_prompt(level: MessageType, text_1: &str, text_2: &str) {
     debug!("Showing popup; type: {}, title: {}", level, text_1);
     let dialog = MessageDialog::builder()
@@ -79,15 +126,40 @@ pub fn notify_nonblocking_prompt(level: MessageType, text_1: &str, text_2: &str)
     dialog.present(); // bring to foreground
 }
 
+/// Ask the user a yes/no question, blocking until answered.
+///
+/// Returns whether the user answered yes.
+#[cfg(feature = "gui")]
+pub fn confirm_blocking_prompt(text_1: &str, text_2: &str) -> bool {
+    debug!("Showing confirmation popup; title: {}", text_1);
+    let dialog = MessageDialog::builder()
+        .buttons(ButtonsType::YesNo)
+        .deletable(true)
+        .message_type(MessageType::Question)
+        .secondary_text(text_2)
+        .text(text_1)
+        .title("shadowsocks-gtk-rs")
+        .build();
+    let response = dialog.run(); // blocks until answered or closed
+    dialog.close();
+    response == ResponseType::Yes
+}
+
 /// Notification impl for `NotifyMethod::Toast`.
-pub fn notify_toast(urgency: Urgency, text_1: &str, text_2: &str) -> notify_error::Result<NotificationHandle> {
+#[cfg(feature = "gui")]
+pub fn notify_toast(
+    urgency: Urgency,
+    timeout: Timeout,
+    text_1: &str,
+    text_2: &str,
+) -> notify_error::Result<NotificationHandle> {
     debug!("Sending system notification: urgency: {:?}, title: {}", urgency, text_1);
     Notification::new()
         .auto_icon()
         .body(text_2)
         .hint(Hint::Category("network".into()))
         .summary(text_1)
-        .timeout(Timeout::Default)
+        .timeout(timeout)
         .urgency(urgency)
         .show()
 }
@@ -1,27 +1,34 @@
 //! This module contains code that handles profile switching and automatic restarting.
 
 use std::{
+    collections::BTreeMap,
     fmt,
     io::{self, BufRead, BufReader, Read},
     os::unix::net::UnixStream,
     process::ExitStatus,
-    sync::{Arc, Mutex, RwLock},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
     thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use bus::{Bus, BusReader};
-use crossbeam_channel::{unbounded as unbounded_channel, Receiver, Sender};
+use clap::ValueEnum;
+use crossbeam_channel::{never, select, unbounded as unbounded_channel, Receiver, Sender};
 use derivative::Derivative;
 use duct::{unix::HandleExt, Handle};
 use itertools::Itertools;
 use log::{debug, error, info, trace, warn};
 use nix::sys::signal::Signal;
+#[cfg(feature = "runtime-api")]
+use shadowsocks_gtk_rs::runtime_api_msg::TestProfileReply;
 use shadowsocks_gtk_rs::{
     consts::*,
     util::{
         self,
-        leaky_bucket::{NaiveLeakyBucket, NaiveLeakyBucketConfig},
+        leaky_bucket::{LeakyBucketOverflowed, NaiveLeakyBucket, NaiveLeakyBucketConfig},
         mutex_lock, rwlock_read, OutputKind,
     },
 };
@@ -29,11 +36,61 @@ use shadowsocks_gtk_rs::{
 use crate::{
     event::AppEvent,
     io::{
-        app_state::AppState,
-        profile_loader::{Profile, ProfileFolder},
+        app_state::{AppState, ResumeRetryConfig},
+        backlog::Backlog,
+        connectivity,
+        pid_registry::PidRegistry,
+        profile_loader::{LifecycleHook, OnFailure, Profile, ProfileFolder},
+        sslocal_error::SslocalErrorKind,
     },
 };
 
+/// The order in which to start the new instance and stop the old one when switching profiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[clap(rename_all = "kebab-case")]
+pub enum SwitchStrategy {
+    /// Stop the old instance, then start the new one.
+    BreakBeforeMake,
+    /// Start the new instance and wait for it to listen, then stop the old one.
+    ///
+    /// Falls back to `BreakBeforeMake` if the two instances may listen on the same port.
+    MakeBeforeBreak,
+}
+
+/// Traffic statistics of a `sslocal` instance, parsed from its output as lines arrive.
+///
+/// `sslocal` is expected to periodically print a line containing `stat:`,
+/// followed by the cumulative byte counts since launch as `tx=<bytes>` and `rx=<bytes>`, e.g.
+/// `2022-10-01T12:00:00 INFO stat: tx=1024 rx=4096`.
+/// Versions that don't print such lines simply leave the counts at zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InstanceStats {
+    /// How long the instance has been running.
+    pub uptime: Duration,
+    /// Total bytes sent to the server.
+    pub bytes_sent: u64,
+    /// Total bytes received from the server.
+    pub bytes_received: u64,
+}
+
+impl InstanceStats {
+    /// Parse the byte counts out of a stat line, as `(sent, received)`.
+    ///
+    /// Returns `None` if the line is not a well-formed stat line.
+    fn parse_line(line: &str) -> Option<(u64, u64)> {
+        let (_, fields) = line.split_once("stat:")?;
+        let (mut sent, mut received) = (None, None);
+        for field in fields.split_whitespace() {
+            match field.split_once('=') {
+                Some(("tx", n)) => sent = Some(n.parse().ok()?),
+                Some(("rx", n)) => received = Some(n.parse().ok()?),
+                _ => {} // ignore unknown fields for forward compatibility
+            }
+        }
+        Some((sent?, received?))
+    }
+}
+
 /// Represents a currently running `sslocal` instance, storing the relevant information
 /// for its subprocess(es).
 ///
@@ -45,12 +102,33 @@ struct ActiveSSInstance {
     profile: Profile,
     /// The handle of the subprocess.
     sslocal_process: Arc<Handle>,
+    /// When this instance was started.
+    started_at: Instant,
     /// Subscribe to me to handle `sslocal`'s `stdout`.
     #[derivative(Debug(format_with = "shadowsocks_gtk_rs::util::hacks::omit_bus"))]
     stdout_brd: Arc<Mutex<Bus<String>>>,
     /// Subscribe to me to handle `sslocal`'s `stderr`.
     #[derivative(Debug(format_with = "shadowsocks_gtk_rs::util::hacks::omit_bus"))]
     stderr_brd: Arc<Mutex<Bus<String>>>,
+    /// Total bytes transferred, as last reported by `sslocal`'s manager interface.
+    bytes_transferred: Arc<RwLock<Option<u64>>>,
+    /// Bytes sent & received, as last printed by `sslocal`; the uptime is filled in on read.
+    stats: Arc<RwLock<InstanceStats>>,
+    /// Emits once if `sslocal` has not started listening within the profile's launch timeout.
+    ///
+    /// Never emits if the profile has no launch timeout.
+    launch_alert: Receiver<()>,
+    /// Where to record the PIDs of `sslocal`, if anywhere.
+    pid_registry: Option<PidRegistry>,
+    /// How long to wait for `sslocal` to exit gracefully when dropped, before killing it.
+    stop_timeout: Duration,
+    /// Where the output of the profile's lifecycle hooks goes.
+    #[derivative(Debug = "ignore")]
+    backlog: Arc<Mutex<Backlog>>,
+    /// Whether the profile's `post_down` hook still needs to run when dropped.
+    post_down_pending: bool,
+    /// Default: false. Set to true to halt the polling daemons on next poll.
+    halt_flag: Arc<RwLock<bool>>,
     /// The daemon threads that need to be cleanup up when deactivating.
     daemon_handles: Vec<JoinHandle<()>>,
 }
@@ -75,13 +153,13 @@ impl Drop for ActiveSSInstance {
 
         trace!("{} is getting dropped", self_name);
 
-        // send stop signal to `sslocal` process
-        if let Err(err) = self.sslocal_process.send_signal(Signal::SIGINT as i32) {
-            trace!("{}'s underlying process has already exited: {}", self_name, err);
+        // stop `sslocal` process, forcibly if necessary
+        if let Err(err) = self.terminate(self.stop_timeout) {
+            error!("Failed to terminate {}: {}", self_name, err);
         }
 
-        // sleep for a short time to allow `sslocal` to exit fully
-        thread::sleep(Duration::from_millis(100));
+        // notify polling daemons halt
+        *util::rwlock_write(&self.halt_flag) = true;
 
         // make sure all daemon threads finish
         for handle in self.daemon_handles.drain(..) {
@@ -89,30 +167,254 @@ impl Drop for ActiveSSInstance {
                 warn!("A daemon of {} panicked unexpectedly: {:?}", self_name, err);
             };
         }
+
+        // clean exit, so forget PIDs
+        if let Some(registry) = &self.pid_registry {
+            if let Err(err) = registry.remove(&self.sslocal_process.pids()) {
+                warn!("Failed to remove PIDs of {} from registry: {}", self_name, err);
+            }
+        }
+
+        if self.post_down_pending {
+            if let Err(err) = run_hook(&self.profile, LifecycleHook::PostDown, &self.backlog) {
+                warn!("post_down hook of {} failed: {}", self_name, err);
+            }
+        }
+    }
+}
+
+/// The local port a profile listens on, if known.
+fn listen_port(profile: &Profile) -> Option<u16> {
+    profile
+        .local_addr()
+        .or_else(|| profile.socks_addr())
+        .map(|addr| addr.port())
+}
+
+/// Run a lifecycle hook of a profile if it has one, appending its output to the backlog.
+fn run_hook(profile: &Profile, hook: LifecycleHook, backlog: &Mutex<Backlog>) -> io::Result<()> {
+    let (output, res) = match profile.run_lifecycle_hook(hook) {
+        Some(ran) => ran,
+        None => return Ok(()),
+    };
+    debug!("Ran {} hook of profile \"{}\"", hook, profile.metadata.display_name);
+    let mut backlog = mutex_lock(backlog);
+    for line in output.lines() {
+        if let Err(err) = backlog.push_str(&format!("[{}] {}\n", hook, line)) {
+            warn!("Failed to append to backlog: {}", err);
+        }
     }
+    res
 }
 
+/// Run the `post_down` hook of a profile if it has one, after its `sslocal` has exited
+/// or has failed to start, logging any failure.
+fn run_post_down(profile: &Profile, backlog: &Mutex<Backlog>) {
+    if let Err(err) = run_hook(profile, LifecycleHook::PostDown, backlog) {
+        warn!(
+            "post_down hook of profile \"{}\" failed: {}",
+            profile.metadata.display_name, err
+        );
+    }
+}
+
+/// Which of its profile's lifecycle hooks an instance runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InstanceHooks {
+    /// Neither, e.g. for a test run.
+    None,
+    /// Only `post_down`, because `pre_up` has already run on another thread.
+    ///
+    /// If `sslocal` fails to start, `post_down` is left to the caller.
+    PostDown,
+    /// `pre_up` before `sslocal` is started, and `post_down` after it has exited.
+    Both,
+}
+
+/// How often to query `sslocal`'s manager interface for traffic statistics.
+const MANAGER_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A line of `sslocal` output containing this indicates that it has launched successfully.
+const LAUNCH_SUCCESS_MARKER: &str = "listening";
+
+/// How long to wait for a new instance to start listening during a make-before-break switch.
+const LISTEN_TIMEOUT: Duration = Duration::from_secs(3);
+
 impl ActiveSSInstance {
     /// Start a new instance of `sslocal`.
-    fn new(profile: Profile) -> io::Result<Self> {
+    ///
+    /// Its PIDs are recorded in `pid_registry`, if specified.
+    ///
+    /// When dropped, `sslocal` is killed if it has not exited within `stop_timeout`.
+    ///
+    /// Unless the profile sets its own, `launch_timeout` limits how long `sslocal` is given to start listening.
+    ///
+    /// The profile's lifecycle hooks run as specified by `hooks`, and their output is appended to `backlog`.
+    fn new(
+        profile: Profile,
+        pid_registry: Option<PidRegistry>,
+        stop_timeout: Duration,
+        launch_timeout: Option<Duration>,
+        backlog: Arc<Mutex<Backlog>>,
+        hooks: InstanceHooks,
+    ) -> io::Result<Self> {
+        Self::new_capturing(
+            profile,
+            pid_registry,
+            stop_timeout,
+            launch_timeout,
+            backlog,
+            false,
+            hooks,
+        )
+        .map(|(instance, _)| instance)
+    }
+
+    /// Same as `new`, but if `capture` is set, also returns listeners for `stdout` & `stderr`
+    /// that are subscribed before piping starts, so that no line is missed.
+    fn new_capturing(
+        profile: Profile,
+        pid_registry: Option<PidRegistry>,
+        stop_timeout: Duration,
+        launch_timeout: Option<Duration>,
+        backlog: Arc<Mutex<Backlog>>,
+        capture: bool,
+        hooks: InstanceHooks,
+    ) -> io::Result<(Self, Option<[BusReader<String>; 2]>)> {
         let (stdout_stream_tx, stdout_stream_rx) = UnixStream::pair()?;
         let (stderr_stream_tx, stderr_stream_rx) = UnixStream::pair()?;
 
+        // a failed `pre_up` hook aborts the start
+        if hooks == InstanceHooks::Both {
+            run_hook(&profile, LifecycleHook::PreUp, &backlog)
+                .map_err(|err| io::Error::new(err.kind(), format!("pre_up hook failed: {}", err)))?;
+        }
+
         // start instance
-        let proc = profile.run_sslocal(Some(stdout_stream_tx), Some(stderr_stream_tx))?;
+        let proc = match profile.run_sslocal(Some(stdout_stream_tx), Some(stderr_stream_tx)) {
+            Ok(proc) => proc,
+            Err(err) => {
+                // undo whatever `pre_up` has done
+                if hooks == InstanceHooks::Both {
+                    run_post_down(&profile, &backlog);
+                }
+                return Err(err);
+            }
+        };
+        if let Some(registry) = &pid_registry {
+            if let Err(err) = registry.add(&proc.pids()) {
+                warn!("Failed to record PIDs of sslocal in registry: {}", err);
+            }
+        }
         let mut instance = Self {
             profile,
             sslocal_process: proc.into(),
+            started_at: Instant::now(),
             stdout_brd: Mutex::new(Bus::new(BUS_BUFFER_SIZE)).into(),
             stderr_brd: Mutex::new(Bus::new(BUS_BUFFER_SIZE)).into(),
+            bytes_transferred: RwLock::new(None).into(),
+            stats: RwLock::new(InstanceStats::default()).into(),
+            launch_alert: never(),
+            pid_registry,
+            stop_timeout,
+            backlog,
+            post_down_pending: false, // not until fully set up, see below
+            halt_flag: RwLock::new(false).into(),
             daemon_handles: vec![],
         };
+        let streams = [stdout_stream_rx, stderr_stream_rx];
+        match instance.set_up(streams, launch_timeout, capture) {
+            Ok(captured) => {
+                instance.post_down_pending = hooks != InstanceHooks::None;
+                Ok((instance, captured))
+            }
+            Err(err) => {
+                let (profile, backlog) = (instance.profile.clone(), Arc::clone(&instance.backlog));
+                drop(instance); // stops `sslocal`
+                if hooks == InstanceHooks::Both {
+                    run_post_down(&profile, &backlog);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Start the daemons of a new instance, returning the listeners for `stdout` & `stderr` if `capture` is set;
+    /// see `Self::new_capturing`.
+    fn set_up(
+        &mut self,
+        [stdout_stream_rx, stderr_stream_rx]: [UnixStream; 2],
+        launch_timeout: Option<Duration>,
+        capture: bool,
+    ) -> io::Result<Option<[BusReader<String>; 2]>> {
+        // subscribe before piping starts, so that no line is missed
+        let launch_watch = self.profile.launch_timeout(launch_timeout).map(|timeout| {
+            let listeners = [
+                self.new_listener(OutputKind::Stdout),
+                self.new_listener(OutputKind::Stderr),
+            ];
+            (timeout, listeners)
+        });
+        let captured = capture.then(|| {
+            [
+                self.new_listener(OutputKind::Stdout),
+                self.new_listener(OutputKind::Stderr),
+            ]
+        });
 
         // pipe output
-        instance.pipe_to_broadcast(stdout_stream_rx, OutputKind::Stdout)?;
-        instance.pipe_to_broadcast(stderr_stream_rx, OutputKind::Stderr)?;
+        self.pipe_to_broadcast(stdout_stream_rx, OutputKind::Stdout)?;
+        self.pipe_to_broadcast(stderr_stream_rx, OutputKind::Stderr)?;
 
-        Ok(instance)
+        // watch for launch timeout
+        if let Some((timeout, listeners)) = launch_watch {
+            self.launch_alert = self.alert_on_launch_timeout(timeout, listeners)?;
+        }
+
+        // poll traffic statistics
+        self.poll_manager_stats()?;
+
+        Ok(captured)
+    }
+
+    /// The PIDs of the `sslocal` process(es) of this instance.
+    #[cfg(feature = "runtime-api")]
+    pub fn pids(&self) -> Vec<u32> {
+        self.sslocal_process.pids()
+    }
+
+    /// Start a daemon that periodically queries `sslocal`'s manager interface
+    /// for traffic statistics, if the profile has configured one.
+    fn poll_manager_stats(&mut self) -> io::Result<()> {
+        let manager_addr = match self.profile.manager_addr() {
+            Some(addr) => addr.clone(),
+            None => return Ok(()),
+        };
+        let self_name = self.to_string();
+        let bytes_transferred = Arc::clone(&self.bytes_transferred);
+        let halt_flag = Arc::clone(&self.halt_flag);
+        let handle = thread::Builder::new()
+            .name(format!("manager stats poller daemon for {}", self_name))
+            .spawn(move || {
+                trace!("Manager stats poller daemon for {} started", self_name);
+                loop {
+                    match manager_addr.query_bytes_transferred(Duration::from_secs(1)) {
+                        Ok(bytes) => *util::rwlock_write(&bytes_transferred) = Some(bytes),
+                        Err(err) => debug!("Failed to query manager at {}: {}", manager_addr, err),
+                    }
+                    // wait for next poll, while checking for halt
+                    let last_query = Instant::now();
+                    while last_query.elapsed() < MANAGER_POLL_INTERVAL {
+                        if *util::rwlock_read(&halt_flag) {
+                            trace!("Manager stats poller daemon for {} exiting", self_name);
+                            return;
+                        }
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                }
+            })?;
+        self.daemon_handles.push(handle);
+        Ok(())
     }
 
     /// Start a daemon to pipe output from a readable source to a broadcasting channel.
@@ -126,18 +428,25 @@ impl ActiveSSInstance {
             OutputKind::Stdout => Arc::clone(&self.stdout_brd),
             OutputKind::Stderr => Arc::clone(&self.stderr_brd),
         };
+        let stats = Arc::clone(&self.stats);
         let handle = thread::Builder::new()
             .name(format!("{} piper daemon for {}", output_kind, self_name))
             .spawn(move || {
                 trace!("{} piper daemon for {} started", output_kind, self_name);
+                // number of lines dropped since the last successful broadcast
+                let mut dropped = 0usize;
                 for line_res in source.lines() {
                     let line = {
                         let raw = line_res.unwrap_or_else(|err| format!("Error reading {}: {}", &output_kind, err));
+                        if let Some((sent, received)) = InstanceStats::parse_line(&raw) {
+                            let mut stats = util::rwlock_write(&stats);
+                            stats.bytes_sent = sent;
+                            stats.bytes_received = received;
+                        }
                         format!("[{}] {}\n", output_kind, raw)
                     };
                     trace!("Broadcasting: {}", line);
-                    // try to send through channel
-                    if let Err(_) = mutex_lock(&brd).try_broadcast(line) {
+                    if !try_broadcast_or_count(&mut mutex_lock(&brd), line, output_kind, &mut dropped) {
                         warn!(
                             "{} wrote to {}, but the broadcasting channel is full.",
                             self_name, output_kind
@@ -150,6 +459,114 @@ impl ActiveSSInstance {
         Ok(())
     }
 
+    /// Starts a monitoring thread that scans `sslocal`'s output for a sign of a successful launch,
+    /// and emits via the returned channel if none is seen within `timeout`.
+    fn alert_on_launch_timeout(
+        &mut self,
+        timeout: Duration,
+        mut listeners: [BusReader<String>; 2],
+    ) -> io::Result<Receiver<()>> {
+        let self_name = self.to_string();
+        let halt_flag = Arc::clone(&self.halt_flag);
+        let (timeout_tx, timeout_rx) = unbounded_channel();
+        let handle = thread::Builder::new()
+            .name(format!("launch timeout daemon for instance {}", self_name))
+            .spawn(move || {
+                let deadline = Instant::now() + timeout;
+                loop {
+                    // check for halt
+                    if *util::rwlock_read(&halt_flag) {
+                        trace!("{} halted before launch completed; daemon exiting", self_name);
+                        break;
+                    }
+                    let launched = listeners.iter_mut().any(|l| {
+                        std::iter::from_fn(|| l.try_recv().ok()).any(|line| line.contains(LAUNCH_SUCCESS_MARKER))
+                    });
+                    if launched {
+                        debug!("{} has launched successfully", self_name);
+                        break;
+                    }
+                    if Instant::now() >= deadline {
+                        warn!("{} has not started listening within {:?}", self_name, timeout);
+                        if let Err(_) = timeout_tx.send(()) {
+                            warn!("{} launch timed out, but the receiver has hung up.", self_name);
+                        }
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+                // listeners dropped, so that they do not hold up the broadcast
+            })?;
+        self.daemon_handles.push(handle);
+        Ok(timeout_rx)
+    }
+
+    /// Block until the local SOCKS5 listener of this instance responds,
+    /// or until `timeout` has elapsed.
+    fn wait_until_listening(&self, timeout: Duration) -> io::Result<()> {
+        let local_addr = self
+            .profile
+            .socks_addr()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "Profile has no SOCKS5 listener"))?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.sslocal_process.try_wait()?.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    format!("{} exited before listening", self),
+                ));
+            }
+            match connectivity::probe_socks5(local_addr, Duration::from_millis(500)) {
+                Ok(_) => return Ok(()),
+                Err(err) if Instant::now() >= deadline => return Err(err),
+                Err(_) => thread::sleep(Duration::from_millis(100)),
+            }
+        }
+    }
+
+    /// Ask `sslocal` to exit and block until it has, killing it if
+    /// it does not exit within `timeout`.
+    fn terminate(&self, timeout: Duration) -> io::Result<()> {
+        if let Err(err) = self.signal(Signal::SIGINT) {
+            match err.kind() {
+                io::ErrorKind::PermissionDenied => warn!("Not permitted to stop {}: {}", self, err),
+                _ => trace!("{}'s underlying process has already exited: {}", self, err),
+            }
+        }
+        let deadline = Instant::now() + timeout;
+        while self.sslocal_process.try_wait()?.is_none() {
+            if Instant::now() >= deadline {
+                warn!("{} did not exit within {:?}; killing", self, timeout);
+                self.signal(Signal::SIGKILL)?;
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        self.sslocal_process.wait()?;
+        Ok(())
+    }
+
+    /// Send `signal` to `sslocal`, going through the privilege escalation tool
+    /// if it is running as another user, e.g. as root when launched with `pkexec`.
+    fn signal(&self, signal: Signal) -> io::Result<()> {
+        match (
+            self.sslocal_process.send_signal(signal as i32),
+            self.profile.privilege_escalation(),
+        ) {
+            (Err(err), Some(tool)) if err.kind() == io::ErrorKind::PermissionDenied => {
+                debug!(
+                    "Not permitted to signal {} directly, sending {} via {}",
+                    self, signal, tool
+                );
+                self.sslocal_process
+                    .pids()
+                    .into_iter()
+                    .try_for_each(|pid| tool.kill(pid, signal))
+            }
+            (res, _) => res,
+        }
+    }
+
     /// Convenience function to create a new broadcast listener.
     fn new_listener(&self, output_kind: OutputKind) -> BusReader<String> {
         let brd = match output_kind {
@@ -181,6 +598,116 @@ impl ActiveSSInstance {
     }
 }
 
+/// The slot of a running instance, shared with its failure monitor; `None` once stopped.
+type InstanceSlot = Arc<RwLock<Option<ActiveSSInstance>>>;
+
+/// An instance stopping in the background; see `ProfileManager::tear_down`.
+#[derive(Debug)]
+struct Teardown {
+    /// The local port the instance listens on, if known.
+    port: Option<u16>,
+    handle: JoinHandle<()>,
+    /// Disconnects once the instance has exited.
+    done: Receiver<()>,
+}
+
+/// Whether `ProfileManager::switch_to` or `ProfileManager::start` has started the new instance right away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartProgress {
+    /// The new instance is running.
+    Started,
+    /// The new instance starts once the instances still stopping have exited,
+    /// and its profile's `pre_up` hook has run on another thread,
+    /// which is announced by `AppEvent::StartReady`; see `ProfileManager::proceed`.
+    Deferred,
+}
+
+/// What a new instance is started as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StartRole {
+    /// Replaces the active instance; see `ProfileManager::switch_to`.
+    Switch { make_before_break: bool },
+    /// Runs alongside the active instance; see `ProfileManager::start`.
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    Concurrent,
+}
+
+/// A start that is waiting for the instances still stopping to exit, and for its `pre_up` hook.
+#[derive(Debug)]
+struct PendingStart {
+    profile: Profile,
+    role: StartRole,
+    /// The number of retries so far, if resuming on launch; see `ProfileManager::resume`.
+    resume_attempt: Option<u32>,
+    /// Set if the start has been superseded or stopped, in which case
+    /// its `pre_up` hook is undone once it has run.
+    cancelled: bool,
+}
+
+/// The outcome of a deferred start; see `ProfileManager::proceed`.
+#[derive(Debug)]
+pub struct DeferredStart {
+    pub profile: Profile,
+    /// Whether the instance runs alongside the active one, rather than replacing it.
+    pub concurrent: bool,
+    pub res: io::Result<()>,
+}
+
+/// How long a profile is run for by `test_profile`.
+#[cfg(feature = "runtime-api")]
+const TEST_PROFILE_DURATION: Duration = Duration::from_secs(3);
+
+/// Start `sslocal` with a profile separately from the active instance,
+/// capture its output for a few seconds, then stop it.
+///
+/// This blocks for the duration of the test, and does not affect the active instance.
+/// The profile's `pre_up` & `post_down` hooks are not run, lest they disturb the active instance.
+/// Note however that the test will fail if the active instance is listening on the same address.
+#[cfg(feature = "runtime-api")]
+pub fn test_profile(profile: Profile) -> io::Result<TestProfileReply> {
+    let name = profile.metadata.display_name.clone();
+    info!("Testing profile \"{}\" for {:?}", name, TEST_PROFILE_DURATION);
+    let (instance, listeners) = ActiveSSInstance::new_capturing(
+        profile,
+        None,
+        SSLOCAL_STOP_TIMEOUT_DEFAULT,
+        None,
+        Arc::new(Mutex::new(Backlog::default())),
+        true,
+        InstanceHooks::None,
+    )?;
+    let mut listeners = listeners.unwrap(); // capture is set
+    let mut output = String::new();
+    let mut drain = |output: &mut String| {
+        for listener in listeners.iter_mut() {
+            output.extend(std::iter::from_fn(|| listener.try_recv().ok()));
+        }
+    };
+
+    let deadline = Instant::now() + TEST_PROFILE_DURATION;
+    let exit_status = loop {
+        drain(&mut output);
+        if let Some(out) = instance.sslocal_process.try_wait()? {
+            break Some(out.status);
+        }
+        if Instant::now() >= deadline {
+            break None;
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    // piping daemons are joined on drop, so all output is available afterwards
+    drop(instance);
+    drain(&mut output);
+
+    Ok(TestProfileReply {
+        listening: output.contains(LAUNCH_SUCCESS_MARKER),
+        profile: name,
+        still_running: exit_status.is_none(),
+        exit_status: exit_status.map(|status| status.to_string()),
+        output,
+    })
+}
+
 /// A daemon that manages profile-switching and restarts.
 #[derive(Derivative)]
 #[derivative(Debug)]
@@ -191,21 +718,69 @@ pub struct ProfileManager {
     ///
     /// Scenarios in which a restart will not be attempted:
     /// - Limit reached
+    /// - The profile is set to halt on failure
     /// - `sslocal` instance terminated by a signal
     /// - Various errors which make it impossible for monitoring to continue
-    pub restart_limit: NaiveLeakyBucketConfig,
+    ///
+    /// Shared with the failure monitor daemon so that changes apply on its next restart.
+    restart_limit: Arc<RwLock<NaiveLeakyBucketConfig>>,
+    /// Counts the restarts of the active profile; shared with the failure monitor daemon
+    /// so that it can be reset externally.
+    restart_counter: Arc<Mutex<NaiveLeakyBucket>>,
+    /// Counts the restarts triggered by network changes, using the same limit as auto-restart.
+    ///
+    /// Separate from `restart_counter`, which every restart resets.
+    network_restart_counter: Mutex<NaiveLeakyBucket>,
+    /// The total number of successful auto-restarts since launch, across all profiles.
+    total_restarts: Arc<AtomicUsize>,
+    /// While set, a failed instance is left stopped instead of being restarted, e.g. for debugging.
+    ///
+    /// Shared with the failure monitor daemon so that changes apply on the next failure.
+    auto_restart_paused: Arc<RwLock<bool>>,
+    /// When the most recent profile switch (or manual restart) happened.
+    switched_at: Option<Instant>,
+    /// How to replace the old instance when switching profiles.
+    pub switch_strategy: SwitchStrategy,
+    /// Where to record the PIDs of launched `sslocal` processes, if anywhere.
+    pid_registry: Option<PidRegistry>,
+    /// How long to wait for `sslocal` to exit gracefully when stopping it, before killing it.
+    pub stop_timeout: Duration,
+    /// How long `sslocal` is given to start listening before it's stopped as failed,
+    /// unless its profile sets its own; `None` means unlimited.
+    pub launch_timeout: Option<Duration>,
+    /// How to retry resuming a profile on launch if starting it fails.
+    pub resume_retry: ResumeRetryConfig,
+    /// Incremented by every switch and stop, so that a retry scheduled (or a startup wait begun)
+    /// before them can tell it's stale.
+    switch_generation: u64,
     events_tx: Sender<AppEvent>,
     /// Inner value of `None` means `Self` is inactive.
-    active_instance: Arc<RwLock<Option<ActiveSSInstance>>>,
+    active_instance: InstanceSlot,
+    /// Instances started alongside the active one, keyed by profile name,
+    /// e.g. a "proxy"-type profile next to a "tun"-type one on a different port.
+    ///
+    /// Each has its own failure monitor and restart budget, and its output is merged into the same logs.
+    /// An entry whose slot is `None` has stopped.
+    concurrent_instances: BTreeMap<String, InstanceSlot>,
+    /// The profile most recently switched to, kept after the instance has stopped.
+    last_profile: Option<Profile>,
 
-    /// A string holding the combined backlog history of `stdout` & `stderr`.
-    pub backlog: Arc<Mutex<String>>,
+    /// The combined backlog history of `stdout` & `stderr`.
+    pub backlog: Arc<Mutex<Backlog>>,
     /// A channel that broadcasts the combined logs of `stdout` & `stderr`.
     #[derivative(Debug(format_with = "shadowsocks_gtk_rs::util::hacks::omit_bus"))]
     pub logs_brd: Arc<Mutex<Bus<String>>>,
+    /// The buffer size of `logs_brd`, in lines.
+    pub log_buffer_lines: usize,
 
     /// The daemon threads that need to be cleanup up when deactivating.
     daemon_handles: Vec<JoinHandle<()>>,
+    /// The instances stopping in the background; see `Self::tear_down`.
+    teardowns: Vec<Teardown>,
+    /// The starts waiting for instances to exit, keyed by the ID in `AppEvent::StartReady`.
+    pending_starts: BTreeMap<u64, PendingStart>,
+    /// The ID of the next deferred start.
+    next_start_id: u64,
 }
 
 impl Drop for ProfileManager {
@@ -215,8 +790,15 @@ impl Drop for ProfileManager {
     fn drop(&mut self) {
         trace!("ProfileManager is getting dropped");
 
-        // deactivate `sslocal` instance
+        // deactivate `sslocal` instances
         let _ = self.try_stop();
+        for (_, slot) in std::mem::take(&mut self.concurrent_instances) {
+            let instance = util::rwlock_write(&slot).take();
+            if let Some(instance) = instance {
+                self.tear_down(instance, || {});
+            }
+        }
+        self.await_teardowns();
 
         // make sure all daemon threads finish
         for handle in self.daemon_handles.drain(..) {
@@ -230,23 +812,87 @@ impl Drop for ProfileManager {
 impl ProfileManager {
     pub fn new(restart_limit: NaiveLeakyBucketConfig, events_tx: Sender<AppEvent>) -> Self {
         Self {
-            restart_limit,
+            restart_limit: RwLock::new(restart_limit).into(),
+            restart_counter: Mutex::new(restart_limit.into()).into(),
+            network_restart_counter: Mutex::new(restart_limit.into()),
+            total_restarts: AtomicUsize::new(0).into(),
+            auto_restart_paused: RwLock::new(false).into(),
+            switched_at: None,
+            switch_strategy: SwitchStrategy::BreakBeforeMake,
+            pid_registry: None,
+            stop_timeout: SSLOCAL_STOP_TIMEOUT_DEFAULT,
+            launch_timeout: None,
+            resume_retry: ResumeRetryConfig::default(),
+            switch_generation: 0,
             events_tx,
             active_instance: RwLock::new(None).into(),
-            backlog: Mutex::new(String::new()).into(),
+            concurrent_instances: BTreeMap::new(),
+            last_profile: None,
+            backlog: Mutex::new(Backlog::default()).into(),
             logs_brd: Mutex::new(Bus::new(BUS_BUFFER_SIZE)).into(),
+            log_buffer_lines: BUS_BUFFER_SIZE,
             daemon_handles: vec![],
+            teardowns: vec![],
+            pending_starts: BTreeMap::new(),
+            next_start_id: 0,
         }
     }
 
     /// Resume from a previously saved state.
-    pub fn resume_from(state: &AppState, profiles: &ProfileFolder, events_tx: Sender<AppEvent>) -> Self {
+    ///
+    /// Resumes with the default profile if set, otherwise with the most recent profile.
+    ///
+    /// The PIDs of all launched `sslocal` processes are recorded in `pid_registry`, if specified.
+    pub fn resume_from(
+        state: &AppState,
+        profiles: &ProfileFolder,
+        pid_registry: Option<PidRegistry>,
+        events_tx: Sender<AppEvent>,
+    ) -> Self {
         let mut pm = Self::new(state.restart_limit, events_tx);
-        match state.most_recent_profile.as_str() {
+        pm.pid_registry = pid_registry;
+        pm.stop_timeout = state.stop_timeout;
+        pm.launch_timeout = state.launch_timeout;
+        pm.resume_retry = state.resume_retry;
+        // no listener has subscribed yet, so the channel can be replaced
+        pm.logs_brd = Mutex::new(Bus::new(state.log_buffer_lines)).into();
+        pm.log_buffer_lines = state.log_buffer_lines;
+        util::mutex_lock(&pm.backlog).set_limit(match state.backlog_limit {
+            0 => None,
+            bytes => Some(bytes as usize),
+        });
+        let resume_name = match &state.default_profile {
+            Some(name) if profiles.lookup(name).is_some() => {
+                debug!("Resuming with default profile \"{}\"", name);
+                name.as_str()
+            }
+            Some(name) => {
+                error!(
+                    "Cannot find default profile \"{}\"; resuming with the most recent profile instead",
+                    name
+                );
+                state.most_recent_profile.as_str()
+            }
+            None => state.most_recent_profile.as_str(),
+        };
+        match resume_name {
             "" => debug!("Most recent profile is none; will not attempt to resume"),
             name => match profiles.lookup(name) {
-                Some(p) => match pm.switch_to(p.clone()) {
-                    Ok(_) => info!("Successfully resumed with profile \"{}\"", name),
+                Some(p) if p.startup_delay().is_some() || p.wait_for_network().is_some() => {
+                    info!("Profile \"{}\" will be resumed after its startup wait", name);
+                    if let Err(err) =
+                        connectivity::spawn_startup_wait(p.clone(), pm.switch_generation, pm.events_tx.clone())
+                    {
+                        error!(
+                            "Cannot resume - failed to start waiting for profile \"{}\": {}",
+                            name, err
+                        );
+                    }
+                }
+                // retries are scheduled by `resume` itself
+                Some(p) => match pm.resume(p.clone(), 0) {
+                    Ok(StartProgress::Started) => info!("Successfully resumed with profile \"{}\"", name),
+                    Ok(StartProgress::Deferred) => info!("Profile \"{}\" will be resumed once ready", name),
                     Err(err) => error!("Cannot resume - switch to profile \"{}\" failed: {}", name, err),
                 },
                 None => warn!("Cannot resume - profile \"{}\" not found", name),
@@ -255,11 +901,61 @@ impl ProfileManager {
         pm
     }
 
+    /// Retry resuming with a profile after a backoff delay, on a daemon thread,
+    /// which sends an `AppEvent::ResumeRetry` when it's time.
+    ///
+    /// Gives up if `attempt` exceeds the maximum number of attempts.
+    pub fn schedule_resume_retry(&self, profile: Profile, attempt: u32) {
+        let name = profile.metadata.display_name.clone();
+        if attempt > self.resume_retry.max_attempts {
+            if self.resume_retry.max_attempts > 0 {
+                error!(
+                    "Giving up resuming with profile \"{}\" after {} retries",
+                    name,
+                    attempt - 1
+                );
+            }
+            return;
+        }
+        let delay = self.resume_retry.delay(attempt);
+        info!(
+            "Retrying to resume with profile \"{}\" in {:?} (attempt {}/{})",
+            name, delay, attempt, self.resume_retry.max_attempts
+        );
+        let events_tx = self.events_tx.clone();
+        let generation = self.switch_generation;
+        let spawn_res = thread::Builder::new().name("resume retry".into()).spawn(move || {
+            thread::sleep(delay);
+            if let Err(_) = events_tx.send(AppEvent::ResumeRetry {
+                profile,
+                attempt,
+                generation,
+            }) {
+                error!("Trying to send ResumeRetry event, but all receivers have hung up.");
+            }
+        });
+        if let Err(err) = spawn_res {
+            error!("Cannot retry resuming with profile \"{}\": {}", name, err);
+        }
+    }
+
+    /// Get the number of switches and stops so far; see `AppEvent::ResumeRetry` & `AppEvent::StartupWaitDone`.
+    pub fn switch_generation(&self) -> u64 {
+        self.switch_generation
+    }
+
     /// Indicate whether a `sslocal` instance is currently running.
     pub fn is_active(&self) -> bool {
         util::rwlock_read(&self.active_instance).is_some()
     }
 
+    /// Indicate whether a switch is waiting to start the new active instance.
+    pub fn is_starting(&self) -> bool {
+        self.pending_starts
+            .values()
+            .any(|pending| !pending.cancelled && matches!(pending.role, StartRole::Switch { .. }))
+    }
+
     /// Get the profile of the currently active instance.
     pub fn current_profile(&self) -> Option<Profile> {
         util::rwlock_read(&self.active_instance)
@@ -267,46 +963,561 @@ impl ProfileManager {
             .map(|instance| instance.profile.clone())
     }
 
+    /// Get the profile of the currently active instance,
+    /// or that of the most recent one if inactive.
+    pub fn last_profile(&self) -> Option<Profile> {
+        self.current_profile().or_else(|| self.last_profile.clone())
+    }
+
+    /// Get how long the currently active instance has been running.
+    #[cfg(feature = "gui")]
+    pub fn uptime(&self) -> Option<Duration> {
+        util::rwlock_read(&self.active_instance)
+            .as_ref()
+            .map(|instance| instance.started_at.elapsed())
+    }
+
+    /// Get the uptime and traffic statistics of the currently active instance.
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    pub fn instance_stats(&self) -> Option<InstanceStats> {
+        util::rwlock_read(&self.active_instance)
+            .as_ref()
+            .map(|instance| InstanceStats {
+                uptime: instance.started_at.elapsed(),
+                ..*util::rwlock_read(&instance.stats)
+            })
+    }
+
+    /// Get the PIDs of the currently active instance; empty if inactive.
+    #[cfg(feature = "runtime-api")]
+    pub fn pids(&self) -> Vec<u32> {
+        util::rwlock_read(&self.active_instance)
+            .as_ref()
+            .map_or(vec![], ActiveSSInstance::pids)
+    }
+
+    /// Get the total number of successful auto-restarts since launch.
+    #[cfg(feature = "runtime-api")]
+    pub fn total_restarts(&self) -> usize {
+        self.total_restarts.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of auto-restarts of the active profile within the restart limit's time window.
+    #[cfg(feature = "runtime-api")]
+    pub fn recent_restarts(&self) -> usize {
+        mutex_lock(&self.restart_counter).current()
+    }
+
+    /// Get how long ago the most recent profile switch happened, if any.
+    #[cfg(feature = "runtime-api")]
+    pub fn since_switch(&self) -> Option<Duration> {
+        self.switched_at.map(|t| t.elapsed())
+    }
+
+    /// Get the total bytes transferred by the currently active instance,
+    /// as last reported by `sslocal`'s manager interface.
+    #[cfg(feature = "runtime-api")]
+    pub fn bytes_transferred(&self) -> Option<u64> {
+        util::rwlock_read(&self.active_instance)
+            .as_ref()
+            .and_then(|instance| *util::rwlock_read(&instance.bytes_transferred))
+    }
+
+    /// Get the names of the profiles running alongside the active one, in alphabetical order.
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    pub fn concurrent_profiles(&self) -> Vec<String> {
+        self.concurrent_instances
+            .iter()
+            .filter(|(_, slot)| util::rwlock_read(slot).is_some())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Start a `sslocal` instance with a profile alongside the active one,
+    /// without stopping or replacing any running instance.
+    ///
+    /// Fails if the profile is already running (or starting), either as the active instance or alongside it.
+    /// Note that the instances must listen on different ports, otherwise the new one will fail.
+    ///
+    /// If instances that may listen on the same port are still stopping, the start is deferred until they have exited.
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    pub fn start(&mut self, profile: Profile) -> io::Result<StartProgress> {
+        let name = profile.metadata.display_name.clone();
+        let is_active = self.current_profile().map(|p| p.metadata.display_name).as_deref() == Some(&*name);
+        let is_starting = self.pending_starts.values().any(|pending| {
+            !pending.cancelled && pending.role == StartRole::Concurrent && pending.profile.metadata.display_name == name
+        });
+        if is_active || is_starting || self.concurrent_profiles().contains(&name) {
+            let msg = format!("Profile \"{}\" is already running", name);
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg));
+        }
+
+        let awaited = self.teardowns_before(Some(&profile));
+        let pending = PendingStart {
+            profile,
+            role: StartRole::Concurrent,
+            resume_attempt: None,
+            cancelled: false,
+        };
+        self.launch_or_defer(pending, awaited)
+    }
+
+    /// The second half of `Self::start`, once no colliding instance is stopping.
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    fn launch_concurrent(&mut self, profile: Profile) -> io::Result<()> {
+        let name = profile.metadata.display_name.clone();
+        let mut new_instance = self.new_instance(profile)?;
+        let exit_alert_rx = new_instance.alert_on_exit()?;
+        let launch_alert_rx = new_instance.launch_alert.clone();
+        for output_kind in [OutputKind::Stdout, OutputKind::Stderr] {
+            let handle = log_piping_setup_impl(
+                &new_instance,
+                output_kind,
+                Arc::clone(&self.logs_brd),
+                Arc::clone(&self.backlog),
+            )?;
+            self.daemon_handles.push(handle);
+        }
+        info!("Started {} alongside the active instance", new_instance);
+
+        // replaces the entry of a previous run that has stopped, if any
+        let slot: InstanceSlot = RwLock::new(Some(new_instance)).into();
+        self.concurrent_instances.insert(name, Arc::clone(&slot));
+        // with its own restart budget, which is filled by the monitor
+        let restart_counter = Mutex::new(self.restart_limit().into()).into();
+        self.spawn_failure_monitor(slot, restart_counter, false, exit_alert_rx, launch_alert_rx)
+    }
+
+    /// Stop the instance running with the named profile,
+    /// whether it's the active instance or one running alongside it.
+    ///
+    /// A start of the profile alongside the active one that is still deferred is cancelled.
+    ///
+    /// Returns `Err(())` if no instance is running (or starting alongside the active one) with the profile.
+    #[cfg(any(feature = "gui", feature = "runtime-api"))]
+    pub fn stop(&mut self, name: &str) -> Result<(), ()> {
+        let is_active = self.current_profile().map(|p| p.metadata.display_name).as_deref() == Some(name);
+        if is_active {
+            return self.try_stop();
+        }
+        let pending = self.pending_starts.values_mut().find(|pending| {
+            !pending.cancelled && pending.role == StartRole::Concurrent && pending.profile.metadata.display_name == name
+        });
+        if let Some(pending) = pending {
+            debug!("Cancelled the deferred start of profile \"{}\"", name);
+            pending.cancelled = true;
+            return Ok(());
+        }
+        let slot = self.concurrent_instances.remove(name).ok_or(())?;
+        let instance = util::rwlock_write(&slot).take().ok_or(())?;
+        self.tear_down(instance, || {});
+        Ok(())
+    }
+
     /// Start a `sslocal` instance with a new profile, replacing the old one if necessary.
     ///
-    /// Returns `Ok(())` if and only if the new instance starts successfully and the old one is cleaned up.
+    /// Returns `Ok(StartProgress::Started)` if and only if the new instance starts successfully
+    /// and the old one is being cleaned up.
+    /// If instances are still stopping, the start is deferred until they have exited,
+    /// so that the new instance neither collides with them on the same port, nor overlaps with their hooks;
+    /// any previous deferred switch is cancelled.
+    ///
+    /// If the new instance fails to start, this `ProfileManager` will be left in deactivated state,
+    /// unless the switch is make-before-break, in which case the old instance is kept.
+    pub fn switch_to(&mut self, profile: Profile) -> io::Result<StartProgress> {
+        self.begin_switch(profile, None)
+    }
+
+    /// Same as `Self::switch_to`, but if the new instance fails to start,
+    /// retry later with an increasing delay; see `Self::schedule_resume_retry`.
     ///
-    /// If the new instance fails to start, this `ProfileManager` will be left in deactivated state.
-    pub fn switch_to(&mut self, profile: Profile) -> io::Result<()> {
+    /// `attempt` is the number of retries so far.
+    pub fn resume(&mut self, profile: Profile, attempt: u32) -> io::Result<StartProgress> {
+        self.begin_switch(profile, Some(attempt))
+    }
+
+    /// Implementation of `Self::switch_to` & `Self::resume`.
+    fn begin_switch(&mut self, profile: Profile, resume_attempt: Option<u32>) -> io::Result<StartProgress> {
+        self.switch_generation += 1;
+        let make_before_break = match self.switch_strategy {
+            SwitchStrategy::BreakBeforeMake => false,
+            SwitchStrategy::MakeBeforeBreak => match self.ports_differ(&profile) {
+                Some(true) => true,
+                Some(false) => {
+                    debug!("New profile may listen on the same port; falling back to break-before-make");
+                    false
+                }
+                None => false, // inactive; nothing to break
+            },
+        };
+
+        // the profile cannot run twice, so it stops running alongside the active one
+        if let Some(slot) = self.concurrent_instances.remove(&profile.metadata.display_name) {
+            debug!("Stopping the instance of the new profile that runs alongside the old one");
+            let instance = util::rwlock_write(&slot).take();
+            if let Some(instance) = instance {
+                self.tear_down(instance, || {});
+            }
+        }
+
         // deactivate the old instance
-        let _ = self.try_stop();
+        if make_before_break {
+            self.cancel_pending_switch();
+        } else {
+            let _ = self.try_stop();
+        }
+
+        let awaited = self.teardowns_before(None);
+        let pending = PendingStart {
+            profile,
+            role: StartRole::Switch { make_before_break },
+            resume_attempt,
+            cancelled: false,
+        };
+        self.launch_or_defer(pending, awaited)
+    }
 
+    /// The second half of `Self::switch_to`, once no instance is stopping.
+    fn launch_switch(&mut self, profile: Profile, make_before_break: bool) -> io::Result<()> {
         // activate the new instance
-        let mut new_instance = ActiveSSInstance::new(profile)?;
+        let probe_profile = profile.probe_target().map(|_| profile.clone());
+        self.last_profile = Some(profile.clone());
+        let mut new_instance = self.new_instance(profile)?;
+        if make_before_break {
+            if let Err(err) = new_instance.wait_until_listening(LISTEN_TIMEOUT) {
+                self.tear_down(new_instance, || {});
+                return Err(err);
+            }
+            debug!("{} is listening; stopping the old instance", new_instance);
+        }
 
         // monitor for failure
-        let exit_alert_rx = new_instance.alert_on_exit()?;
+        let exit_alert_rx = match new_instance.alert_on_exit() {
+            Ok(rx) => rx,
+            Err(err) => {
+                self.tear_down(new_instance, || {});
+                return Err(err);
+            }
+        };
+        let launch_alert_rx = new_instance.launch_alert.clone();
 
-        // set
-        *util::rwlock_write(&self.active_instance) = Some(new_instance);
+        // set, then stop the old instance (if any) in the background
+        let old_instance = util::rwlock_write(&self.active_instance).replace(new_instance);
+        if let Some(old_instance) = old_instance {
+            self.tear_down(old_instance, || {});
+        }
 
         // pipe output
         self.log_piping_setup(OutputKind::Stdout)?;
         self.log_piping_setup(OutputKind::Stderr)?;
 
         // monitor
-        self.handle_fail(exit_alert_rx)?;
+        self.handle_fail(exit_alert_rx, launch_alert_rx)?;
+        self.switched_at = Some(Instant::now());
+
+        // check connectivity in the background
+        if let Some(p) = probe_profile {
+            if let Err(err) = connectivity::spawn_probe(p, self.events_tx.clone()) {
+                warn!("Failed to start the connectivity probe: {}", err);
+            }
+        }
 
         Ok(())
     }
 
+    /// Forget all previous restarts of the active profile,
+    /// so that auto-restart has its full budget again.
+    #[cfg(feature = "runtime-api")]
+    pub fn reset_restart_counter(&mut self) {
+        *mutex_lock(&self.restart_counter) = self.restart_limit().into();
+        *mutex_lock(&self.network_restart_counter) = self.restart_limit().into();
+    }
+
+    /// Count a restart triggered by a network change against the auto-restart limit,
+    /// so that a flapping network cannot restart the active profile excessively.
+    pub fn count_network_restart(&self) -> Result<(), LeakyBucketOverflowed> {
+        let limit = self.restart_limit();
+        let mut counter = mutex_lock(&self.network_restart_counter);
+        if counter.config() != limit {
+            debug!("Auto-restart limit has changed to {:?}", limit);
+            counter.reconfigure(limit);
+        }
+        counter.push()
+    }
+
+    /// Whether auto-restart is currently paused.
+    #[cfg(feature = "runtime-api")]
+    pub fn auto_restart_paused(&self) -> bool {
+        *util::rwlock_read(&self.auto_restart_paused)
+    }
+
+    /// Pause or resume auto-restart, without affecting the active instance.
+    #[cfg(feature = "runtime-api")]
+    pub fn set_auto_restart_paused(&mut self, paused: bool) {
+        *util::rwlock_write(&self.auto_restart_paused) = paused;
+    }
+
+    /// The current auto-restart limit.
+    pub fn restart_limit(&self) -> NaiveLeakyBucketConfig {
+        *util::rwlock_read(&self.restart_limit)
+    }
+
+    /// Change the auto-restart limit.
+    ///
+    /// Restarts already made are kept, and are counted against the new limit on the next restart.
+    #[cfg(feature = "runtime-api")]
+    pub fn set_restart_limit(&mut self, limit: NaiveLeakyBucketConfig) {
+        *util::rwlock_write(&self.restart_limit) = limit;
+    }
+
+    /// Check whether the currently active instance and the specified profile
+    /// are known to listen on different ports.
+    ///
+    /// Returns `None` if inactive.
+    fn ports_differ(&self, profile: &Profile) -> Option<bool> {
+        let instance_opt = util::rwlock_read(&self.active_instance);
+        let current = instance_opt.as_ref()?;
+        let differ = match (current.profile.socks_addr(), profile.socks_addr()) {
+            (Some(a), Some(b)) => a.port() != b.port(),
+            _ => false, // unknown, so assume collision
+        };
+        Some(differ)
+    }
+
     /// Convenience function to create a new broadcast listener.
+    #[cfg(feature = "gui")]
     pub fn new_listener(&self) -> BusReader<String> {
         mutex_lock(&self.logs_brd).add_rx()
     }
 
-    /// Stop the `sslocal` instance if active.
+    /// Stop the `sslocal` instance if active, and cancel any deferred switch.
+    ///
+    /// Returns immediately, while `sslocal` exits in the background; see `Self::tear_down`.
     ///
-    /// Returns `Err(())` if already inactive.
+    /// Returns `Err(())` if already inactive, and no switch was deferred.
     pub fn try_stop(&mut self) -> Result<(), ()> {
+        self.switch_generation += 1;
+        let cancelled = self.cancel_pending_switch();
         let instance = util::rwlock_write(&self.active_instance).take();
-        instance.map(|_| ()).ok_or(())
-        // `sslocal` instance dropped implicitly
+        match instance {
+            Some(instance) => {
+                self.tear_down(instance, || {});
+                Ok(())
+            }
+            None if cancelled => Ok(()),
+            None => Err(()),
+        }
+    }
+
+    /// Stop the `sslocal` instance if active on a separate thread,
+    /// then call `on_exit` once its process has exited.
+    ///
+    /// Like `Self::try_stop`, but `on_exit` lets the caller know once `sslocal` is dead
+    /// (and its ports are freed).
+    ///
+    /// Returns `Err(())` if already inactive, in which case `on_exit` is not called,
+    /// even if a deferred switch has been cancelled.
+    #[cfg(feature = "runtime-api")]
+    pub fn stop_then<F>(&mut self, on_exit: F) -> Result<(), ()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.switch_generation += 1;
+        self.cancel_pending_switch();
+        let instance = util::rwlock_write(&self.active_instance).take().ok_or(())?;
+        self.tear_down(instance, on_exit);
+        Ok(())
+    }
+
+    /// Stop an instance on a separate thread, then call `on_exit` once its process has exited.
+    ///
+    /// Stopping blocks for up to `Self::stop_timeout`, which would otherwise freeze the GTK main thread.
+    /// Starting another instance is deferred until the ones it would race with have exited;
+    /// see `Self::launch_or_defer`.
+    fn tear_down<F>(&mut self, instance: ActiveSSInstance, on_exit: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let port = listen_port(&instance.profile);
+        self.spawn_teardown(port, move || {
+            drop(instance); // terminates `sslocal` and joins daemon threads
+            on_exit();
+        });
+    }
+
+    /// Run the `post_down` hook of a profile that has not started, on a separate thread,
+    /// to undo whatever its `pre_up` hook has done.
+    ///
+    /// Later starts wait for it like for a stopping instance.
+    fn undo_pre_up(&mut self, profile: Profile) {
+        let backlog = Arc::clone(&self.backlog);
+        self.spawn_teardown(listen_port(&profile), move || run_post_down(&profile, &backlog));
+    }
+
+    /// Run `teardown` on a separate thread, keeping track of it; see `Self::tear_down`.
+    fn spawn_teardown<F>(&mut self, port: Option<u16>, teardown: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // forget the threads that are already done
+        self.teardowns.retain(|teardown| !teardown.handle.is_finished());
+        let (done_tx, done_rx) = unbounded_channel::<()>();
+        let spawn_res = thread::Builder::new().name("sslocal stopper".into()).spawn(move || {
+            teardown();
+            drop(done_tx);
+        });
+        match spawn_res {
+            Ok(handle) => self.teardowns.push(Teardown {
+                port,
+                handle,
+                done: done_rx,
+            }),
+            // the closure is dropped, and any instance along with it (hence killed)
+            Err(err) => error!("Failed to spawn a thread to stop sslocal: {}", err),
+        }
+    }
+
+    /// Block until all instances stopping in the background have exited.
+    fn await_teardowns(&mut self) {
+        if !self.teardowns.is_empty() {
+            debug!("Waiting for {} stopping instance(s) to exit", self.teardowns.len());
+        }
+        for teardown in self.teardowns.drain(..) {
+            if let Err(err) = teardown.handle.join() {
+                warn!("A thread stopping sslocal panicked unexpectedly: {:?}", err);
+            }
+        }
+    }
+
+    /// Get the instances stopping in the background that a new instance of `profile` has to wait for,
+    /// i.e. those that may listen on the same port; all of them if `profile` is `None`.
+    fn teardowns_before(&mut self, profile: Option<&Profile>) -> Vec<Receiver<()>> {
+        self.teardowns.retain(|teardown| !teardown.handle.is_finished());
+        let new_port = profile.map(listen_port);
+        self.teardowns
+            .iter()
+            .filter(|teardown| match new_port {
+                Some(new_port) => teardown.port.is_none() || new_port.is_none() || teardown.port == new_port,
+                None => true,
+            })
+            .map(|teardown| teardown.done.clone())
+            .collect()
+    }
+
+    /// Start a new instance right away if no instance in `awaited` is still stopping,
+    /// and its profile has no `pre_up` hook.
+    ///
+    /// Otherwise wait for them and run the hook on a separate thread, which sends an `AppEvent::StartReady`
+    /// once done; the start then continues in `Self::proceed`.
+    fn launch_or_defer(&mut self, pending: PendingStart, awaited: Vec<Receiver<()>>) -> io::Result<StartProgress> {
+        if awaited.is_empty() && !pending.profile.has_lifecycle_hook(LifecycleHook::PreUp) {
+            return self.launch(pending, Ok(())).map(|_| StartProgress::Started);
+        }
+
+        debug!(
+            "Starting profile \"{}\" once {} stopping instance(s) have exited and its pre_up hook has run",
+            pending.profile.metadata.display_name,
+            awaited.len()
+        );
+        let id = self.next_start_id;
+        self.next_start_id += 1;
+        let events_tx = self.events_tx.clone();
+        let profile = pending.profile.clone();
+        let backlog = Arc::clone(&self.backlog);
+        let handle = thread::Builder::new().name("sslocal starter".into()).spawn(move || {
+            for done in awaited {
+                let _ = done.recv(); // disconnects once the instance has exited
+            }
+            let pre_up = run_hook(&profile, LifecycleHook::PreUp, &backlog).map_err(|err| err.to_string());
+            if let Err(_) = events_tx.send(AppEvent::StartReady { id, pre_up }) {
+                error!("Trying to send StartReady event, but all receivers have hung up.");
+            }
+        })?;
+        self.daemon_handles.push(handle);
+        self.pending_starts.insert(id, pending);
+        Ok(StartProgress::Deferred)
+    }
+
+    /// Continue a start deferred by `Self::switch_to` or `Self::start`,
+    /// once the instances it was waiting for have exited, and its `pre_up` hook has run with result `pre_up`.
+    ///
+    /// A failed `pre_up` hook aborts the start.
+    ///
+    /// Returns `None` if the start has since been cancelled.
+    pub fn proceed(&mut self, id: u64, pre_up: Result<(), String>) -> Option<DeferredStart> {
+        let pending = self.pending_starts.remove(&id)?;
+        let profile = pending.profile.clone();
+        if pending.cancelled {
+            debug!(
+                "Profile \"{}\" is ready to start, but it has been cancelled since",
+                profile.metadata.display_name
+            );
+            if pre_up.is_ok() {
+                self.undo_pre_up(profile);
+            }
+            return None;
+        }
+        let concurrent = !matches!(pending.role, StartRole::Switch { .. });
+        let pre_up = pre_up.map_err(|err| io::Error::other(format!("pre_up hook failed: {}", err)));
+        let res = self.launch(pending, pre_up);
+        Some(DeferredStart {
+            profile,
+            concurrent,
+            res,
+        })
+    }
+
+    /// Cancel the deferred start of a new active instance, if any.
+    ///
+    /// Returns whether there was one.
+    fn cancel_pending_switch(&mut self) -> bool {
+        let mut cancelled = false;
+        for pending in self.pending_starts.values_mut() {
+            if !pending.cancelled && matches!(pending.role, StartRole::Switch { .. }) {
+                pending.cancelled = true;
+                cancelled = true;
+            }
+        }
+        cancelled
+    }
+
+    /// Start a new instance, unless its `pre_up` hook has failed.
+    ///
+    /// Schedules a retry if it's being resumed and has failed.
+    fn launch(&mut self, pending: PendingStart, pre_up: io::Result<()>) -> io::Result<()> {
+        let PendingStart {
+            profile,
+            role,
+            resume_attempt,
+            ..
+        } = pending;
+        let res = pre_up.and_then(|_| match role {
+            StartRole::Switch { make_before_break } => self.launch_switch(profile.clone(), make_before_break),
+            #[cfg(any(feature = "gui", feature = "runtime-api"))]
+            StartRole::Concurrent => self.launch_concurrent(profile.clone()),
+        });
+        if let (Err(_), Some(attempt)) = (&res, resume_attempt) {
+            self.schedule_resume_retry(profile, attempt + 1);
+        }
+        res
+    }
+
+    /// Start a new instance whose profile's `pre_up` hook has already run.
+    ///
+    /// If it fails to start, its `post_down` hook runs in the background; see `Self::undo_pre_up`.
+    fn new_instance(&mut self, profile: Profile) -> io::Result<ActiveSSInstance> {
+        let res = ActiveSSInstance::new(
+            profile.clone(),
+            self.pid_registry.clone(),
+            self.stop_timeout,
+            self.launch_timeout,
+            Arc::clone(&self.backlog),
+            InstanceHooks::PostDown,
+        );
+        if res.is_err() {
+            self.undo_pre_up(profile);
+        }
+        res
     }
 
     /// Start a daemon that subscribes to an output broadcast of
@@ -321,7 +1532,7 @@ impl ProfileManager {
         let backlog = Arc::clone(&self.backlog);
 
         // create thread
-        let handle = log_piping_setup_impl(&instance, output_kind, re_brd, backlog)?;
+        let handle = log_piping_setup_impl(instance, output_kind, re_brd, backlog)?;
         self.daemon_handles.push(handle);
 
         Ok(())
@@ -330,16 +1541,44 @@ impl ProfileManager {
     /// Starts a monitoring thread that waits for the underlying `sslocal` instance
     /// to fail, when it will attempt to perform a restart as specified by
     /// `Self::restart_limit`.
-    fn handle_fail(&mut self, listener: Receiver<ExitStatus>) -> io::Result<()> {
+    ///
+    /// If the instance fails to launch within its profile's launch timeout,
+    /// it is stopped without restarting.
+    fn handle_fail(&mut self, listener: Receiver<ExitStatus>, launch_listener: Receiver<()>) -> io::Result<()> {
+        let instance = Arc::clone(&self.active_instance);
+        let restart_counter = Arc::clone(&self.restart_counter);
+        self.spawn_failure_monitor(instance, restart_counter, true, listener, launch_listener)
+    }
+
+    /// Starts the failure monitor of an instance; see `Self::handle_fail`.
+    ///
+    /// Unless `primary` is set, i.e. the instance runs alongside the active one,
+    /// its stoppage is reported with `AppEvent::ConcurrentStop` instead.
+    fn spawn_failure_monitor(
+        &mut self,
+        instance: InstanceSlot,
+        restart_counter: Arc<Mutex<NaiveLeakyBucket>>,
+        primary: bool,
+        listener: Receiver<ExitStatus>,
+        launch_listener: Receiver<()>,
+    ) -> io::Result<()> {
+        // a new profile (or a restart) starts with a full budget
+        *mutex_lock(&restart_counter) = self.restart_limit().into();
+
         // variables that need to be moved into thread
-        let restart_limit = self.restart_limit;
+        let restart_limit = Arc::clone(&self.restart_limit);
+        let total_restarts = Arc::clone(&self.total_restarts);
+        let auto_restart_paused = Arc::clone(&self.auto_restart_paused);
         let events_tx = self.events_tx.clone();
-        let instance = Arc::clone(&self.active_instance);
-        let profile = self
-            .current_profile()
+        let profile = util::rwlock_read(&instance)
+            .as_ref()
+            .map(|inst| inst.profile.clone())
             .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Not active"))?;
         let logs_brd = Arc::clone(&self.logs_brd);
         let backlog = Arc::clone(&self.backlog);
+        let pid_registry = self.pid_registry.clone();
+        let stop_timeout = self.stop_timeout;
+        let launch_timeout = self.launch_timeout;
 
         // create thread
         let handle = thread::Builder::new()
@@ -348,31 +1587,68 @@ impl ProfileManager {
                 // profile stays the same across restarts, therefore outside of loop
                 let profile_name = profile.metadata.display_name.clone();
                 let mut exit_listener = listener; // is set to new listener in every iteration
-                let mut restart_counter: NaiveLeakyBucket = restart_limit.into();
+                let mut launch_listener = launch_listener; // ditto
+
+                // the event to send once the instance stops; see `stop_event`
+                let stop_event = |instance_name, failure| stop_event(primary, &profile_name, instance_name, failure);
+
+                // the name of the instance being watched, if any
+                let mut watched_name = None;
+
+                let restart_ctx = RestartContext {
+                    profile: profile.clone(),
+                    pid_registry,
+                    stop_timeout,
+                    launch_timeout,
+                    logs_brd,
+                    backlog: Arc::clone(&backlog),
+                };
 
                 // restart loop can exit for a variety of reasons; see code
                 loop {
                     let instance_name = match &*util::rwlock_read(&instance) {
-                        Some(inst) => inst.to_string(),
+                        Some(inst) => {
+                            let name = inst.to_string();
+                            watched_name = Some(name.clone());
+                            name
+                        }
                         None => {
-                            debug!("ProfileManager has been set to inactive; auto-restart stopped");
-                            if let Err(_) = events_tx.send(AppEvent::OkStop { instance_name: None }) {
+                            debug!(
+                                "Instance of profile \"{}\" has been stopped; auto-restart stopped",
+                                profile_name
+                            );
+                            if let Err(_) = events_tx.send(stop_event(None, None)) {
                                 error!("Trying to send OkStop event, but all receivers have hung up.");
                             }
                             break;
                         }
                     };
 
-                    // wait for `sslocal` instance exit signal
-                    match exit_listener.recv() {
+                    // wait for `sslocal` instance exit signal, or launch timeout
+                    let exit_res = select! {
+                        recv(exit_listener) -> res => res,
+                        recv(launch_listener) -> res => {
+                            if res.is_err() {
+                                // launched successfully; stop watching
+                                launch_listener = never();
+                                continue;
+                            }
+                            error!("{} has timed out while launching; auto-restart stopped", instance_name);
+                            let err = "sslocal did not start listening in time".into();
+                            let failure = Some((err, classify_recent_output(&backlog)));
+                            if let Err(_) = events_tx.send(stop_event(Some(instance_name), failure)) {
+                                error!("Trying to send ErrorStop event, but all receivers have hung up.");
+                            }
+                            break;
+                        }
+                    };
+                    match exit_res {
                         Ok(status) if status.success() => {
                             // most likely because `ActiveInstance` gets dropped
                             // causing `sslocal` to exit gracefully,
                             // or if the user calls `sslocal --version` or something
                             debug!("{} has exited successfully; auto-restart stopped", instance_name);
-                            if let Err(_) = events_tx.send(AppEvent::OkStop {
-                                instance_name: Some(instance_name),
-                            }) {
+                            if let Err(_) = events_tx.send(stop_event(Some(instance_name), None)) {
                                 error!("Trying to send OkStop event, but all receivers have hung up.");
                             }
                             break;
@@ -383,10 +1659,43 @@ impl ProfileManager {
                                 "The exit alert daemon for {} has hung up: {}; auto-restart stopped",
                                 instance_name, err
                             );
-                            if let Err(_) = events_tx.send(AppEvent::ErrorStop {
-                                instance_name: Some(instance_name),
-                                err: err.to_string(),
-                            }) {
+                            if let Err(_) =
+                                events_tx.send(stop_event(Some(instance_name), Some((err.to_string(), None))))
+                            {
+                                error!("Trying to send ErrorStop event, but all receivers have hung up.");
+                            }
+                            break;
+                        }
+                        Ok(bad_status)
+                            if util::rwlock_read(&instance).as_ref().map(|inst| inst.to_string()) != watched_name =>
+                        {
+                            // the instance has been stopped deliberately, but was killed after not exiting in time
+                            debug!(
+                                "{} was stopped with exit status {}; auto-restart stopped",
+                                instance_name, bad_status
+                            );
+                            if let Err(_) = events_tx.send(stop_event(Some(instance_name), None)) {
+                                error!("Trying to send OkStop event, but all receivers have hung up.");
+                            }
+                            break;
+                        }
+                        Ok(bad_status) if *util::rwlock_read(&auto_restart_paused) => {
+                            warn!(
+                                "{} has failed with {}; auto-restart is paused, so it's left stopped",
+                                instance_name, bad_status
+                            );
+                            if let Err(_) = events_tx.send(stop_event(Some(instance_name), None)) {
+                                error!("Trying to send OkStop event, but all receivers have hung up.");
+                            }
+                            break;
+                        }
+                        Ok(bad_status) if profile.on_failure() == OnFailure::Halt => {
+                            error!(
+                                "{} has failed with {}; profile \"{}\" is set to halt on failure",
+                                instance_name, bad_status, profile_name
+                            );
+                            let failure = Some((bad_status.to_string(), classify_recent_output(&backlog)));
+                            if let Err(_) = events_tx.send(stop_event(Some(instance_name), failure)) {
                                 error!("Trying to send ErrorStop event, but all receivers have hung up.");
                             }
                             break;
@@ -398,49 +1707,58 @@ impl ProfileManager {
                         }
                     }
 
-                    // Check if restart counter has overflowed
-                    if let Err(err) = restart_counter.push() {
+                    // Check if restart counter has overflowed, using the latest limit
+                    let (push_res, capacity, remaining) = {
+                        let limit = *util::rwlock_read(&restart_limit);
+                        let mut counter = mutex_lock(&restart_counter);
+                        if counter.config() != limit {
+                            debug!("Auto-restart limit has changed to {:?}", limit);
+                            counter.reconfigure(limit);
+                        }
+                        (counter.push(), counter.capacity(), counter.remaining())
+                    };
+                    if let Err(err) = push_res {
                         error!(
                             "sslocal exits excessively with profile \"{}\"; auto-restart stopped",
                             profile_name
                         );
                         error!("{}", err);
-                        if let Err(_) = events_tx.send(AppEvent::ErrorStop {
-                            instance_name: Some(instance_name),
-                            err: err.to_string(),
-                        }) {
+                        let failure = Some((err.to_string(), classify_recent_output(&backlog)));
+                        if let Err(_) = events_tx.send(stop_event(Some(instance_name), failure)) {
                             error!("Trying to send ErrorStop event, but all receivers have hung up.");
                         }
                         break;
                     }
 
+                    // Warn if restart counter is close to overflowing
+                    if (capacity - remaining) * 5 >= capacity * 4 {
+                        warn!(
+                            "Profile \"{}\" can only be restarted {} more time(s) before auto-restart stops",
+                            profile_name, remaining
+                        );
+                        if let Err(_) = events_tx.send(AppEvent::RestartBudgetLow {
+                            profile_name: profile_name.clone(),
+                            remaining,
+                        }) {
+                            error!("Trying to send RestartBudgetLow event, but all receivers have hung up.");
+                        }
+                    }
+
                     // Restart
-                    /// Temporary helper builder function to simplify error handling.
-                    fn start_pipe_alert(
-                        profile: Profile,
-                        re_brd: Arc<Mutex<Bus<String>>>,
-                        backlog: Arc<Mutex<String>>,
-                        exit_listener: &mut Receiver<ExitStatus>,
-                    ) -> io::Result<ActiveSSInstance> {
-                        let mut instance = ActiveSSInstance::new(profile)?;
-                        log_piping_setup_impl(
-                            &instance,
-                            OutputKind::Stdout,
-                            Arc::clone(&re_brd),
-                            Arc::clone(&backlog),
-                        )?;
-                        log_piping_setup_impl(&instance, OutputKind::Stderr, re_brd, backlog)?;
-                        *exit_listener = instance.alert_on_exit()?;
-                        Ok(instance)
+                    // the failed instance is only dropped once replaced, so run its `post_down` hook now,
+                    // lest it undo the `pre_up` hook of the new instance
+                    let post_down_pending = util::rwlock_write(&instance)
+                        .as_mut()
+                        .filter(|inst| Some(inst.to_string()) == watched_name)
+                        .map(|inst| std::mem::replace(&mut inst.post_down_pending, false));
+                    if post_down_pending == Some(true) {
+                        if let Err(err) = run_hook(&profile, LifecycleHook::PostDown, &backlog) {
+                            warn!("post_down hook of {} failed: {}", instance_name, err);
+                        }
                     }
 
                     let new_instance = {
-                        let start_res = start_pipe_alert(
-                            profile.clone(),
-                            Arc::clone(&logs_brd),
-                            Arc::clone(&backlog),
-                            &mut exit_listener,
-                        );
+                        let start_res = restart_ctx.start_pipe_alert(&mut exit_listener, &mut launch_listener);
                         match start_res {
                             Ok(p) => p,
                             Err(err) => {
@@ -448,10 +1766,8 @@ impl ProfileManager {
                                     "Failed to restart with profile \"{}\": {}. Failure monitor daemon stopping",
                                     profile_name, err
                                 );
-                                if let Err(_) = events_tx.send(AppEvent::ErrorStop {
-                                    instance_name: Some(instance_name),
-                                    err: err.to_string(),
-                                }) {
+                                let failure = Some((err.to_string(), None));
+                                if let Err(_) = events_tx.send(stop_event(Some(instance_name), failure)) {
                                     error!("Trying to send ErrorStop event, but all receivers have hung up.");
                                 }
                                 break;
@@ -460,10 +1776,26 @@ impl ProfileManager {
                     };
 
                     // Set new active instance
+                    let new_instance_name = new_instance.to_string();
                     *util::rwlock_write(&instance) = Some(new_instance);
+                    total_restarts.fetch_add(1, Ordering::Relaxed);
+                    if let Err(_) = events_tx.send(AppEvent::RestartOk {
+                        instance_name: new_instance_name,
+                        attempt: capacity - remaining,
+                    }) {
+                        error!("Trying to send RestartOk event, but all receivers have hung up.");
+                    }
                 }
-                // loop exit means we should leave ProfileManager inactive
-                drop(util::rwlock_write(&instance).take());
+                // loop exit means we should leave ProfileManager inactive,
+                // unless the watched instance has already been replaced by another one
+                let stale = {
+                    let mut instance_opt = util::rwlock_write(&instance);
+                    match instance_opt.as_ref().map(|inst| inst.to_string()) == watched_name {
+                        true => instance_opt.take(),
+                        false => None,
+                    }
+                };
+                drop(stale);
             })?;
         self.daemon_handles.push(handle);
 
@@ -471,13 +1803,119 @@ impl ProfileManager {
     }
 }
 
+/// Everything the failure monitor daemon needs to start a replacement instance.
+struct RestartContext {
+    profile: Profile,
+    pid_registry: Option<PidRegistry>,
+    stop_timeout: Duration,
+    launch_timeout: Option<Duration>,
+    logs_brd: Arc<Mutex<Bus<String>>>,
+    backlog: Arc<Mutex<Backlog>>,
+}
+
+impl RestartContext {
+    /// Start a new instance and pipe its logs, then point
+    /// `exit_listener` & `launch_listener` at its alerts.
+    fn start_pipe_alert(
+        &self,
+        exit_listener: &mut Receiver<ExitStatus>,
+        launch_listener: &mut Receiver<()>,
+    ) -> io::Result<ActiveSSInstance> {
+        // on the failure monitor's own thread, hence free to block on `pre_up`
+        let mut instance = ActiveSSInstance::new(
+            self.profile.clone(),
+            self.pid_registry.clone(),
+            self.stop_timeout,
+            self.launch_timeout,
+            Arc::clone(&self.backlog),
+            InstanceHooks::Both,
+        )?;
+        for output_kind in [OutputKind::Stdout, OutputKind::Stderr] {
+            log_piping_setup_impl(
+                &instance,
+                output_kind,
+                Arc::clone(&self.logs_brd),
+                Arc::clone(&self.backlog),
+            )?;
+        }
+        *exit_listener = instance.alert_on_exit()?;
+        *launch_listener = instance.launch_alert.clone();
+        Ok(instance)
+    }
+}
+
+/// Build the event reporting that an instance has stopped, and will not be restarted.
+///
+/// `failure` is the error and its recognised cause, if the instance has failed.
+/// Unless `primary` is set, the instance runs alongside the active one.
+fn stop_event(
+    primary: bool,
+    profile_name: &str,
+    instance_name: Option<String>,
+    failure: Option<(String, Option<SslocalErrorKind>)>,
+) -> AppEvent {
+    match (primary, failure) {
+        (true, None) => AppEvent::OkStop { instance_name },
+        (true, Some((err, kind))) => AppEvent::ErrorStop {
+            instance_name,
+            err,
+            kind,
+        },
+        (false, None) => AppEvent::ConcurrentStop {
+            profile_name: profile_name.into(),
+            err: None,
+            kind: None,
+        },
+        (false, Some((err, kind))) => AppEvent::ConcurrentStop {
+            profile_name: profile_name.into(),
+            err: Some(err),
+            kind,
+        },
+    }
+}
+
+/// Try to recognise the cause of a failure from the tail of the backlog.
+fn classify_recent_output(backlog: &Mutex<Backlog>) -> Option<SslocalErrorKind> {
+    let backlog = mutex_lock(backlog);
+    match backlog.read_page(backlog.len(), 4096) {
+        Ok((_, tail)) => SslocalErrorKind::classify(&tail),
+        Err(err) => {
+            warn!("Failed to read backlog to classify failure: {}", err);
+            None
+        }
+    }
+}
+
+/// Broadcast a line without blocking.
+///
+/// If the channel is full (i.e. a listener is lagging behind), the line is dropped
+/// and counted in `dropped` instead. Once there is room again, a marker reporting
+/// the number of dropped lines is sent first, so that the loss is visible to listeners.
+///
+/// Returns whether the line was sent.
+fn try_broadcast_or_count(brd: &mut Bus<String>, line: String, output_kind: OutputKind, dropped: &mut usize) -> bool {
+    // make previous loss visible to listeners
+    if *dropped > 0 {
+        let marker = format!("[{}] … {} lines dropped …\n", output_kind, dropped);
+        if brd.try_broadcast(marker).is_ok() {
+            *dropped = 0;
+        }
+    }
+    // preserve order; don't send this line before the marker
+    if *dropped > 0 || brd.try_broadcast(line).is_err() {
+        *dropped += 1;
+        return false;
+    }
+    true
+}
+
 /// This is not an associated function because it has to be called by
 /// threads created by `ProfileManager::handle_fail`.
 fn log_piping_setup_impl(
     instance: &ActiveSSInstance,
     output_kind: OutputKind,
     re_brd: Arc<Mutex<Bus<String>>>,
-    backlog: Arc<Mutex<String>>,
+    backlog: Arc<Mutex<Backlog>>,
 ) -> io::Result<JoinHandle<()>> {
     // variables that need to be moved into thread
     let instance_name = instance.to_string();
@@ -487,12 +1925,22 @@ fn log_piping_setup_impl(
         .name(format!("{} log porter daemon for {}", output_kind, instance_name))
         .spawn(move || {
             trace!("{} log porter daemon for {} started", output_kind, instance_name);
+            // number of lines dropped since the last successful rebroadcast
+            let mut dropped = 0usize;
             for line in listener.iter() {
                 // doing those two in reverse to eliminate `line.clone()` call
                 // append to backlog
-                mutex_lock(&backlog).push_str(&line);
-                // rebroadcast
-                mutex_lock(&re_brd).broadcast(line);
+                if let Err(err) = mutex_lock(&backlog).push_str(&line) {
+                    warn!("Failed to append to backlog: {}", err);
+                }
+                // rebroadcast without blocking, so that a slow listener (e.g. the log viewer)
+                // cannot hold up the backlog, or cause lines to be dropped from it
+                if !try_broadcast_or_count(&mut mutex_lock(&re_brd), line, output_kind, &mut dropped) {
+                    debug!(
+                        "A logs listener of {} is lagging behind; dropped a line from its view",
+                        instance_name
+                    );
+                }
             }
             // thread exits when broadcast stops
         })
@@ -533,6 +1981,9 @@ mod test {
         // run through all example profiles
         for p in profile_list {
             println!();
+            // so that the switch is not deferred
+            let _ = mgr.try_stop();
+            mgr.await_teardowns();
             mgr.switch_to(p.clone()).unwrap();
             let (mut stdout_listener, mut stderr_listener) = {
                 let instance_opt = rwlock_read(&mgr.active_instance);
@@ -548,4 +1999,43 @@ mod test {
         }
         let _ = mgr.try_stop();
     }
+
+    #[test]
+    fn broadcast_drops_are_reported() {
+        let mut brd = Bus::new(2);
+        let mut listener = brd.add_rx();
+        let mut dropped = 0;
+        let sent: Vec<_> = (0..4)
+            .map(|i| try_broadcast_or_count(&mut brd, format!("{}\n", i), OutputKind::Stdout, &mut dropped))
+            .collect();
+        assert_eq!(sent, [true, true, false, false]);
+        assert_eq!(dropped, 2);
+
+        // once the listener catches up, the loss is reported before the next line
+        let received: Vec<_> = std::iter::from_fn(|| listener.try_recv().ok()).collect();
+        assert_eq!(received, ["0\n", "1\n"]);
+        assert!(try_broadcast_or_count(
+            &mut brd,
+            "4\n".into(),
+            OutputKind::Stdout,
+            &mut dropped
+        ));
+        assert_eq!(dropped, 0);
+        let received: Vec<_> = std::iter::from_fn(|| listener.try_recv().ok()).collect();
+        assert_eq!(received, ["[stdout] … 2 lines dropped …\n", "4\n"]);
+    }
+
+    #[test]
+    fn parse_stat_lines() {
+        let parse = InstanceStats::parse_line;
+        assert_eq!(
+            parse("2022-10-01T12:00:00 INFO stat: tx=1024 rx=4096"),
+            Some((1024, 4096))
+        );
+        assert_eq!(parse("stat: rx=2 conns=3 tx=1"), Some((1, 2)));
+        // not a stat line, or missing/malformed counts
+        assert_eq!(parse("INFO shadowsocks socks TCP listening on 127.0.0.1:1080"), None);
+        assert_eq!(parse("stat: tx=1024"), None);
+        assert_eq!(parse("stat: tx=-1 rx=0"), None);
+    }
 }
@@ -1,9 +1,10 @@
 //! This module contains code that define the CLI API.
 
-use std::path::PathBuf;
+use std::{ffi::OsStr, path::PathBuf};
 
 use clap::{Parser, Subcommand};
-use shadowsocks_gtk_rs::{consts::*, notify_method::NotifyMethod, runtime_api_msg::APICommand};
+use clap_complete::Shell;
+use shadowsocks_gtk_rs::{log_level::LogLevel, notify_method::NotifyMethod, runtime_api_msg::APICommand};
 
 #[derive(Debug, Clone, Parser)]
 #[clap(
@@ -18,10 +19,11 @@ pub struct CliArgs {
     /// Send command to the runtime API listener at a custom socket path.
     ///
     /// Useful if you want to control multiple instances.
-    #[clap(short = 'a', long = "api-socket", value_name = "PATH", default_value_os = RUNTIME_API_SOCKET_PATH_DEFAULT.as_os_str())]
+    #[clap(short = 'a', long = "api-socket", value_name = "PATH", default_value_os = default_api_socket())]
     pub runtime_api_socket_path: PathBuf,
 
-    /// Print examples of how to interface with the Unix socket directly.
+    /// Print examples of how to interface with the Unix socket directly,
+    /// one for each command the runtime API accepts.
     #[clap(long = "print-socket-examples")]
     pub print_socket_examples: bool,
 
@@ -29,6 +31,18 @@ pub struct CliArgs {
     pub sub_cmd: Option<SubCmd>,
 }
 
+/// The default runtime API socket path.
+///
+/// It's only known if there's a runtime directory, so tests use a fixed path instead.
+#[cfg(not(test))]
+fn default_api_socket() -> &'static OsStr {
+    shadowsocks_gtk_rs::consts::RUNTIME_API_SOCKET_PATH_DEFAULT.as_os_str()
+}
+#[cfg(test)]
+fn default_api_socket() -> &'static OsStr {
+    OsStr::new("/run/user/0/TEST.sock")
+}
+
 #[derive(Debug, Clone, Subcommand)]
 #[clap(rename_all = "kebab-case")]
 pub enum SubCmd {
@@ -45,6 +59,41 @@ pub enum SubCmd {
         notify_method: NotifyMethod,
     },
 
+    /// Set how long toast notifications stay on screen.
+    SetNotifyTimeout {
+        /// The timeout in milliseconds; `0` or `never` to never expire.
+        /// Omit to use the notification server's default.
+        #[clap(index = 1, value_name = "MS", value_parser = parse_notify_timeout)]
+        timeout_ms: Option<u32>,
+
+        /// Keep error notifications on screen until dismissed, regardless of the timeout.
+        #[clap(long = "sticky-errors")]
+        sticky_errors: bool,
+    },
+
+    /// Set the log level of ssgtk itself (not sslocal).
+    SetLogLevel {
+        /// The log level to use.
+        #[clap(index = 1, value_name = "LEVEL", value_enum)]
+        log_level: LogLevel,
+    },
+
+    /// Look up the tray icons again, e.g. after installing a new icon theme.
+    ReloadIcon,
+
+    /// Use a different tray icon, with variants derived as with `ssgtk --icon-name`.
+    ///
+    /// The current icon is kept if the new one cannot be found.
+    SetIcon {
+        /// The name of the icon to use while a profile is running.
+        #[clap(index = 1, value_name = "NAME")]
+        name: String,
+
+        /// Also search for icons in this directory; defaults to the current one.
+        #[clap(long = "theme-dir", value_name = "DIR")]
+        theme_dir: Option<PathBuf>,
+    },
+
     /// Restart the currently running sslocal instance.
     Restart,
 
@@ -55,11 +104,159 @@ pub enum SubCmd {
         profile_name: String,
     },
 
+    /// Start a profile alongside the currently running one, e.g. a proxy next to a tunnel.
+    ///
+    /// The profiles must listen on different ports.
+    StartProfile {
+        /// The display name of the profile to start (CASE SENSITIVE)
+        #[clap(index = 1, value_name = "NAME")]
+        profile_name: String,
+    },
+
+    /// Stop a profile started with `start-profile`.
+    StopProfile {
+        /// The display name of the profile to stop (CASE SENSITIVE)
+        #[clap(index = 1, value_name = "NAME")]
+        profile_name: String,
+    },
+
+    /// Switch to the next profile in the order shown in the tray, wrapping around.
+    ///
+    /// Starts the first profile if none is running.
+    NextProfile,
+
+    /// Switch to the previous profile in the order shown in the tray, wrapping around.
+    ///
+    /// Starts the last profile if none is running.
+    PrevProfile,
+
     /// Stop the currently running sslocal instance.
-    Stop,
+    Stop {
+        /// Only return after sslocal has exited, e.g. to make sure its ports are free.
+        #[clap(long = "wait")]
+        wait: bool,
+    },
+
+    /// Stop the currently running sslocal instance if any,
+    /// otherwise start the most recent profile again.
+    Toggle,
 
     /// Quit the application.
-    Quit,
+    Quit {
+        /// Confirm the quit; required if ssgtk is run with `--api-require-quit-confirm`.
+        #[clap(long = "confirm")]
+        confirm: bool,
+    },
+
+    /// Reset the auto-restart counter of the current profile, restoring its full restart budget.
+    ResetRestarts,
+
+    /// Leave sslocal stopped when it fails instead of auto-restarting it, e.g. to debug a crash.
+    ///
+    /// The running instance is unaffected. Use `resume-restarts` to undo.
+    PauseRestarts,
+
+    /// Auto-restart sslocal when it fails again, after `pause-restarts`.
+    ResumeRestarts,
+
+    /// Change how many times sslocal can be auto-restarted within a time window,
+    /// taking effect on the next restart.
+    SetRestartLimit {
+        /// The maximum number of restarts within the time window.
+        #[clap(index = 1, value_name = "TIMES")]
+        times: usize,
+        /// The length of the time window in seconds.
+        #[clap(index = 2, value_name = "SECONDS")]
+        within_secs: u64,
+    },
+
+    /// Re-scan the profiles directory for added, changed, or removed profiles.
+    ///
+    /// The running sslocal instance is kept running, even if its profile has been removed.
+    ReloadProfiles,
+
+    /// Temporarily route traffic around the tunnel of the current "tun"-type profile,
+    /// by running its bypass hook.
+    Bypass {
+        /// Run the restore hook instead, routing traffic through the tunnel again.
+        #[clap(long = "off")]
+        off: bool,
+    },
+
+    /// Query the status of the application, including traffic statistics if available.
+    Query {
+        /// Print the reply as JSON5, e.g. for scripted health checks.
+        #[clap(long = "json")]
+        json: bool,
+    },
+
+    /// Find profiles whose display name contains a substring (case-insensitive).
+    Find {
+        /// The substring to search for.
+        #[clap(index = 1, value_name = "SUBSTRING")]
+        pattern: String,
+    },
+
+    /// List all loaded profiles, indented by group.
+    ListProfiles {
+        /// Print the reply as JSON5, e.g. for shell completion scripts.
+        #[clap(long = "json")]
+        json: bool,
+    },
+
+    /// Print the PIDs of the running sslocal instance, one per line, e.g. to attach a debugger.
+    ///
+    /// Prints nothing if sslocal is not running.
+    Pids {
+        /// Print the reply as JSON5 instead.
+        #[clap(long = "json")]
+        json: bool,
+    },
+
+    /// Print metrics (restart counts, time since the last switch, etc.)
+    /// in the Prometheus text exposition format, e.g. for a textfile collector.
+    Metrics,
+
+    /// Print the most recently probed latency to each profile's server.
+    ///
+    /// Profiles without a known server address (i.e. "config-file"-type profiles) are left out.
+    Latencies {
+        /// Print the reply as JSON5 instead.
+        #[clap(long = "json")]
+        json: bool,
+    },
+
+    /// Start sslocal with a profile for a few seconds without switching to it,
+    /// then report whether it started cleanly, and its output.
+    ///
+    /// Exits with status 1 if sslocal has exited during the test.
+    TestProfile {
+        /// The name of the profile to test.
+        #[clap(index = 1, value_name = "NAME")]
+        profile_name: String,
+
+        /// Print the reply as JSON5 instead.
+        #[clap(long = "json")]
+        json: bool,
+    },
+
+    /// Print the logs of sslocal.
+    Logs {
+        /// Only print the last N lines.
+        #[clap(short = 'n', long = "lines", value_name = "N")]
+        lines: Option<usize>,
+    },
+
+    /// Print a completion script for a shell to stdout.
+    ///
+    /// Profile names are not completed, since they are only known to the running ssgtk;
+    /// see the README for how to complete them with `ssgtkctl list-profiles --json`.
+    #[clap(hide = true)]
+    GenerateCompletions {
+        /// The shell to generate the completion script for.
+        #[clap(index = 1, value_name = "SHELL", value_enum)]
+        shell: Shell,
+    },
 }
 
 impl From<SubCmd> for APICommand {
@@ -68,10 +265,95 @@ impl From<SubCmd> for APICommand {
             SubCmd::LogViewerShow => APICommand::LogViewerShow,
             SubCmd::LogViewerHide => APICommand::LogViewerHide,
             SubCmd::SetNotify { notify_method } => APICommand::SetNotify(notify_method),
+            SubCmd::SetNotifyTimeout {
+                timeout_ms,
+                sticky_errors,
+            } => APICommand::SetNotifyTimeout {
+                ms: timeout_ms,
+                sticky_errors,
+            },
+            SubCmd::SetLogLevel { log_level } => APICommand::SetLogLevel(log_level),
+            SubCmd::ReloadIcon => APICommand::ReloadIcon,
+            SubCmd::SetIcon { name, theme_dir } => APICommand::SetIcon { name, theme_dir },
             SubCmd::Restart => APICommand::Restart,
             SubCmd::SwitchProfile { profile_name } => APICommand::SwitchProfile(profile_name),
-            SubCmd::Stop => APICommand::Stop,
-            SubCmd::Quit => APICommand::Quit,
+            SubCmd::StartProfile { profile_name } => APICommand::StartProfile(profile_name),
+            SubCmd::StopProfile { profile_name } => APICommand::StopProfile(profile_name),
+            SubCmd::Stop { wait: false } => APICommand::Stop,
+            SubCmd::Stop { wait: true } => APICommand::StopWait,
+            SubCmd::Toggle => APICommand::Toggle,
+            SubCmd::NextProfile => APICommand::NextProfile,
+            SubCmd::PrevProfile => APICommand::PrevProfile,
+            SubCmd::Quit { confirm: false } => APICommand::Quit,
+            SubCmd::Quit { confirm: true } => APICommand::QuitConfirmed,
+            SubCmd::ResetRestarts => APICommand::ResetRestartCounter,
+            SubCmd::PauseRestarts => APICommand::PauseAutoRestart,
+            SubCmd::ResumeRestarts => APICommand::ResumeAutoRestart,
+            SubCmd::SetRestartLimit { times, within_secs } => APICommand::SetRestartLimit { times, within_secs },
+            SubCmd::ReloadProfiles => APICommand::ReloadProfiles,
+            SubCmd::Bypass { off } => APICommand::Bypass { enable: !off },
+            SubCmd::Query { .. } => APICommand::Query,
+            SubCmd::Find { pattern } => APICommand::FindProfiles(pattern),
+            SubCmd::ListProfiles { .. } => APICommand::ListProfiles,
+            SubCmd::Pids { .. } => APICommand::GetPids,
+            SubCmd::Metrics => APICommand::Metrics,
+            SubCmd::Latencies { .. } => APICommand::GetLatencies,
+            SubCmd::Logs { lines } => APICommand::GetBacklog { lines },
+            SubCmd::TestProfile { profile_name, .. } => APICommand::TestProfile(profile_name),
+            SubCmd::GenerateCompletions { .. } => unreachable!("handled locally without the runtime API"),
+        }
+    }
+}
+
+/// Parse a notification timeout in milliseconds, accepting `never` for `0`.
+fn parse_notify_timeout(s: &str) -> Result<u32, String> {
+    match s {
+        "never" => Ok(0),
+        _ => s
+            .parse()
+            .map_err(|_| format!("expected milliseconds or \"never\", got \"{}\"", s)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::IntoApp;
+    use clap_complete::Shell;
+
+    use super::CliArgs;
+
+    #[test]
+    fn completions_cover_all_subcommands() {
+        let cmd = CliArgs::command();
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let mut script = vec![];
+            clap_complete::generate(shell, &mut cmd.clone(), "ssgtkctl", &mut script);
+            let script = String::from_utf8(script).unwrap();
+            let subs: Vec<_> = cmd.get_subcommands().filter(|sub| !sub.is_hide_set()).collect();
+            for sub in &subs {
+                assert!(
+                    script.contains(sub.get_name()),
+                    "{} script is missing {:?}",
+                    shell,
+                    sub.get_name()
+                );
+            }
+            for sub in std::iter::once(&cmd).chain(subs) {
+                let values = sub
+                    .get_arguments()
+                    .filter(|arg| !arg.is_hide_set())
+                    .filter_map(|arg| arg.get_value_parser().possible_values())
+                    .flatten();
+                for value in values {
+                    assert!(
+                        script.contains(value.get_name()),
+                        "{} script is missing value {:?} of {:?}",
+                        shell,
+                        value.get_name(),
+                        sub.get_name()
+                    );
+                }
+            }
         }
     }
 }
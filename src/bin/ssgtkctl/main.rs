@@ -1,14 +1,19 @@
 use std::{
-    io::{self, Write},
+    io::{self, Read, Write},
     net,
     os::unix::net::UnixStream,
     path::Path,
+    process,
     time::Duration,
 };
 
 use clap::{IntoApp, Parser};
-use clap_def::CliArgs;
-use shadowsocks_gtk_rs::{notify_method::NotifyMethod, runtime_api_msg::APICommand};
+use clap_def::{CliArgs, SubCmd};
+use shadowsocks_gtk_rs::{
+    profile_tree::ProfileTreeNode,
+    runtime_api_msg::{APICommand, ProfileLatency, QueryReply, TestProfileReply},
+    util::{format_bytes_short, format_duration_short},
+};
 
 mod clap_def;
 
@@ -34,29 +39,134 @@ fn main() -> io::Result<()> {
             .exit(),
     };
 
+    // generate completions locally
+    if let SubCmd::GenerateCompletions { shell } = sub_cmd {
+        clap_complete::generate(shell, &mut CliArgs::command(), "ssgtkctl", &mut io::stdout());
+        return Ok(());
+    }
+
     // send
+    let expect_reply = matches!(sub_cmd, SubCmd::Find { .. });
+    let decode_query = matches!(sub_cmd, SubCmd::Query { json: false });
+    let decode_tree = matches!(sub_cmd, SubCmd::ListProfiles { json: false });
+    let decode_pids = matches!(sub_cmd, SubCmd::Pids { json: false });
+    let decode_latencies = matches!(sub_cmd, SubCmd::Latencies { json: false });
+    let raw_logs = matches!(sub_cmd, SubCmd::Logs { .. });
+    let decode_test = matches!(sub_cmd, SubCmd::TestProfile { json: false, .. });
     let send_res = send_cmd(runtime_api_socket_path, sub_cmd.into());
     match &send_res {
-        Ok(_) => println!("Command sent successfully"),
+        Ok(reply) if expect_reply && reply.trim().is_empty() => {
+            eprintln!("No matches found");
+            process::exit(1);
+        }
+        Ok(reply) if decode_query => match json5::from_str::<QueryReply>(reply) {
+            Ok(status) => print_status(&status),
+            Err(err) => {
+                eprintln!("Failed to decode reply: {}", err);
+                println!("{}", reply.trim_end());
+            }
+        },
+        Ok(reply) if decode_tree => match json5::from_str::<ProfileTreeNode>(reply) {
+            Ok(tree) => print!("{}", tree),
+            Err(err) => {
+                eprintln!("Failed to decode reply: {}", err);
+                println!("{}", reply.trim_end());
+            }
+        },
+        Ok(reply) if decode_pids => match json5::from_str::<Vec<u32>>(reply) {
+            Ok(pids) => pids.iter().for_each(|pid| println!("{}", pid)),
+            Err(err) => {
+                eprintln!("Failed to decode reply: {}", err);
+                println!("{}", reply.trim_end());
+            }
+        },
+        Ok(reply) if decode_latencies => match json5::from_str::<Vec<ProfileLatency>>(reply) {
+            Ok(latencies) => print_latencies(&latencies),
+            Err(_) => {
+                // not a list of latencies, e.g. probing is disabled
+                println!("{}", reply.trim_end());
+                process::exit(1);
+            }
+        },
+        Ok(reply) if raw_logs => print!("{}", reply),
+        Ok(reply) if decode_test => match json5::from_str::<TestProfileReply>(reply) {
+            Ok(res) => {
+                print_test_result(&res);
+                if !res.still_running {
+                    process::exit(1);
+                }
+            }
+            Err(_) => {
+                // not a test result, e.g. profile not found
+                println!("{}", reply.trim_end());
+                process::exit(1);
+            }
+        },
+        Ok(reply) if reply.is_empty() => println!("Command sent successfully"),
+        Ok(reply) => println!("{}", reply.trim_end()),
         Err(_) => println!("Failed to send command"),
     }
-    send_res
+    send_res.map(|_| ())
+}
+
+fn print_status(status: &QueryReply) {
+    println!("Active profile: {}", status.active_profile.as_deref().unwrap_or("None"));
+    println!("sslocal running: {}", if status.sslocal_running { "yes" } else { "no" });
+    if !status.concurrent_profiles.is_empty() {
+        println!("Running alongside: {}", status.concurrent_profiles.join(", "));
+    }
+    println!("Notify method: {}", status.notify_method);
+    match status.bytes_transferred {
+        Some(bytes) => println!("Bytes transferred: {}", bytes),
+        None => println!("Bytes transferred: unknown"),
+    }
+    if let Some(secs) = status.uptime_secs {
+        println!("Uptime: {}", format_duration_short(Duration::from_secs(secs)));
+        println!(
+            "Traffic: {} sent, {} received",
+            format_bytes_short(status.bytes_sent),
+            format_bytes_short(status.bytes_received)
+        );
+    }
+    println!(
+        "Auto-restart: {}",
+        if status.auto_restart_paused {
+            "paused"
+        } else {
+            "enabled"
+        }
+    );
+}
+
+fn print_latencies(latencies: &[ProfileLatency]) {
+    let width = latencies.iter().map(|l| l.profile.chars().count()).max().unwrap_or(0);
+    for l in latencies {
+        match l.latency_ms {
+            Some(ms) => println!("{:width$}  {} ms", l.profile, ms, width = width),
+            None => println!("{:width$}  unreachable", l.profile, width = width),
+        }
+    }
+}
+
+fn print_test_result(res: &TestProfileReply) {
+    match (&res.exit_status, res.listening) {
+        (None, true) => println!("Profile \"{}\" started cleanly and is listening", res.profile),
+        (None, false) => println!(
+            "Profile \"{}\" is still running, but has not reported listening",
+            res.profile
+        ),
+        (Some(status), _) => println!("Profile \"{}\" failed: sslocal has exited ({})", res.profile, status),
+    }
+    if !res.output.is_empty() {
+        println!("{}", "-".repeat(50));
+        print!("{}", res.output);
+    }
 }
 
 fn print_socket_egs() {
-    use APICommand::*;
-    let egs = vec![
-        LogViewerShow,
-        LogViewerHide,
-        SetNotify(NotifyMethod::Toast),
-        Restart,
-        SwitchProfile("Example Profile".into()),
-        Stop,
-        Quit,
-    ];
     println!("{}", "-".repeat(50));
-    println!("Here are some of the commands you can issue (CASE SENSITIVE):");
-    for cmd in egs.into_iter() {
+    println!("Here are all the commands you can issue (CASE SENSITIVE):");
+    for cmd in APICommand::examples() {
         let cmd_str = json5::to_string(&cmd).expect("Manually created, shouldn't error");
         println!("\t`echo \'{}\' | nc -U /path/to/shadowsocks-gtk-rs.sock`", cmd_str);
     }
@@ -64,7 +174,10 @@ fn print_socket_egs() {
         "Note 0: you likely need the BSD variant of netcat to be able to connect \
         to Unix sockets (see https://unix.stackexchange.com/a/26781/375550)\n\
         Note 1: due to technical limitations and my laziness (mainly the latter) \
-        the JSON5 command string must be a single line"
+        the JSON5 command string must be a single line\n\
+        Note 2: you can send multiple commands in one connection, one per line; \
+        they are handled in order, e.g.\n\
+        \t`printf '\"stop\"\\n\"log-viewer-show\"\\n' | nc -U /path/to/shadowsocks-gtk-rs.sock`"
     );
     println!(
         "For the default socket path and how to manually set a different one, see\n\
@@ -73,7 +186,18 @@ fn print_socket_egs() {
     println!("{}", "-".repeat(50));
 }
 
-fn send_cmd(destination: impl AsRef<Path>, cmd: APICommand) -> io::Result<()> {
+/// Send a command and wait for a reply, if any.
+///
+/// Returns an empty string if the command produced no reply.
+fn send_cmd(destination: impl AsRef<Path>, cmd: APICommand) -> io::Result<String> {
+    // this must outlast the listener's own wait for a reply to the command
+    let read_timeout = match cmd {
+        // the listener waits for as long as its stop timeout, which only it knows,
+        // so rely on it to close the connection instead
+        APICommand::StopWait => None,
+        APICommand::Bypass { .. } => Some(Duration::from_secs(65)),
+        _ => Some(Duration::from_secs(20)),
+    };
     let mut socket = UnixStream::connect(destination)?;
     socket.set_write_timeout(Some(Duration::from_secs(3)))?;
     socket.write_all(
@@ -82,5 +206,11 @@ fn send_cmd(destination: impl AsRef<Path>, cmd: APICommand) -> io::Result<()> {
             .as_bytes(),
     )?;
     socket.flush()?;
-    socket.shutdown(net::Shutdown::Both)
+    socket.shutdown(net::Shutdown::Write)?;
+
+    // read reply until the listener closes the connection
+    socket.set_read_timeout(read_timeout)?;
+    let mut reply = String::new();
+    socket.read_to_string(&mut reply)?;
+    Ok(reply)
 }
@@ -1,6 +1,6 @@
 //! This module contains predefined shared constants.
 
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use lazy_static::lazy_static;
 
@@ -33,6 +33,10 @@ pub const PROFILE_CONFIG_FILE_NAME: &str = "profile.yaml";
 /// as ignored during the loading process.
 pub const PROFILE_IGNORE_FILE_NAME: &str = ".ss_ignore";
 
+/// This file in a group directory lists the names of its child directories
+/// in the order they should be displayed.
+pub const PROFILE_GROUP_ORDER_FILE_NAME: &str = ".order";
+
 /// The default binary to lookup in $PATH, if not overridden by profile.
 pub const SSLOCAL_LOOKUP_NAME_DEFAULT: &str = "sslocal";
 
@@ -45,7 +49,22 @@ pub const SSLOCAL_LOOKUP_NAME_DEFAULT: &str = "sslocal";
 pub const DEFAULT_LOG_LEVEL: i32 = 2;
 
 /// Default buffer size for a `bus::Bus`.
-pub const BUS_BUFFER_SIZE: usize = 20;
+///
+/// This is the number of log lines that can be queued for the slowest listener.
+/// Lines are never dropped from the backlog because of a slow listener; but if
+/// `sslocal` produces output faster than the listener (e.g. the log viewer) consumes it,
+/// excess lines are dropped from its live view and replaced by a "… N lines dropped …" marker.
+///
+/// A larger buffer tolerates longer bursts at the cost of memory,
+/// which is only used while a listener is lagging behind.
+pub const BUS_BUFFER_SIZE: usize = 256;
+
+/// The number of recent profile switches to remember.
+pub const PROFILE_HISTORY_LEN: usize = 10;
+
+/// Default time to wait for `sslocal` to exit gracefully when stopping it,
+/// before killing it with `SIGKILL`.
+pub const SSLOCAL_STOP_TIMEOUT_DEFAULT: Duration = Duration::from_secs(3);
 
 // Static runtime paths
 // ========================================
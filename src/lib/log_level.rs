@@ -0,0 +1,47 @@
+use clap::ValueEnum;
+use enum_iterator::Sequence;
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+
+/// The verbosity of the application's own logging.
+#[derive(Debug, strum::Display, Clone, Copy, PartialEq, Eq, Sequence, ValueEnum, Serialize, Deserialize)]
+#[clap(rename_all = "kebab-case")]
+pub enum LogLevel {
+    /// Disable logging.
+    Off,
+    /// Log errors only.
+    Error,
+    /// Log warnings and above.
+    Warn,
+    /// Log info and above.
+    Info,
+    /// Log debug and above.
+    Debug,
+    /// Log everything.
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+impl From<LevelFilter> for LogLevel {
+    fn from(filter: LevelFilter) -> Self {
+        match filter {
+            LevelFilter::Off => LogLevel::Off,
+            LevelFilter::Error => LogLevel::Error,
+            LevelFilter::Warn => LogLevel::Warn,
+            LevelFilter::Info => LogLevel::Info,
+            LevelFilter::Debug => LogLevel::Debug,
+            LevelFilter::Trace => LogLevel::Trace,
+        }
+    }
+}
@@ -1,6 +1,8 @@
 // public members
 pub mod consts;
+pub mod log_level;
 pub mod notify_method;
+pub mod profile_tree;
 #[cfg(feature = "runtime-api")]
 pub mod runtime_api_msg;
 pub mod util;
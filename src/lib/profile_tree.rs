@@ -0,0 +1,105 @@
+//! This module defines a serializable view of the loaded profiles,
+//! shared by `ssgtk` and external tools such as `ssgtkctl`.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// A serializable projection of a `ProfileFolder`, for consumption by external tools.
+///
+/// Only carries what's needed to render a menu; secrets such as passwords are left out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum ProfileTreeNode {
+    Profile {
+        name: String,
+        mode: String,
+        quiet: bool,
+        url: Option<String>,
+    },
+    Group {
+        name: String,
+        children: Vec<ProfileTreeNode>,
+    },
+}
+
+impl fmt::Display for ProfileTreeNode {
+    /// Renders the tree with one node per line, indented by depth.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+impl ProfileTreeNode {
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        match self {
+            Self::Profile { name, mode, .. } => writeln!(f, "{}{} ({})", indent, name, mode),
+            Self::Group { name, children } => {
+                writeln!(f, "{}{}/", indent, name)?;
+                children.iter().try_for_each(|child| child.fmt_indented(f, depth + 1))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ProfileTreeNode;
+
+    #[test]
+    fn nested_group_round_trip() {
+        let profile = |name: &str, mode: &str| ProfileTreeNode::Profile {
+            name: name.into(),
+            mode: mode.into(),
+            quiet: false,
+            url: None,
+        };
+        let tree = ProfileTreeNode::Group {
+            name: "Root".into(),
+            children: vec![
+                profile("A", "proxy"),
+                ProfileTreeNode::Group {
+                    name: "Inner".into(),
+                    children: vec![profile("B", "tun"), profile("C", "config-file")],
+                },
+            ],
+        };
+
+        let json = json5::to_string(&tree).unwrap();
+        let parsed: ProfileTreeNode = json5::from_str(&json).unwrap();
+        assert_eq!(parsed, tree);
+        assert_eq!(
+            parsed.to_string(),
+            "Root/\n  A (proxy)\n  Inner/\n    B (tun)\n    C (config-file)\n"
+        );
+    }
+    #[test]
+    fn nested_group_json() {
+        let tree = ProfileTreeNode::Group {
+            name: "Root".into(),
+            children: vec![ProfileTreeNode::Group {
+                name: "Inner".into(),
+                children: vec![ProfileTreeNode::Profile {
+                    name: "B".into(),
+                    mode: "tun".into(),
+                    quiet: true,
+                    url: Some("https://example.org".into()),
+                }],
+            }],
+        };
+
+        // the shape external front-ends build their menus from
+        let json = serde_json::to_value(&tree).unwrap();
+        assert_eq!(json["kind"], "group");
+        let inner = &json["children"][0];
+        assert_eq!(inner["kind"], "group");
+        assert_eq!(inner["name"], "Inner");
+        let profile = &inner["children"][0];
+        assert_eq!(profile["kind"], "profile");
+        assert_eq!(profile["name"], "B");
+        assert_eq!(profile["mode"], "tun");
+        assert_eq!(profile["quiet"], true);
+        assert_eq!(profile["url"], "https://example.org");
+    }
+}
@@ -1,25 +1,90 @@
 //! This module defines the messages passed to and from the
 //! runtime API, enabled behind the "runtime-api" feature.
 
-use std::fmt;
+use std::{fmt, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
+use strum::{EnumDiscriminants, EnumIter, IntoEnumIterator};
 
-use crate::notify_method::NotifyMethod;
+use crate::{log_level::LogLevel, notify_method::NotifyMethod};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, EnumDiscriminants)]
 #[serde(rename_all = "kebab-case")]
+#[strum_discriminants(name(APICommandKind), derive(EnumIter))]
 pub enum APICommand {
     // GUI
     LogViewerShow,
     LogViewerHide,
     SetNotify(NotifyMethod),
+    /// Set how long toast notifications stay on screen.
+    SetNotifyTimeout {
+        /// `None` uses the notification server's default; `Some(0)` never expires.
+        ms: Option<u32>,
+        /// Keep error toasts on screen until dismissed, regardless of `ms`.
+        sticky_errors: bool,
+    },
+    SetLogLevel(LogLevel),
+    /// Look up the tray icons again, e.g. after a new icon theme has been installed.
+    ReloadIcon,
+    /// Use a new base tray icon, with variants derived as with `--icon-name`.
+    SetIcon {
+        name: String,
+        /// Search for the icons in this directory too; keeps the current one if `None`.
+        theme_dir: Option<PathBuf>,
+    },
 
     // core
     Restart,
     SwitchProfile(String),
+    /// Start a profile alongside the active one, without stopping any running profile.
+    StartProfile(String),
+    /// Stop a profile running alongside the active one.
+    StopProfile(String),
+    /// Switch to the profile after the active one in the displayed order, or the first one if none is active.
+    NextProfile,
+    /// Switch to the profile before the active one in the displayed order, or the last one if none is active.
+    PrevProfile,
     Stop,
+    /// Same as `Stop`, but only replies once `sslocal` has exited.
+    StopWait,
+    /// Stop the active instance if running, otherwise start the most recent profile again.
+    Toggle,
     Quit,
+    /// Same as `Quit`, but also accepted when the listener requires confirmation.
+    QuitConfirmed,
+    ResetRestartCounter,
+    /// Leave `sslocal` stopped when it fails instead of restarting it, until `ResumeAutoRestart`.
+    PauseAutoRestart,
+    ResumeAutoRestart,
+    /// Change the auto-restart limit, taking effect on the next restart.
+    SetRestartLimit {
+        times: usize,
+        within_secs: u64,
+    },
+    /// Re-scan the profiles directory, keeping the active instance running.
+    ReloadProfiles,
+    /// Run the bypass (or restore) hook of the active "tun"-type profile.
+    Bypass {
+        enable: bool,
+    },
+
+    // query
+    Query,
+    FindProfiles(String),
+    ListProfiles,
+    /// Get the PIDs of the running `sslocal` instance, as a JSON5 list.
+    GetPids,
+    /// Get metrics in the Prometheus text exposition format.
+    Metrics,
+    /// Get the most recently probed latency to each profile's server, as a JSON5 list of `ProfileLatency`.
+    GetLatencies,
+    /// Get the last lines of `sslocal`'s logs, or all of them if `lines` is `None`.
+    GetBacklog {
+        lines: Option<usize>,
+    },
+    /// Start `sslocal` with a profile for a few seconds without switching to it,
+    /// replying with a `TestProfileReply` as JSON5.
+    TestProfile(String),
 }
 
 impl fmt::Display for APICommand {
@@ -29,12 +94,201 @@ impl fmt::Display for APICommand {
             LogViewerShow => "Show log viewer".into(),
             LogViewerHide => "Hide log viewer".into(),
             SetNotify(method) => format!("Set notification method to {}", method),
+            SetNotifyTimeout { ms, sticky_errors } => {
+                let timeout = match ms {
+                    None => "default".into(),
+                    Some(0) => "never".into(),
+                    Some(ms) => format!("{}ms", ms),
+                };
+                match sticky_errors {
+                    true => format!("Set notification timeout to {} (errors never expire)", timeout),
+                    false => format!("Set notification timeout to {}", timeout),
+                }
+            }
+            SetLogLevel(level) => format!("Set log level to {}", level),
+            ReloadIcon => "Reload tray icon".into(),
+            SetIcon { name, theme_dir: None } => format!("Set tray icon to {}", name),
+            SetIcon {
+                name,
+                theme_dir: Some(dir),
+            } => format!("Set tray icon to {} from {:?}", name, dir),
 
             Restart => "Restart current profile".into(),
             SwitchProfile(name) => format!("Switch Profile to {}", name),
+            StartProfile(name) => format!("Start profile {} alongside the current one", name),
+            StopProfile(name) => format!("Stop profile {} running alongside the current one", name),
+            NextProfile => "Switch to next profile".into(),
+            PrevProfile => "Switch to previous profile".into(),
             Stop => "Stop current profile".into(),
+            StopWait => "Stop current profile and wait for it to exit".into(),
+            Toggle => "Toggle current profile on/off".into(),
             Quit => "Quit application".into(),
+            QuitConfirmed => "Quit application (confirmed)".into(),
+            ResetRestartCounter => "Reset auto-restart counter".into(),
+            PauseAutoRestart => "Pause auto-restart".into(),
+            ResumeAutoRestart => "Resume auto-restart".into(),
+            SetRestartLimit { times, within_secs } => {
+                format!("Set auto-restart limit to {} times within {}s", times, within_secs)
+            }
+            ReloadProfiles => "Reload profiles".into(),
+            Bypass { enable: true } => "Bypass the tunnel of current profile".into(),
+            Bypass { enable: false } => "Restore the tunnel of current profile".into(),
+
+            Query => "Query application status".into(),
+            FindProfiles(pattern) => format!("Find profiles matching {}", pattern),
+            ListProfiles => "List all profiles".into(),
+            GetPids => "Get PIDs of sslocal".into(),
+            Metrics => "Get metrics".into(),
+            GetLatencies => "Get server latencies".into(),
+            GetBacklog { lines: Some(n) } => format!("Get the last {} lines of sslocal logs", n),
+            GetBacklog { lines: None } => "Get all sslocal logs".into(),
+            TestProfile(name) => format!("Test profile {}", name),
         };
         write!(f, "{}", msg)
     }
 }
+
+impl APICommand {
+    /// A canonical example of each kind of command.
+    ///
+    /// Every kind must have an example, so that documentation never drifts from this enum.
+    pub fn examples() -> Vec<Self> {
+        use APICommandKind::*;
+        APICommandKind::iter()
+            .map(|kind| match kind {
+                LogViewerShow => Self::LogViewerShow,
+                LogViewerHide => Self::LogViewerHide,
+                SetNotify => Self::SetNotify(NotifyMethod::Toast),
+                SetNotifyTimeout => Self::SetNotifyTimeout {
+                    ms: Some(10000),
+                    sticky_errors: true,
+                },
+                SetLogLevel => Self::SetLogLevel(LogLevel::Debug),
+                ReloadIcon => Self::ReloadIcon,
+                SetIcon => Self::SetIcon {
+                    name: "shadowsocks-gtk-rs".into(),
+                    theme_dir: Some("/usr/share/icons/hicolor".into()),
+                },
+
+                Restart => Self::Restart,
+                SwitchProfile => Self::SwitchProfile("Example Profile".into()),
+                StartProfile => Self::StartProfile("Example Profile".into()),
+                StopProfile => Self::StopProfile("Example Profile".into()),
+                NextProfile => Self::NextProfile,
+                PrevProfile => Self::PrevProfile,
+                Stop => Self::Stop,
+                StopWait => Self::StopWait,
+                Toggle => Self::Toggle,
+                Quit => Self::Quit,
+                QuitConfirmed => Self::QuitConfirmed,
+                ResetRestartCounter => Self::ResetRestartCounter,
+                PauseAutoRestart => Self::PauseAutoRestart,
+                ResumeAutoRestart => Self::ResumeAutoRestart,
+                SetRestartLimit => Self::SetRestartLimit {
+                    times: 5,
+                    within_secs: 30,
+                },
+                ReloadProfiles => Self::ReloadProfiles,
+                Bypass => Self::Bypass { enable: true },
+
+                Query => Self::Query,
+                FindProfiles => Self::FindProfiles("example".into()),
+                ListProfiles => Self::ListProfiles,
+                GetPids => Self::GetPids,
+                Metrics => Self::Metrics,
+                GetLatencies => Self::GetLatencies,
+                GetBacklog => Self::GetBacklog { lines: Some(20) },
+                TestProfile => Self::TestProfile("Example Profile".into()),
+            })
+            .collect()
+    }
+}
+
+/// The reply sent back to the client for an `APICommand::Query`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct QueryReply {
+    /// The display name of the currently active profile, if any.
+    pub active_profile: Option<String>,
+    /// Whether an `sslocal` instance is currently running.
+    pub sslocal_running: bool,
+    /// The notification method currently in use.
+    pub notify_method: NotifyMethod,
+    /// Total bytes transferred, as reported by `sslocal`'s manager interface.
+    ///
+    /// The manager protocol does not distinguish between upload and download,
+    /// so this is the sum of both.
+    ///
+    /// `None` if the active profile has no `manager_addr` configured,
+    /// or if no statistics have been received yet.
+    pub bytes_transferred: Option<u64>,
+    /// Whether auto-restart is paused, i.e. a failed `sslocal` is left stopped.
+    #[serde(default)]
+    pub auto_restart_paused: bool,
+    /// The display names of the profiles running alongside the active one.
+    #[serde(default)]
+    pub concurrent_profiles: Vec<String>,
+    /// How long the active `sslocal` instance has been running, in seconds.
+    ///
+    /// `None` if no instance is running.
+    #[serde(default)]
+    pub uptime_secs: Option<u64>,
+    /// Total bytes sent to the server, as printed by `sslocal`'s stat lines.
+    ///
+    /// Stays zero if `sslocal` does not print stat lines.
+    #[serde(default)]
+    pub bytes_sent: u64,
+    /// Total bytes received from the server, as printed by `sslocal`'s stat lines.
+    ///
+    /// Stays zero if `sslocal` does not print stat lines.
+    #[serde(default)]
+    pub bytes_received: u64,
+}
+
+/// The reply sent back to the client for an `APICommand::TestProfile`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TestProfileReply {
+    /// The display name of the tested profile.
+    pub profile: String,
+    /// Whether `sslocal` was still running at the end of the test, i.e. it has started cleanly.
+    pub still_running: bool,
+    /// Whether `sslocal` has reported that it is listening.
+    pub listening: bool,
+    /// How `sslocal` exited, if it exited before the end of the test.
+    pub exit_status: Option<String>,
+    /// The combined `stdout` & `stderr` of `sslocal` during the test.
+    pub output: String,
+}
+
+/// An entry in the reply sent back to the client for an `APICommand::GetLatencies`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProfileLatency {
+    /// The display name of the probed profile.
+    pub profile: String,
+    /// How long connecting to the server has taken, in milliseconds.
+    ///
+    /// `None` if the server is unreachable.
+    pub latency_ms: Option<u64>,
+}
+
+#[cfg(test)]
+mod test {
+    use strum::IntoEnumIterator;
+
+    use super::{APICommand, APICommandKind};
+
+    #[test]
+    fn examples_cover_all_kinds() {
+        let examples = APICommand::examples();
+        assert_eq!(examples.len(), APICommandKind::iter().count());
+        for (eg, kind) in examples.iter().zip(APICommandKind::iter()) {
+            assert_eq!(APICommandKind::from(eg), kind);
+            // round trip
+            let eg_str = json5::to_string(eg).unwrap();
+            let parsed: APICommand = json5::from_str(&eg_str).unwrap();
+            assert_eq!(APICommandKind::from(&parsed), kind);
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! This module contains helper functions for displaying byte counts
+//! in a human-friendly way.
+
+/// Format a byte count compactly with a binary unit, e.g. `512 B`, `1.5 KiB`, `42.0 MiB`.
+pub fn format_bytes_short(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod test {
+    use super::format_bytes_short;
+
+    #[test]
+    fn units() {
+        assert_eq!(format_bytes_short(0), "0 B");
+        assert_eq!(format_bytes_short(1023), "1023 B");
+        assert_eq!(format_bytes_short(1536), "1.5 KiB");
+        assert_eq!(format_bytes_short(42 * 1024 * 1024), "42.0 MiB");
+        assert_eq!(format_bytes_short(3 << 40), "3.0 TiB");
+    }
+}
@@ -0,0 +1,39 @@
+//! This module contains helper functions for displaying durations
+//! in a human-friendly way.
+
+use std::time::Duration;
+
+/// Format a duration compactly, keeping only the two most significant units,
+/// e.g. `2h13m`, `5m07s`, `42s`.
+pub fn format_duration_short(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (d, h, m, s) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    match (d, h, m) {
+        (0, 0, 0) => format!("{}s", s),
+        (0, 0, _) => format!("{}m{:02}s", m, s),
+        (0, _, _) => format!("{}h{:02}m", h, m),
+        _ => format!("{}d{:02}h", d, h),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::format_duration_short;
+
+    #[test]
+    fn units() {
+        assert_eq!(format_duration_short(Duration::from_millis(999)), "0s");
+        assert_eq!(format_duration_short(Duration::from_secs(42)), "42s");
+        assert_eq!(format_duration_short(Duration::from_secs(5 * 60 + 7)), "5m07s");
+        assert_eq!(
+            format_duration_short(Duration::from_secs(2 * 3600 + 13 * 60 + 59)),
+            "2h13m"
+        );
+        assert_eq!(
+            format_duration_short(Duration::from_secs(3 * 86400 + 4 * 3600)),
+            "3d04h"
+        );
+    }
+}
@@ -5,9 +5,11 @@
 
 use std::fmt;
 
+#[cfg(feature = "gui")]
 use libappindicator::AppIndicator;
 
 // `libappindicator::AppIndicator` currently has no Debug impl.
+#[cfg(feature = "gui")]
 pub fn omit_ai(_: &AppIndicator, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
     write!(fmt, "*AppIndicator info omitted*")
 }
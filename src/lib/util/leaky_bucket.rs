@@ -2,6 +2,7 @@
 //! rate limiter algorithm.
 
 use std::{
+    collections::VecDeque,
     fmt,
     time::{Duration, Instant},
 };
@@ -28,11 +29,15 @@ impl fmt::Display for LeakyBucketOverflowed {
 impl From<&NaiveLeakyBucket> for LeakyBucketOverflowed {
     fn from(lb: &NaiveLeakyBucket) -> Self {
         let NaiveLeakyBucket { times, within, history } = lb.clone();
-        Self { times, within, history }
+        Self {
+            times,
+            within,
+            history: history.into(),
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NaiveLeakyBucketConfig {
     times: usize,
     within: Duration,
@@ -48,7 +53,8 @@ impl NaiveLeakyBucketConfig {
 pub struct NaiveLeakyBucket {
     times: usize,
     within: Duration,
-    history: Vec<Instant>,
+    /// Always sorted from oldest to newest.
+    history: VecDeque<Instant>,
 }
 
 impl From<NaiveLeakyBucketConfig> for NaiveLeakyBucket {
@@ -56,26 +62,68 @@ impl From<NaiveLeakyBucketConfig> for NaiveLeakyBucket {
         Self {
             times,
             within,
-            history: vec![],
+            history: VecDeque::new(),
         }
     }
 }
 
 impl NaiveLeakyBucket {
+    /// The configuration this bucket currently uses.
+    pub fn config(&self) -> NaiveLeakyBucketConfig {
+        NaiveLeakyBucketConfig::new(self.times, self.within)
+    }
+
+    /// Change the configuration of this bucket, keeping its push history.
+    pub fn reconfigure(&mut self, NaiveLeakyBucketConfig { times, within }: NaiveLeakyBucketConfig) {
+        self.times = times;
+        self.within = within;
+        self.prune(Instant::now());
+    }
+
     pub fn push(&mut self) -> Result<(), LeakyBucketOverflowed> {
         let now = Instant::now();
-        self.history.push(now);
-        self.history = self
-            .history
-            .iter()
-            .filter(|&&t| now.saturating_duration_since(t) < self.within)
-            .map(|&t| t)
-            .collect();
+        self.history.push_back(now);
+        self.prune(now);
         match self.history.len() <= self.times {
             true => Ok(()),
             false => Err((self as &Self).into()),
         }
     }
+
+    /// The maximum number of pushes allowed within the time window.
+    pub fn capacity(&self) -> usize {
+        self.times
+    }
+
+    /// The number of pushes made within the time window.
+    pub fn current(&self) -> usize {
+        self.unexpired(Instant::now()).count()
+    }
+
+    /// The number of pushes that can still be made within the time window
+    /// before this bucket overflows.
+    pub fn remaining(&self) -> usize {
+        self.times.saturating_sub(self.current())
+    }
+
+    /// Remove the pushes that have expired at `now`.
+    ///
+    /// Since `history` is sorted, expired pushes are always at the front.
+    fn prune(&mut self, now: Instant) {
+        while let Some(&t) = self.history.front() {
+            if now.saturating_duration_since(t) < self.within {
+                break;
+            }
+            self.history.pop_front();
+        }
+    }
+
+    /// Iterate over the pushes that have not yet expired at `now`.
+    fn unexpired(&self, now: Instant) -> impl Iterator<Item = &Instant> {
+        self.history
+            .iter()
+            .filter(move |&&t| now.saturating_duration_since(t) < self.within)
+    }
 }
 
 #[cfg(test)]
@@ -108,4 +156,50 @@ mod test {
         sleep(Duration::from_millis(80)); // expire 1
         assert!(lb.push().is_ok()); // len 1
     }
+    #[test]
+    fn remaining_3() {
+        let mut lb: NaiveLeakyBucket = NaiveLeakyBucketConfig::new(3, Duration::from_secs(10)).into();
+        assert_eq!((lb.current(), lb.remaining()), (0, 3));
+        for i in 1..=3 {
+            assert!(lb.push().is_ok());
+            assert_eq!((lb.current(), lb.remaining()), (i, 3 - i));
+        }
+        assert!(lb.push().is_err());
+        assert_eq!((lb.current(), lb.remaining()), (4, 0));
+    }
+    #[test]
+    fn remaining_expire() {
+        let mut lb: NaiveLeakyBucket = NaiveLeakyBucketConfig::new(2, Duration::from_millis(100)).into();
+        assert!(lb.push().is_ok());
+        sleep(Duration::from_millis(60));
+        assert!(lb.push().is_ok());
+        assert_eq!((lb.current(), lb.remaining()), (2, 0));
+        sleep(Duration::from_millis(60)); // expire 1
+        assert_eq!((lb.current(), lb.remaining()), (1, 1));
+        sleep(Duration::from_millis(60)); // expire 1
+        assert_eq!((lb.current(), lb.remaining()), (0, 2));
+    }
+    #[test]
+    fn reconfigure_keeps_history() {
+        let mut lb: NaiveLeakyBucket = NaiveLeakyBucketConfig::new(3, Duration::from_secs(10)).into();
+        for _ in 0..2 {
+            assert!(lb.push().is_ok());
+        }
+        lb.reconfigure(NaiveLeakyBucketConfig::new(2, Duration::from_secs(10)));
+        assert_eq!((lb.current(), lb.remaining()), (2, 0));
+        assert!(lb.push().is_err());
+    }
+    #[test]
+    fn prune_keeps_unexpired() {
+        let mut lb: NaiveLeakyBucket = NaiveLeakyBucketConfig::new(100, Duration::from_millis(100)).into();
+        for _ in 0..10 {
+            assert!(lb.push().is_ok());
+        }
+        sleep(Duration::from_millis(120)); // expire all 10
+        for _ in 0..5 {
+            assert!(lb.push().is_ok());
+        }
+        assert_eq!(lb.history.len(), 5);
+        assert!(lb.history.iter().zip(lb.history.iter().skip(1)).all(|(a, b)| a <= b));
+    }
 }
@@ -5,6 +5,12 @@ pub mod hacks;
 pub mod leaky_bucket;
 
 // private members with re-export
+mod bytes_fmt;
+pub use bytes_fmt::*;
+
+mod duration_fmt;
+pub use duration_fmt::*;
+
 mod output_kind;
 pub use output_kind::*;
 